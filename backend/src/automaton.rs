@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use graphviz_rust::dot_structures::{Attribute, EdgeTy, Graph, Id, Stmt, Vertex};
+use iter_tools::Itertools;
+
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{Input, Marking, ReachabilityGraph},
+    lts::{Label, Lts},
+};
+
+/// finite automaton over transition names, tau moves read nothing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Automaton {
+    pub lts: Lts,
+    pub accepting: Vec<bool>,
+    pub names: Vec<String>,
+}
+
+/// labels standing for tau in the specifications
+fn label(name: &str) -> Label {
+    match name {
+        "i" | "tau" | "τ" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+fn bad_specification(reason: String) -> ErrorTypes {
+    ErrorTypes::BadSpecification { reason }
+}
+
+impl Automaton {
+    /// number of the state `name`, added when missing
+    fn state(&mut self, indices: &mut HashMap<String, usize>, name: &str) -> usize {
+        *indices.entry(name.to_string()).or_insert_with(|| {
+            self.names.push(name.to_string());
+            self.lts.edges.push(vec![]);
+            self.accepting.push(false);
+            self.names.len() - 1
+        })
+    }
+
+    /// every state accepts when none was declared final
+    fn accept_all_unless_declared(&mut self) {
+        if !self.accepting.contains(&true) {
+            self.accepting.iter_mut().for_each(|a| *a = true);
+        }
+    }
+
+    /// aldebaran format of CADP (`.aut`), every state accepts
+    pub fn from_aut(text: &str) -> Result<Self, ErrorTypes> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .and_then(|line| line.trim().strip_prefix("des"))
+            .and_then(|line| line.trim().strip_prefix('('))
+            .and_then(|line| line.trim_end().strip_suffix(')'))
+            .ok_or(bad_specification("missing des header".to_string()))?;
+        let numbers = header
+            .split(',')
+            .map(|x| x.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| bad_specification(e.to_string()))?;
+        let [initial, _, states] = numbers[..] else {
+            return Err(bad_specification(format!("bad des header {}", header)));
+        };
+        let mut automaton = Automaton {
+            lts: Lts {
+                initial,
+                edges: vec![vec![]; states],
+            },
+            accepting: vec![true; states],
+            names: (0..states).map(|s| s.to_string()).collect(),
+        };
+        for line in lines {
+            let bad_line = || bad_specification(format!("bad transition {}", line));
+            let inner = line
+                .trim()
+                .strip_prefix('(')
+                .and_then(|l| l.strip_suffix(')'))
+                .ok_or_else(bad_line)?;
+            let (from, rest) = inner.split_once(',').ok_or_else(bad_line)?;
+            let (name, to) = rest.rsplit_once(',').ok_or_else(bad_line)?;
+            let (from, to) = (from.trim().parse::<usize>(), to.trim().parse::<usize>());
+            let (Ok(from), Ok(to)) = (from, to) else {
+                return Err(bad_line());
+            };
+            if from >= states || to >= states {
+                return Err(bad_line());
+            }
+            let name = name.trim().trim_matches('"');
+            automaton.lts.edges[from].push((label(name), to));
+        }
+        if initial >= states {
+            return Err(bad_specification(format!("no initial state {}", initial)));
+        }
+        Ok(automaton)
+    }
+
+    /// directed dot graph labelled by its edges, the final states drawn as
+    /// double circles, the initial state is the target of the edge leaving
+    /// a `point` node or the first state otherwise
+    pub fn from_dot(text: &str) -> Result<Self, ErrorTypes> {
+        let Graph::DiGraph { stmts, .. } = graphviz_rust::parse(text).map_err(bad_specification)?
+        else {
+            return Err(bad_specification("expected a digraph".to_string()));
+        };
+        let id = |id: &Id| match id {
+            Id::Html(s) | Id::Plain(s) | Id::Anonymous(s) => s.clone(),
+            Id::Escaped(s) => s.trim_matches('"').to_string(),
+        };
+        let attribute = |attributes: &[Attribute], key: &str| {
+            attributes
+                .iter()
+                .find(|Attribute(k, _)| id(k) == key)
+                .map(|Attribute(_, v)| id(v))
+        };
+        let mut automaton = Automaton::default();
+        let mut indices = HashMap::new();
+        let mut starts = vec![];
+        let mut initial = None;
+        for stmt in &stmts {
+            match stmt {
+                Stmt::Node(node) => {
+                    let name = id(&node.id.0);
+                    match attribute(&node.attributes, "shape").as_deref() {
+                        Some("point") => starts.push(name),
+                        Some("doublecircle") => {
+                            let s = automaton.state(&mut indices, &name);
+                            automaton.accepting[s] = true;
+                        }
+                        _ => {
+                            automaton.state(&mut indices, &name);
+                        }
+                    }
+                }
+                Stmt::Edge(edge) => {
+                    let vertices = match &edge.ty {
+                        EdgeTy::Pair(from, to) => vec![from, to],
+                        EdgeTy::Chain(vertices) => vertices.iter().collect(),
+                    };
+                    let names = vertices
+                        .into_iter()
+                        .map(|vertex| match vertex {
+                            Vertex::N(node) => Ok(id(&node.0)),
+                            Vertex::S(_) => {
+                                Err(bad_specification("subgraphs are not supported".to_string()))
+                            }
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for pair in names.windows(2) {
+                        if starts.contains(&pair[0]) {
+                            initial = Some(automaton.state(&mut indices, &pair[1]));
+                            continue;
+                        }
+                        let name = attribute(&edge.attributes, "label").ok_or_else(|| {
+                            bad_specification(format!(
+                                "missing label on {} -> {}",
+                                pair[0], pair[1]
+                            ))
+                        })?;
+                        let from = automaton.state(&mut indices, &pair[0]);
+                        let to = automaton.state(&mut indices, &pair[1]);
+                        automaton.lts.edges[from].push((label(&name), to));
+                    }
+                }
+                _ => (),
+            }
+        }
+        if automaton.names.is_empty() {
+            return Err(bad_specification("no state".to_string()));
+        }
+        automaton.lts.initial = initial.unwrap_or(0);
+        automaton.accept_all_unless_declared();
+        Ok(automaton)
+    }
+
+    /// one declaration per line, `#` starting comments:
+    /// `initial s`, `final s t …` (every state when missing) and
+    /// `s -label-> t`, the initial state being the first one otherwise
+    pub fn from_dsl(text: &str) -> Result<Self, ErrorTypes> {
+        let mut automaton = Automaton::default();
+        let mut indices = HashMap::new();
+        let mut initial = None;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("initial ") {
+                initial = Some(automaton.state(&mut indices, name.trim()));
+            } else if let Some(names) = line.strip_prefix("final ") {
+                for name in names.split_whitespace() {
+                    let s = automaton.state(&mut indices, name);
+                    automaton.accepting[s] = true;
+                }
+            } else {
+                let (from, rest) = line
+                    .split_once(" -")
+                    .ok_or(bad_specification(format!("bad line {}", line)))?;
+                let (name, to) = rest
+                    .split_once("-> ")
+                    .ok_or(bad_specification(format!("bad line {}", line)))?;
+                let from = automaton.state(&mut indices, from.trim());
+                let to = automaton.state(&mut indices, to.trim());
+                automaton.lts.edges[from].push((label(name.trim()), to));
+            }
+        }
+        if automaton.names.is_empty() {
+            return Err(bad_specification("no state".to_string()));
+        }
+        automaton.lts.initial = initial.unwrap_or(0);
+        automaton.accept_all_unless_declared();
+        Ok(automaton)
+    }
+
+    /// the graph of `input` read through the names of its visible steps,
+    /// the states whose marking satisfies `is_final` accept
+    pub fn from_graph(
+        input: &Input,
+        graph: &ReachabilityGraph,
+        is_final: impl Fn(&Marking) -> bool,
+    ) -> Self {
+        Automaton {
+            lts: Lts::from_graph(input, graph),
+            accepting: graph.markings.iter().map(is_final).collect(),
+            names: graph.state_names("-"),
+        }
+    }
+
+    /// labels of the visible moves, sorted
+    pub fn alphabet(&self) -> Vec<String> {
+        self.lts
+            .edges
+            .iter()
+            .flatten()
+            .filter_map(|(label, _)| label.clone())
+            .unique()
+            .sorted()
+            .collect()
+    }
+
+    /// no tau move and at most one move per label leaving each state
+    pub fn is_deterministic(&self) -> bool {
+        self.lts.edges.iter().all(|next| {
+            next.iter().all(|(label, _)| label.is_some())
+                && next.iter().map(|(label, _)| label).all_unique()
+        })
+    }
+
+    /// subset construction over the tau closures, only the reachable
+    /// non-empty subsets become states
+    pub fn determinise(&self) -> Automaton {
+        let mut subsets = vec![self.lts.tau_closure([self.lts.initial])];
+        let mut indices = HashMap::from([(subsets[0].clone(), 0)]);
+        let mut edges = vec![];
+        let mut current = 0;
+        while current < subsets.len() {
+            let mut next = vec![];
+            for label in self.lts.visible_labels(&subsets[current]) {
+                let subset = self.lts.after(&subsets[current], label);
+                let index = *indices.entry(subset.clone()).or_insert_with(|| {
+                    subsets.push(subset);
+                    subsets.len() - 1
+                });
+                next.push((Some(label.to_string()), index));
+            }
+            edges.push(next);
+            current += 1;
+        }
+        Automaton {
+            lts: Lts { initial: 0, edges },
+            accepting: subsets
+                .iter()
+                .map(|subset| subset.iter().any(|s| self.accepting[*s]))
+                .collect(),
+            names: subsets
+                .iter()
+                .map(|subset| format!("{{{}}}", subset.iter().map(|s| &self.names[*s]).join(", ")))
+                .collect(),
+        }
+    }
+
+    /// deterministic automaton with the fewest states accepting the same
+    /// language (Hopcroft), the states from which no final state can be
+    /// reached are dropped; each state is named after its first member
+    pub fn minimise(&self) -> Automaton {
+        let dfa = match self.is_deterministic() {
+            true => self.clone(),
+            false => self.determinise(),
+        };
+        let alphabet = dfa.alphabet();
+        // the missing moves go to an extra rejecting state
+        let sink = dfa.names.len();
+        let mut delta = vec![vec![sink; alphabet.len()]; sink + 1];
+        for (s, next) in dfa.lts.edges.iter().enumerate() {
+            for (label, t) in next {
+                let a = alphabet.binary_search(label.as_ref().unwrap()).unwrap();
+                delta[s][a] = *t;
+            }
+        }
+        let mut inverse = vec![vec![vec![]; sink + 1]; alphabet.len()];
+        for (s, next) in delta.iter().enumerate() {
+            for (a, t) in next.iter().enumerate() {
+                inverse[a][*t].push(s);
+            }
+        }
+        let (finals, others): (Vec<usize>, Vec<usize>) =
+            (0..=sink).partition(|s| *s < sink && dfa.accepting[*s]);
+        let mut block_of = vec![0; sink + 1];
+        let mut blocks = vec![others];
+        let mut pending = HashSet::new();
+        let mut splitters = vec![];
+        if !finals.is_empty() {
+            finals.iter().for_each(|s| block_of[*s] = 1);
+            let smaller = usize::from(finals.len() <= blocks[0].len());
+            blocks.push(finals);
+            for a in 0..alphabet.len() {
+                pending.insert((smaller, a));
+                splitters.push((smaller, a));
+            }
+        }
+        while let Some((b, a)) = splitters.pop() {
+            pending.remove(&(b, a));
+            let mut touched = BTreeMap::<usize, Vec<usize>>::new();
+            for t in &blocks[b] {
+                for s in &inverse[a][*t] {
+                    touched.entry(block_of[*s]).or_default().push(*s);
+                }
+            }
+            for (y, states) in touched {
+                if states.len() == blocks[y].len() {
+                    continue;
+                }
+                let new = blocks.len();
+                let moved = states.iter().copied().collect::<HashSet<_>>();
+                blocks[y].retain(|s| !moved.contains(s));
+                states.iter().for_each(|s| block_of[*s] = new);
+                blocks.push(states);
+                for c in 0..alphabet.len() {
+                    let splitter = match pending.contains(&(y, c)) {
+                        true => new,
+                        false if blocks[new].len() <= blocks[y].len() => new,
+                        false => y,
+                    };
+                    if pending.insert((splitter, c)) {
+                        splitters.push((splitter, c));
+                    }
+                }
+            }
+        }
+        // numbers the live blocks in breadth first order from the initial one
+        let dead = block_of[sink];
+        let mut order = vec![block_of[dfa.lts.initial]];
+        let mut indices = HashMap::from([(order[0], 0)]);
+        let mut edges = vec![];
+        let mut current = 0;
+        while current < order.len() {
+            let member = blocks[order[current]][0];
+            let mut next = vec![];
+            for (a, t) in delta[member].iter().enumerate() {
+                let block = block_of[*t];
+                if block == dead {
+                    continue;
+                }
+                let index = *indices.entry(block).or_insert_with(|| {
+                    order.push(block);
+                    order.len() - 1
+                });
+                next.push((Some(alphabet[a].clone()), index));
+            }
+            edges.push(next);
+            current += 1;
+        }
+        Automaton {
+            lts: Lts { initial: 0, edges },
+            accepting: order
+                .iter()
+                .map(|b| blocks[*b][0] < sink && dfa.accepting[blocks[*b][0]])
+                .collect(),
+            names: order
+                .iter()
+                .map(|b| {
+                    let first = blocks[*b].iter().min().unwrap();
+                    dfa.names.get(*first).cloned().unwrap_or_default()
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Automaton;
+    use crate::{
+        graph_gen::{explore, Input},
+        output_generators::generate_automaton_dot,
+        query::Target,
+    };
+
+    #[test]
+    fn test_specification_formats() {
+        let dsl = Automaton::from_dsl(
+            "# a then b, forever\ninitial idle\nidle -a-> busy\nbusy -b-> idle\nfinal idle\n",
+        )
+        .unwrap();
+        let dot = Automaton::from_dot(
+            "digraph { start [shape = point]; start -> idle; idle [shape = doublecircle]; busy; idle -> busy [label = \"a\"]; busy -> idle [label = b] }",
+        )
+        .unwrap();
+        let aut = Automaton::from_aut("des (0, 2, 2)\n(0, \"a\", 1)\n(1, b, 0)\n").unwrap();
+        for automaton in [&dsl, &dot] {
+            assert_eq!(automaton.names, vec!["idle", "busy"]);
+            assert_eq!(automaton.accepting, vec![true, false]);
+        }
+        assert_eq!(dsl.lts, dot.lts);
+        assert_eq!(dsl.lts, aut.lts);
+        assert!(Automaton::from_aut("des (0, 1, 1)\n(0, \"a\", 3)\n").is_err());
+    }
+
+    #[test]
+    fn test_determinise_and_minimise() {
+        let nfa = Automaton::from_dsl(
+            "initial s\ns -a-> x\ns -a-> y\nx -b-> f\ny -i-> z\nz -b-> g\nfinal f g\n",
+        )
+        .unwrap();
+        assert!(!nfa.is_deterministic());
+        let dfa = nfa.determinise();
+        assert!(dfa.is_deterministic());
+        assert_eq!(dfa.names, vec!["{s}", "{x, y, z}", "{f, g}"]);
+        assert_eq!(dfa.accepting, vec![false, false, true]);
+        // two branches accepting the same words, and a dead end
+        let redundant = Automaton::from_dsl(
+            "initial s\ns -a-> x\ns -b-> y\ns -c-> d\nx -c-> f\ny -c-> g\nfinal f g\n",
+        )
+        .unwrap();
+        let minimal = redundant.minimise();
+        assert_eq!(minimal.names, vec!["s", "x", "f"]);
+        assert_eq!(minimal.accepting, vec![false, false, true]);
+        assert_eq!(
+            minimal.lts.edges,
+            vec![
+                vec![(Some("a".to_string()), 1), (Some("b".to_string()), 1)],
+                vec![(Some("c".to_string()), 2)],
+                vec![],
+            ]
+        );
+        assert_eq!(minimal.minimise(), minimal);
+        assert_eq!(
+            Automaton::from_dot(&generate_automaton_dot(&minimal)).unwrap(),
+            minimal
+        );
+    }
+
+    #[test]
+    fn test_final_markings_of_a_net() {
+        // p0 -t-> p1 -u-> p0, only the marking with the token in p1 is final
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1)], vec![(0, 1), (1, 0)]],
+            t_names: vec!["t".to_string(), "u".to_string()],
+            ..Default::default()
+        };
+        let target = Target::parse_predicate(&input, "p1 = 1").unwrap();
+        let graph = explore(&input, &Default::default());
+        let automaton = Automaton::from_graph(&input, &graph, |m| target.holds(m));
+        assert_eq!(automaton.names, vec!["1-0", "0-1"]);
+        assert_eq!(automaton.accepting, vec![false, true]);
+        assert_eq!(automaton.minimise().lts, automaton.lts);
+    }
+}
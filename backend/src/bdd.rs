@@ -0,0 +1,528 @@
+use std::collections::{HashMap, HashSet};
+
+/// index of a node of a `Bdd`, `FALSE` and `TRUE` are the two terminals
+pub type Node = u32;
+
+pub const FALSE: Node = 0;
+pub const TRUE: Node = 1;
+
+/// entries of the operation cache before it is emptied
+const CACHE_LIMIT: usize = 1 << 22;
+
+/// variable of the terminals, below every other variable
+const TERMINAL: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Branch {
+    var: u32,
+    low: Node,
+    high: Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operation {
+    And,
+    Or,
+    Not,
+}
+
+/// events of each top variable and the nodes already saturated or fired
+struct Saturation {
+    tops: HashMap<u32, Vec<Node>>,
+    vars: u32,
+    backward: bool,
+    saturated: HashMap<(u32, Node, Node), Node>,
+    fired: HashMap<(u32, Node, Node, Node), Node>,
+}
+
+/// reduced ordered binary decision diagrams sharing their nodes, variables
+/// are ordered by their index and never freed
+#[derive(Debug, Clone)]
+pub struct Bdd {
+    nodes: Vec<Branch>,
+    unique: HashMap<Branch, Node>,
+    cache: HashMap<(Operation, Node, Node), Node>,
+}
+
+impl Default for Bdd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bdd {
+    pub fn new() -> Self {
+        let terminal = |node| Branch {
+            var: TERMINAL,
+            low: node,
+            high: node,
+        };
+        Bdd {
+            nodes: vec![terminal(FALSE), terminal(TRUE)],
+            unique: HashMap::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// number of nodes ever created, terminals included
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// number of nodes reachable from `f`
+    pub fn node_count(&self, f: Node) -> usize {
+        let mut seen = HashSet::from([f]);
+        let mut stack = vec![f];
+        while let Some(node) = stack.pop() {
+            let branch = self.nodes[node as usize];
+            for next in [branch.low, branch.high] {
+                if seen.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        seen.len()
+    }
+
+    fn make(&mut self, var: u32, low: Node, high: Node) -> Node {
+        if low == high {
+            return low;
+        }
+        let branch = Branch { var, low, high };
+        if let Some(node) = self.unique.get(&branch) {
+            return *node;
+        }
+        let node = self.nodes.len() as Node;
+        self.nodes.push(branch);
+        self.unique.insert(branch, node);
+        node
+    }
+
+    fn remember(&mut self, key: (Operation, Node, Node), result: Node) -> Node {
+        if self.cache.len() >= CACHE_LIMIT {
+            self.cache.clear();
+        }
+        self.cache.insert(key, result);
+        result
+    }
+
+    fn var_of(&self, f: Node) -> u32 {
+        self.nodes[f as usize].var
+    }
+
+    /// low and high cofactors of `f` for `var`
+    fn cofactors(&self, f: Node, var: u32) -> (Node, Node) {
+        let branch = self.nodes[f as usize];
+        match branch.var == var {
+            true => (branch.low, branch.high),
+            false => (f, f),
+        }
+    }
+
+    /// the literal `var`, or its negation when `value` is false
+    pub fn literal(&mut self, var: u32, value: bool) -> Node {
+        match value {
+            true => self.make(var, FALSE, TRUE),
+            false => self.make(var, TRUE, FALSE),
+        }
+    }
+
+    pub fn not(&mut self, f: Node) -> Node {
+        match f {
+            FALSE => return TRUE,
+            TRUE => return FALSE,
+            _ => (),
+        }
+        let key = (Operation::Not, f, f);
+        if let Some(result) = self.cache.get(&key) {
+            return *result;
+        }
+        let branch = self.nodes[f as usize];
+        let low = self.not(branch.low);
+        let high = self.not(branch.high);
+        let result = self.make(branch.var, low, high);
+        self.remember(key, result)
+    }
+
+    pub fn and(&mut self, f: Node, g: Node) -> Node {
+        if f == FALSE || g == FALSE {
+            return FALSE;
+        }
+        if f == TRUE || f == g {
+            return g;
+        }
+        if g == TRUE {
+            return f;
+        }
+        self.apply(Operation::And, f, g)
+    }
+
+    pub fn or(&mut self, f: Node, g: Node) -> Node {
+        if f == TRUE || g == TRUE {
+            return TRUE;
+        }
+        if f == FALSE || f == g {
+            return g;
+        }
+        if g == FALSE {
+            return f;
+        }
+        self.apply(Operation::Or, f, g)
+    }
+
+    /// states of `f` outside of `g`
+    pub fn diff(&mut self, f: Node, g: Node) -> Node {
+        let not_g = self.not(g);
+        self.and(f, not_g)
+    }
+
+    fn apply(&mut self, operation: Operation, f: Node, g: Node) -> Node {
+        let key = (operation, f.min(g), f.max(g));
+        if let Some(result) = self.cache.get(&key) {
+            return *result;
+        }
+        let var = self.var_of(f).min(self.var_of(g));
+        let (f0, f1) = self.cofactors(f, var);
+        let (g0, g1) = self.cofactors(g, var);
+        let (low, high) = match operation {
+            Operation::And => (self.and(f0, g0), self.and(f1, g1)),
+            _ => (self.or(f0, g0), self.or(f1, g1)),
+        };
+        let result = self.make(var, low, high);
+        self.remember(key, result)
+    }
+
+    /// `f` with the variables of `vars` quantified existentially
+    pub fn exists(&mut self, f: Node, vars: &[bool]) -> Node {
+        self.and_exists(f, TRUE, vars)
+    }
+
+    /// `∃ vars. f ∧ g` without building the conjunction (relational product)
+    pub fn and_exists(&mut self, f: Node, g: Node, vars: &[bool]) -> Node {
+        self.and_exists_in(f, g, vars, &mut HashMap::new())
+    }
+
+    fn and_exists_in(
+        &mut self,
+        f: Node,
+        g: Node,
+        vars: &[bool],
+        memo: &mut HashMap<(Node, Node), Node>,
+    ) -> Node {
+        if f == FALSE || g == FALSE {
+            return FALSE;
+        }
+        if f == TRUE && g == TRUE {
+            return TRUE;
+        }
+        let key = (f.min(g), f.max(g));
+        if let Some(result) = memo.get(&key) {
+            return *result;
+        }
+        let var = self.var_of(f).min(self.var_of(g));
+        let (f0, f1) = self.cofactors(f, var);
+        let (g0, g1) = self.cofactors(g, var);
+        let result = match vars.get(var as usize).copied().unwrap_or(false) {
+            true => {
+                let low = self.and_exists_in(f0, g0, vars, memo);
+                match low {
+                    TRUE => TRUE,
+                    _ => {
+                        let high = self.and_exists_in(f1, g1, vars, memo);
+                        self.or(low, high)
+                    }
+                }
+            }
+            false => {
+                let low = self.and_exists_in(f0, g0, vars, memo);
+                let high = self.and_exists_in(f1, g1, vars, memo);
+                self.make(var, low, high)
+            }
+        };
+        memo.insert(key, result);
+        result
+    }
+
+    /// `f` with each variable `v` replaced by `rename(v)`, the renaming must
+    /// keep the order of the variables of `f`
+    pub fn rename(&mut self, f: Node, rename: &impl Fn(u32) -> u32) -> Node {
+        self.rename_in(f, rename, &mut HashMap::new())
+    }
+
+    fn rename_in(
+        &mut self,
+        f: Node,
+        rename: &impl Fn(u32) -> u32,
+        memo: &mut HashMap<Node, Node>,
+    ) -> Node {
+        if f <= TRUE {
+            return f;
+        }
+        if let Some(result) = memo.get(&f) {
+            return *result;
+        }
+        let branch = self.nodes[f as usize];
+        let low = self.rename_in(branch.low, rename, memo);
+        let high = self.rename_in(branch.high, rename, memo);
+        let var = rename(branch.var);
+        debug_assert!(var < self.var_of(low) && var < self.var_of(high));
+        let result = self.make(var, low, high);
+        memo.insert(f, result);
+        result
+    }
+
+    /// number of assignments of the sorted `vars` satisfying `f`, `None`
+    /// when `f` depends on another variable
+    pub fn count(&self, f: Node, vars: &[u32]) -> Option<f64> {
+        let position = |var: u32| match var {
+            TERMINAL => Some(vars.len()),
+            _ => vars.binary_search(&var).ok(),
+        };
+        let mut memo = HashMap::new();
+        let mut stack = vec![f];
+        // assignments of the variables from the one of the node downwards
+        while let Some(&node) = stack.last() {
+            if node <= TRUE {
+                memo.insert(node, f64::from(node));
+                stack.pop();
+                continue;
+            }
+            let branch = self.nodes[node as usize];
+            let missing = [branch.low, branch.high]
+                .into_iter()
+                .filter(|next| !memo.contains_key(next))
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            let level = position(branch.var)?;
+            let mut total = 0.0;
+            for next in [branch.low, branch.high] {
+                let skipped = position(self.var_of(next))? - level - 1;
+                total += memo[&next] * 2f64.powi(skipped as i32);
+            }
+            memo.insert(node, total);
+            stack.pop();
+        }
+        Some(memo[&f] * 2f64.powi(position(self.var_of(f))? as i32))
+    }
+
+    /// least set holding `start` and closed by the `events`, forwards or
+    /// backwards, whose states are in `within`, by saturation: each event
+    /// is fired at the nodes of its top variable once the nodes below are
+    /// saturated; the states use the current variables `0, 2, .., vars - 2`
+    /// and the events relate them to the next ones following them, the
+    /// current variables whose next variable an event leaves out keep their
+    /// value
+    pub fn saturate(
+        &mut self,
+        start: Node,
+        within: Node,
+        events: &[Node],
+        vars: u32,
+        backward: bool,
+    ) -> Node {
+        let mut saturation = Saturation {
+            tops: HashMap::new(),
+            vars,
+            backward,
+            saturated: HashMap::new(),
+            fired: HashMap::new(),
+        };
+        for event in events.iter().filter(|e| **e > TRUE) {
+            let top = self.var_of(*event) & !1;
+            saturation.tops.entry(top).or_default().push(*event);
+        }
+        let within = self.or(within, start);
+        self.saturated(&mut saturation, 0, start, within)
+    }
+
+    /// `p` closed by the events whose top variable is `var` or below, `p`
+    /// must be in `within`
+    fn saturated(&mut self, s: &mut Saturation, var: u32, p: Node, within: Node) -> Node {
+        if p == FALSE || var >= s.vars {
+            return p;
+        }
+        if let Some(result) = s.saturated.get(&(var, p, within)) {
+            return *result;
+        }
+        let (p0, p1) = self.cofactors(p, var);
+        let (c0, c1) = self.cofactors(within, var);
+        let mut r = [
+            self.saturated(s, var + 2, p0, c0),
+            self.saturated(s, var + 2, p1, c1),
+        ];
+        let events = s.tops.get(&var).cloned().unwrap_or_default();
+        loop {
+            let old = r;
+            for event in &events {
+                for (from, to, relation) in self.parts(s, *event, var) {
+                    if r[from] == FALSE {
+                        continue;
+                    }
+                    let c = [c0, c1][to];
+                    let image = self.fired(s, var + 2, r[from], relation, c);
+                    r[to] = self.or(r[to], image);
+                }
+            }
+            if old == r {
+                break;
+            }
+        }
+        let result = self.make(var, r[0], r[1]);
+        s.saturated.insert((var, p, within), result);
+        result
+    }
+
+    /// saturated image of `p` by the part of an event below `var`
+    fn fired(
+        &mut self,
+        s: &mut Saturation,
+        var: u32,
+        p: Node,
+        relation: Node,
+        within: Node,
+    ) -> Node {
+        if p == FALSE || relation == FALSE || within == FALSE {
+            return FALSE;
+        }
+        if relation == TRUE {
+            let p = self.and(p, within);
+            return self.saturated(s, var, p, within);
+        }
+        let key = (var, p, relation, within);
+        if let Some(result) = s.fired.get(&key) {
+            return *result;
+        }
+        let (p0, p1) = self.cofactors(p, var);
+        let (c0, c1) = self.cofactors(within, var);
+        let mut r = [FALSE, FALSE];
+        for (from, to, part) in self.parts(s, relation, var) {
+            let image = self.fired(s, var + 2, [p0, p1][from], part, [c0, c1][to]);
+            r[to] = self.or(r[to], image);
+        }
+        let image = self.make(var, r[0], r[1]);
+        let result = self.saturated(s, var, image, within);
+        s.fired.insert(key, result);
+        result
+    }
+
+    /// values of the current variable `var` in the set fired from and in
+    /// the image, with the rest of `relation`
+    fn parts(&self, s: &Saturation, relation: Node, var: u32) -> Vec<(usize, usize, Node)> {
+        let halves = <[Node; 2]>::from(self.cofactors(relation, var));
+        let mut parts = vec![];
+        for (before, half) in halves.into_iter().enumerate() {
+            if self.var_of(half) != var + 1 {
+                parts.push((before, before, half));
+                continue;
+            }
+            let (low, high) = self.cofactors(half, var + 1);
+            for (after, part) in [low, high].into_iter().enumerate() {
+                match s.backward {
+                    true => parts.push((after, before, part)),
+                    false => parts.push((before, after, part)),
+                }
+            }
+        }
+        parts
+    }
+
+    /// values of the variables along a path of `f` to `TRUE`, the variables
+    /// left out can take any value
+    pub fn pick(&self, f: Node) -> Option<Vec<(u32, bool)>> {
+        if f == FALSE {
+            return None;
+        }
+        let mut path = vec![];
+        let mut node = f;
+        while node != TRUE {
+            let branch = self.nodes[node as usize];
+            let value = branch.low == FALSE;
+            path.push((branch.var, value));
+            node = match value {
+                true => branch.high,
+                false => branch.low,
+            };
+        }
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bdd, FALSE, TRUE};
+
+    #[test]
+    fn test_operations_and_counts() {
+        let mut bdd = Bdd::new();
+        let x: Vec<_> = (0..3).map(|v| bdd.literal(v, true)).collect();
+        let a = bdd.and(x[0], x[1]);
+        let f = bdd.or(a, x[2]);
+        // x0 x1 + x2 over three variables
+        assert_eq!(bdd.count(f, &[0, 1, 2]), Some(5.0));
+        assert_eq!(bdd.count(f, &[0, 1, 2, 3]), Some(10.0));
+        assert_eq!(bdd.count(f, &[0, 2]), None);
+        let not_f = bdd.not(f);
+        assert_eq!(bdd.and(f, not_f), FALSE);
+        assert_eq!(bdd.or(f, not_f), TRUE);
+        assert_eq!(bdd.diff(f, x[2]), bdd.diff(a, x[2]));
+        // ∃x2. f is true, ∃x0. x0 x1 is x1
+        assert_eq!(bdd.exists(f, &[false, false, true]), TRUE);
+        assert_eq!(bdd.exists(a, &[true]), x[1]);
+        assert_eq!(bdd.and_exists(x[0], x[1], &[true, true]), TRUE);
+        // moving every variable one step down keeps the order
+        let shifted = bdd.rename(a, &|v| v + 1);
+        let expected = {
+            let y1 = bdd.literal(1, true);
+            let y2 = bdd.literal(2, true);
+            bdd.and(y1, y2)
+        };
+        assert_eq!(shifted, expected);
+        let path = bdd.pick(not_f).unwrap();
+        let value = |v| path.iter().find(|(w, _)| *w == v).map(|(_, b)| *b);
+        assert_eq!(value(2), Some(false));
+        assert_ne!((value(0), value(1)), (Some(true), Some(true)));
+        assert_eq!(bdd.pick(FALSE), None);
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut bdd = Bdd::new();
+        // x0 and x1 are the current variables 0 and 2, e sets x0 and f
+        // sets x1 once x0 is set, keeping x0
+        let e = {
+            let before = bdd.literal(0, false);
+            let after = bdd.literal(1, true);
+            bdd.and(before, after)
+        };
+        let f = {
+            let x0 = bdd.literal(0, true);
+            let before = bdd.literal(2, false);
+            let after = bdd.literal(3, true);
+            let moved = bdd.and(before, after);
+            bdd.and(x0, moved)
+        };
+        let none = {
+            let x0 = bdd.literal(0, false);
+            let x1 = bdd.literal(2, false);
+            bdd.and(x0, x1)
+        };
+        let both = {
+            let x0 = bdd.literal(0, true);
+            let x1 = bdd.literal(2, true);
+            bdd.and(x0, x1)
+        };
+        let reached = bdd.saturate(none, TRUE, &[e, f], 4, false);
+        assert_eq!(bdd.count(reached, &[0, 2]), Some(3.0));
+        assert_eq!(bdd.and(reached, both), both);
+        let unset = bdd.literal(2, false);
+        let reached = bdd.saturate(none, unset, &[e, f], 4, false);
+        assert_eq!(bdd.count(reached, &[0, 2]), Some(2.0));
+        // every state reaches both, e also sets x0 once x1 is set
+        assert_eq!(bdd.saturate(both, TRUE, &[e, f], 4, true), TRUE);
+        let reaching = bdd.saturate(both, reached, &[e, f], 4, true);
+        assert_eq!(bdd.count(reaching, &[0, 2]), Some(3.0));
+    }
+}
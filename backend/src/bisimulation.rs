@@ -0,0 +1,435 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use iter_tools::Itertools;
+
+use crate::graph_gen::{Marking, ReachabilityGraph, Step};
+
+/// partition of the states being refined, the blocks are grouped in
+/// compound blocks the partition is known to be stable against
+struct Partition {
+    block_of: Vec<usize>,
+    blocks: Vec<Vec<usize>>,
+    compound_of: Vec<usize>,
+    compounds: Vec<Vec<usize>>,
+    /// compound blocks holding several blocks, with duplicates
+    splitters: Vec<usize>,
+}
+
+impl Partition {
+    /// moves the `marked` states of each block into a new block unless the
+    /// whole block is marked, new blocks join the compound of their origin
+    fn split(&mut self, marked: &HashSet<usize>) {
+        let mut touched = HashMap::<usize, Vec<usize>>::new();
+        for s in marked {
+            touched.entry(self.block_of[*s]).or_default().push(*s);
+        }
+        let mut touched = touched.into_iter().collect::<Vec<_>>();
+        touched.sort_unstable();
+        for (block, mut states) in touched {
+            if states.len() == self.blocks[block].len() {
+                continue;
+            }
+            states.sort_unstable();
+            self.blocks[block].retain(|s| !marked.contains(s));
+            let new = self.blocks.len();
+            for s in &states {
+                self.block_of[*s] = new;
+            }
+            self.blocks.push(states);
+            let compound = self.compound_of[block];
+            self.compound_of.push(compound);
+            self.compounds[compound].push(new);
+            if self.compounds[compound].len() == 2 {
+                self.splitters.push(compound);
+            }
+        }
+    }
+}
+
+/// coarsest strong bisimulation of a labelled transition system (Paige–Tarjan),
+/// `edges[s]` holds the labelled successors of `s`, returns the class of
+/// every state, classes are numbered by their smallest state
+pub fn strong_bisimulation<L: Eq + Hash + Clone>(edges: &[Vec<(L, usize)>]) -> Vec<usize> {
+    let n = edges.len();
+    let mut labels = HashMap::new();
+    // (source, label, target) and the edges entering each state
+    let mut arcs = vec![];
+    let mut incoming = vec![vec![]; n];
+    for (s, next) in edges.iter().enumerate() {
+        for (label, t) in next {
+            let count = labels.len();
+            let label = *labels.entry(label.clone()).or_insert(count);
+            incoming[*t].push(arcs.len());
+            arcs.push((s, label, *t));
+        }
+    }
+
+    let mut partition = Partition {
+        block_of: vec![0; n],
+        blocks: vec![(0..n).collect()],
+        compound_of: vec![0],
+        compounds: vec![vec![0]],
+        splitters: vec![],
+    };
+    // stable against the whole state space: same labels enabled, one count
+    // of the `label` successors of `s` shared by its arcs
+    let mut counts = vec![];
+    let mut cells = HashMap::new();
+    let mut cell_of = vec![0; arcs.len()];
+    for (e, (s, label, _)) in arcs.iter().enumerate() {
+        let cell = *cells.entry((*s, *label)).or_insert_with(|| {
+            counts.push(0);
+            counts.len() - 1
+        });
+        counts[cell] += 1;
+        cell_of[e] = cell;
+    }
+    for label in 0..labels.len() {
+        let enabled = cells
+            .keys()
+            .filter(|(_, l)| *l == label)
+            .map(|(s, _)| *s)
+            .collect();
+        partition.split(&enabled);
+    }
+
+    while let Some(compound) = partition.splitters.pop() {
+        if partition.compounds[compound].len() < 2 {
+            continue;
+        }
+        // splits against the smaller of two blocks of the compound and the
+        // rest of it, then makes that block a compound of its own
+        let (first, second) = (
+            partition.compounds[compound][0],
+            partition.compounds[compound][1],
+        );
+        let splitter = match partition.blocks[first].len() <= partition.blocks[second].len() {
+            true => first,
+            false => second,
+        };
+        partition.compounds[compound].retain(|b| *b != splitter);
+        if partition.compounds[compound].len() >= 2 {
+            partition.splitters.push(compound);
+        }
+        partition.compound_of[splitter] = partition.compounds.len();
+        partition.compounds.push(vec![splitter]);
+
+        let mut by_label = HashMap::<usize, Vec<usize>>::new();
+        for t in &partition.blocks[splitter] {
+            for e in &incoming[*t] {
+                by_label.entry(arcs[*e].1).or_default().push(*e);
+            }
+        }
+        let mut by_label = by_label.into_iter().collect::<Vec<_>>();
+        by_label.sort_unstable();
+        for (_, entering) in by_label {
+            // number of arcs from each source into the splitter, next to
+            // the number of arcs into the whole compound
+            let mut into_splitter = HashMap::new();
+            for e in &entering {
+                let s = arcs[*e].0;
+                let cell = *into_splitter.entry(s).or_insert_with(|| {
+                    counts.push(0);
+                    (counts.len() - 1, cell_of[*e])
+                });
+                counts[cell.0] += 1;
+            }
+            let predecessors = into_splitter.keys().copied().collect();
+            let only_into_splitter = into_splitter
+                .iter()
+                .filter(|(_, (inner, outer))| counts[*inner] == counts[*outer])
+                .map(|(s, _)| *s)
+                .collect();
+            partition.split(&predecessors);
+            partition.split(&only_into_splitter);
+            for e in &entering {
+                let (inner, outer) = into_splitter[&arcs[*e].0];
+                counts[outer] -= 1;
+                cell_of[*e] = inner;
+            }
+        }
+    }
+
+    let mut numbers = HashMap::new();
+    (0..n)
+        .map(|s| {
+            let count = numbers.len();
+            *numbers.entry(partition.block_of[s]).or_insert(count)
+        })
+        .collect()
+}
+
+/// the system where `s =a=> t` stands for `s τ* a τ* t` and `s =τ=> t` for
+/// `s τ* t`, `None` labels standing for tau
+pub fn saturate<L: Eq + Hash + Clone>(
+    edges: &[Vec<(Option<L>, usize)>],
+) -> Vec<Vec<(Option<L>, usize)>> {
+    let closures = (0..edges.len())
+        .map(|s| {
+            let mut reached = vec![s];
+            let mut seen = HashSet::from([s]);
+            let mut current = 0;
+            while current < reached.len() {
+                for (label, t) in &edges[reached[current]] {
+                    if label.is_none() && seen.insert(*t) {
+                        reached.push(*t);
+                    }
+                }
+                current += 1;
+            }
+            reached
+        })
+        .collect::<Vec<_>>();
+    closures
+        .iter()
+        .map(|closure| {
+            let mut next = closure.iter().map(|t| (None, *t)).collect::<Vec<_>>();
+            for u in closure {
+                for (label, v) in &edges[*u] {
+                    if label.is_some() {
+                        next.extend(closures[*v].iter().map(|t| (label.clone(), *t)));
+                    }
+                }
+            }
+            next.into_iter().unique().collect()
+        })
+        .collect()
+}
+
+/// coarsest weak bisimulation, `None` labels standing for tau: the strong
+/// bisimulation of the saturated system
+pub fn weak_bisimulation<L: Eq + Hash + Clone>(edges: &[Vec<(Option<L>, usize)>]) -> Vec<usize> {
+    strong_bisimulation(&saturate(edges))
+}
+
+/// coarsest branching bisimulation, `None` labels standing for tau
+/// (Groote–Vaandrager): a block is split until either all or none of its
+/// states can reach, by tau steps inside the block, a step labelled `a`
+/// into any given block, tau steps inside a block being inert
+pub fn branching_bisimulation<L: Eq + Hash + Clone>(
+    edges: &[Vec<(Option<L>, usize)>],
+) -> Vec<usize> {
+    let n = edges.len();
+    let mut tau_predecessors = vec![vec![]; n];
+    for (s, next) in edges.iter().enumerate() {
+        for (label, t) in next {
+            if label.is_none() {
+                tau_predecessors[*t].push(s);
+            }
+        }
+    }
+    let mut block_of = vec![0; n];
+    // number of states of each block, the blocks are numbered as created
+    let mut sizes = vec![n];
+    loop {
+        let mut splitters = HashMap::<(Option<&L>, usize), Vec<(usize, usize)>>::new();
+        for (s, next) in edges.iter().enumerate() {
+            for (label, t) in next {
+                splitters
+                    .entry((label.as_ref(), block_of[*t]))
+                    .or_default()
+                    .push((s, *t));
+            }
+        }
+        let mut splitters = splitters.into_iter().collect::<Vec<_>>();
+        splitters.sort_unstable_by_key(|((_, block), arcs)| (*block, arcs[0]));
+
+        let mut stable = true;
+        for ((label, target), arcs) in splitters {
+            // the blocks split earlier in the round keep the number of the
+            // part holding their states left unreached
+            let mut reached = arcs
+                .into_iter()
+                .filter(|(s, t)| {
+                    block_of[*t] == target && (label.is_some() || block_of[*s] != target)
+                })
+                .map(|(s, _)| s)
+                .collect::<Vec<_>>();
+            reached.sort_unstable();
+            reached.dedup();
+            let mut seen = reached.iter().copied().collect::<HashSet<_>>();
+            let mut current = 0;
+            while current < reached.len() {
+                let s = reached[current];
+                for p in &tau_predecessors[s] {
+                    if block_of[*p] == block_of[s] && seen.insert(*p) {
+                        reached.push(*p);
+                    }
+                }
+                current += 1;
+            }
+            let mut parts = HashMap::<usize, usize>::new();
+            for s in &reached {
+                *parts.entry(block_of[*s]).or_default() += 1;
+            }
+            let mut split = parts
+                .into_iter()
+                .filter(|(block, count)| *count < sizes[*block])
+                .collect::<Vec<_>>();
+            split.sort_unstable();
+            for (block, count) in split {
+                stable = false;
+                for s in &reached {
+                    if block_of[*s] == block {
+                        block_of[*s] = sizes.len();
+                    }
+                }
+                sizes[block] -= count;
+                sizes.push(count);
+            }
+        }
+        if stable {
+            break;
+        }
+    }
+
+    let mut numbers = HashMap::new();
+    block_of
+        .iter()
+        .map(|block| {
+            let count = numbers.len();
+            *numbers.entry(*block).or_insert(count)
+        })
+        .collect()
+}
+
+/// automaton whose states are the classes of bisimilar states of a graph,
+/// each state carries the marking of the first state of its class
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quotient {
+    pub graph: ReachabilityGraph,
+    /// states of the original graph merged into each state
+    pub members: Vec<Vec<usize>>,
+}
+
+impl Quotient {
+    /// quotient modulo strong bisimulation on the fired steps
+    pub fn strong(graph: &ReachabilityGraph) -> Self {
+        Self::from_classes(graph, &strong_bisimulation(&graph.edges))
+    }
+
+    /// merges the states of `graph` in the same class, `classes` being
+    /// numbered from 0 by their smallest state
+    pub fn from_classes(graph: &ReachabilityGraph, classes: &[usize]) -> Self {
+        let count = classes.iter().max().map_or(0, |c| c + 1);
+        let mut members = vec![vec![]; count];
+        for (s, class) in classes.iter().enumerate() {
+            members[*class].push(s);
+        }
+        let edges = members
+            .iter()
+            .map(|states| {
+                let mut next = states
+                    .iter()
+                    .flat_map(|s| &graph.edges[*s])
+                    .map(|(step, t)| (step.clone(), classes[*t]))
+                    .collect::<Vec<(Step, usize)>>();
+                next.sort_unstable();
+                next.dedup();
+                next
+            })
+            .collect();
+        Quotient {
+            graph: ReachabilityGraph {
+                initial: classes[graph.initial],
+                markings: members
+                    .iter()
+                    .map(|states| graph.markings[states[0]].clone())
+                    .collect(),
+                edges,
+            },
+            members,
+        }
+    }
+
+    /// markings of the original states merged into `state`
+    pub fn markings<'a>(&self, original: &'a ReachabilityGraph, state: usize) -> Vec<&'a Marking> {
+        self.members[state]
+            .iter()
+            .map(|s| &original.markings[*s])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{branching_bisimulation, strong_bisimulation, weak_bisimulation, Quotient};
+    use crate::graph_gen::ReachabilityGraph;
+
+    #[test]
+    fn test_strong_bisimulation() {
+        // a.b + a.b merges the two branches, a.b + a.c does not
+        let edges = vec![
+            vec![('a', 1), ('a', 2)],
+            vec![('b', 3)],
+            vec![('b', 4)],
+            vec![],
+            vec![],
+        ];
+        assert_eq!(strong_bisimulation(&edges), vec![0, 1, 1, 2, 2]);
+        let edges = vec![
+            vec![('a', 1), ('a', 2)],
+            vec![('b', 3)],
+            vec![('c', 3)],
+            vec![],
+        ];
+        assert_eq!(strong_bisimulation(&edges), vec![0, 1, 2, 3]);
+        // a.a.a... forever whatever the length of the cycle
+        let edges = vec![vec![('a', 1)], vec![('a', 2)], vec![('a', 0)]];
+        assert_eq!(strong_bisimulation(&edges), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_weak_and_branching_bisimulation() {
+        // τ.(a + b) + a against a + b: the τ step is inert
+        let edges = vec![
+            vec![(None, 1), (Some('a'), 2)],
+            vec![(Some('a'), 2), (Some('b'), 2)],
+            vec![],
+        ];
+        for classes in [weak_bisimulation(&edges), branching_bisimulation(&edges)] {
+            assert_eq!(classes, vec![0, 0, 1]);
+        }
+        // a.(τ.b + c) + a.b against a.(τ.b + c): weakly but not branching
+        // bisimilar, the a.b branch skips the choice of c
+        let edges = vec![
+            vec![(Some('a'), 1), (Some('a'), 4)],
+            vec![(None, 2), (Some('c'), 3)],
+            vec![(Some('b'), 3)],
+            vec![],
+            vec![(Some('b'), 3)],
+            vec![(Some('a'), 1)],
+        ];
+        let weak = weak_bisimulation(&edges);
+        assert_eq!(weak[0], weak[5]);
+        let branching = branching_bisimulation(&edges);
+        assert_eq!(branching[2], branching[4]);
+        assert_ne!(branching[0], branching[5]);
+    }
+
+    #[test]
+    fn test_quotient_keeps_the_markings() {
+        // two tokens moving one at a time from p0 to p1 by t0
+        let graph = ReachabilityGraph {
+            initial: 0,
+            markings: vec![
+                vec![Some(2), Some(0)],
+                vec![Some(1), Some(1)],
+                vec![Some(0), Some(2)],
+            ],
+            edges: vec![vec![(vec![0], 1)], vec![(vec![0], 2)], vec![(vec![1], 0)]],
+        };
+        let quotient = Quotient::strong(&graph);
+        assert_eq!(quotient.graph.edges.len(), 3);
+        let graph = ReachabilityGraph {
+            edges: vec![vec![(vec![0], 1)], vec![(vec![0], 2)], vec![(vec![0], 0)]],
+            ..graph
+        };
+        let quotient = Quotient::strong(&graph);
+        assert_eq!(quotient.graph.edges, vec![vec![(vec![0], 0)]]);
+        assert_eq!(quotient.markings(&graph, 0).len(), 3);
+    }
+}
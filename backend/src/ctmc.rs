@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{explore, Delay, ExplorationOptions, Input, Marking, ReachabilityGraph},
+};
+
+/// precision the iterative solvers stop at
+const EPSILON: f64 = 1e-12;
+
+/// maximum number of iterations of the solvers
+const MAX_ITERATIONS: usize = 1_000_000;
+
+/// states of the systems solved by Gaussian elimination, bigger ones are
+/// iterated
+const DIRECT_LIMIT: usize = 2000;
+
+/// continuous time markov chain of a bounded stochastic petri net, its states
+/// are the tangible markings (markings without enabled immediate transitions)
+#[derive(Debug, Clone)]
+pub struct Ctmc {
+    pub states: Vec<Marking>,
+    /// probability of starting in each state, more than one state when the
+    /// initial marking is vanishing
+    pub initial: Vec<f64>,
+    /// rate of going from a state to each of its successors, without self loops
+    pub rates: Vec<Vec<(usize, f64)>>,
+}
+
+/// a state of the reachability graph once the immediate transitions have
+/// been resolved
+#[derive(Debug, Clone)]
+enum Resolved {
+    Tangible(usize),
+    /// probabilities of reaching each tangible state
+    Vanishing(HashMap<usize, f64>),
+}
+
+impl Ctmc {
+    /// derives the markov chain from the reachability graph of `input`,
+    /// the net must be bounded and free of loops of immediate transitions
+    pub fn build(input: &Input, options: &ExplorationOptions) -> Result<Self, ErrorTypes> {
+        let graph = explore(
+            input,
+            &ExplorationOptions {
+                capacity_rule: options.capacity_rule,
+                ..Default::default()
+            },
+        );
+        if graph.markings.iter().flatten().any(|x| x.is_none()) {
+            return Err(ErrorTypes::BadStochasticNet {
+                reason: "the net is unbounded".to_string(),
+            });
+        }
+        Self::from_graph(input, &graph)
+    }
+
+    fn from_graph(input: &Input, graph: &ReachabilityGraph) -> Result<Self, ErrorTypes> {
+        let immediate = |t: usize| matches!(input.delay(t), Delay::Immediate(_));
+        let vanishing = graph
+            .edges
+            .iter()
+            .map(|next| next.iter().any(|(step, _)| immediate(step[0])))
+            .collect::<Vec<_>>();
+
+        // numbering of the tangible states
+        let mut tangible = vec![None; graph.markings.len()];
+        let mut states = vec![];
+        for (i, marking) in graph.markings.iter().enumerate() {
+            if !vanishing[i] {
+                tangible[i] = Some(states.len());
+                states.push(marking.clone());
+            }
+        }
+
+        let resolved = resolve_vanishing(input, graph, &vanishing, &tangible)?;
+
+        let mut rates = vec![vec![]; states.len()];
+        for (i, next) in graph.edges.iter().enumerate() {
+            let Some(from) = tangible[i] else {
+                continue;
+            };
+            let mut row: HashMap<usize, f64> = HashMap::new();
+            for (step, n) in next {
+                let Delay::Exponential(rate) = input.delay(step[0]) else {
+                    continue;
+                };
+                match &resolved[*n] {
+                    Resolved::Tangible(to) => *row.entry(*to).or_default() += rate,
+                    Resolved::Vanishing(reached) => {
+                        for (to, p) in reached {
+                            *row.entry(*to).or_default() += rate * p;
+                        }
+                    }
+                }
+            }
+            row.remove(&from);
+            let mut row = row.into_iter().collect::<Vec<_>>();
+            row.sort_by_key(|(to, _)| *to);
+            rates[from] = row;
+        }
+
+        let mut initial = vec![0.0; states.len()];
+        match &resolved[graph.initial] {
+            Resolved::Tangible(i) => initial[*i] = 1.0,
+            Resolved::Vanishing(reached) => {
+                for (i, p) in reached {
+                    initial[*i] = *p;
+                }
+            }
+        }
+
+        Ok(Ctmc {
+            states,
+            initial,
+            rates,
+        })
+    }
+
+    pub fn transitions_count(&self) -> usize {
+        self.rates.iter().map(|row| row.len()).sum()
+    }
+
+    /// rate the uniformized chain jumps at, above every exit rate so the
+    /// jump chain is aperiodic
+    fn uniformization_rate(&self) -> f64 {
+        let max_exit = self
+            .rates
+            .iter()
+            .map(|row| row.iter().map(|(_, r)| r).sum::<f64>())
+            .fold(0.0, f64::max);
+        if max_exit > 0.0 {
+            max_exit * 1.1
+        } else {
+            1.0
+        }
+    }
+
+    /// `distribution Q`, the flow of probability into each state minus the
+    /// flow out of it
+    fn residual(&self, distribution: &[f64]) -> Vec<f64> {
+        let mut flow = vec![0.0; distribution.len()];
+        for (i, row) in self.rates.iter().enumerate() {
+            if distribution[i] == 0.0 {
+                continue;
+            }
+            for (j, rate) in row {
+                flow[*j] += distribution[i] * rate;
+                flow[i] -= distribution[i] * rate;
+            }
+        }
+        flow
+    }
+
+    /// one step of the uniformized chain `P = I + Q / lambda`
+    fn jump(&self, distribution: &[f64], lambda: f64) -> Vec<f64> {
+        let flow = self.residual(distribution);
+        distribution
+            .iter()
+            .zip(flow)
+            .map(|(x, flow)| x + flow / lambda)
+            .collect()
+    }
+
+    /// the closed classes of the chain (bottom strongly connected
+    /// components) and the closed class of each state, `None` for the
+    /// transient states
+    fn closed_classes(&self) -> (Vec<Vec<usize>>, Vec<Option<usize>>) {
+        let n = self.states.len();
+        // finishing order of a depth first search
+        let mut order = vec![];
+        let mut seen = vec![false; n];
+        for root in 0..n {
+            if seen[root] {
+                continue;
+            }
+            seen[root] = true;
+            let mut stack = vec![(root, 0)];
+            while let Some(&(i, next)) = stack.last() {
+                match self.rates[i].get(next) {
+                    Some((j, _)) => {
+                        stack.last_mut().unwrap().1 += 1;
+                        if !seen[*j] {
+                            seen[*j] = true;
+                            stack.push((*j, 0));
+                        }
+                    }
+                    None => {
+                        order.push(i);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        // components of the reversed chain in reverse finishing order
+        let mut reverse = vec![vec![]; n];
+        for (i, row) in self.rates.iter().enumerate() {
+            for (j, _) in row {
+                reverse[*j].push(i);
+            }
+        }
+        let mut component = vec![None; n];
+        let mut components = vec![];
+        for root in order.into_iter().rev() {
+            if component[root].is_some() {
+                continue;
+            }
+            let id = components.len();
+            component[root] = Some(id);
+            let mut members = vec![root];
+            let mut stack = vec![root];
+            while let Some(i) = stack.pop() {
+                for j in &reverse[i] {
+                    if component[*j].is_none() {
+                        component[*j] = Some(id);
+                        members.push(*j);
+                        stack.push(*j);
+                    }
+                }
+            }
+            members.sort();
+            components.push(members);
+        }
+
+        let mut closed = vec![];
+        let mut class_of = vec![None; n];
+        for members in components {
+            let leaves = members.iter().any(|i| {
+                self.rates[*i]
+                    .iter()
+                    .any(|(j, _)| component[*j] != component[*i])
+            });
+            if !leaves {
+                for i in &members {
+                    class_of[*i] = Some(closed.len());
+                }
+                closed.push(members);
+            }
+        }
+        (closed, class_of)
+    }
+
+    /// stationary distribution of the closed class `class`, in the order of
+    /// its states
+    fn stationary(&self, class: &[usize]) -> Vec<f64> {
+        let k = class.len();
+        let index = class
+            .iter()
+            .enumerate()
+            .map(|(a, i)| (*i, a))
+            .collect::<HashMap<_, _>>();
+        if k <= DIRECT_LIMIT {
+            // the transposed equations of `pi Q = 0`, the last one replaced
+            // by the sum of the probabilities being 1
+            let mut matrix = vec![vec![0.0; k]; k];
+            for (a, i) in class.iter().enumerate() {
+                for (j, rate) in &self.rates[*i] {
+                    matrix[index[j]][a] += rate;
+                    matrix[a][a] -= rate;
+                }
+            }
+            matrix[k - 1] = vec![1.0; k];
+            let mut rhs = vec![0.0; k];
+            rhs[k - 1] = 1.0;
+            return solve(matrix, rhs);
+        }
+        let lambda = self.uniformization_rate();
+        let mut distribution = vec![0.0; self.states.len()];
+        for i in class {
+            distribution[*i] = 1.0 / k as f64;
+        }
+        for _ in 0..MAX_ITERATIONS {
+            let residual = self.residual(&distribution);
+            if residual.iter().all(|x| x.abs() < EPSILON) {
+                break;
+            }
+            distribution = self.jump(&distribution, lambda);
+        }
+        class.iter().map(|i| distribution[*i]).collect()
+    }
+
+    /// probability of ending in each closed class from the initial
+    /// distribution
+    fn absorption(&self, closed: &[Vec<usize>], class_of: &[Option<usize>]) -> Vec<f64> {
+        let mut absorbed = vec![0.0; closed.len()];
+        for (i, p) in self.initial.iter().enumerate() {
+            if let Some(class) = class_of[i] {
+                absorbed[class] += p;
+            }
+        }
+        let transient = (0..self.states.len())
+            .filter(|i| class_of[*i].is_none())
+            .collect::<Vec<_>>();
+        let index = transient
+            .iter()
+            .enumerate()
+            .map(|(a, i)| (*i, a))
+            .collect::<HashMap<_, _>>();
+        // a transient state always has an exit
+        let exit = |i: usize| self.rates[i].iter().map(|(_, r)| r).sum::<f64>();
+        let start = transient
+            .iter()
+            .map(|i| self.initial[*i])
+            .collect::<Vec<_>>();
+
+        // expected visits of the transient states by the jump chain
+        let visits = if transient.len() <= DIRECT_LIMIT {
+            let k = transient.len();
+            let mut matrix = vec![vec![0.0; k]; k];
+            for (a, i) in transient.iter().enumerate() {
+                matrix[a][a] += 1.0;
+                for (j, rate) in &self.rates[*i] {
+                    if let Some(b) = index.get(j) {
+                        matrix[*b][a] -= rate / exit(*i);
+                    }
+                }
+            }
+            solve(matrix, start)
+        } else {
+            // the mass left among the transient states bounds the error
+            let mut visits = vec![0.0; transient.len()];
+            let mut mass = start;
+            for _ in 0..MAX_ITERATIONS {
+                if mass.iter().sum::<f64>() < EPSILON {
+                    break;
+                }
+                let mut next = vec![0.0; transient.len()];
+                for (a, i) in transient.iter().enumerate() {
+                    visits[a] += mass[a];
+                    for (j, rate) in &self.rates[*i] {
+                        if let Some(b) = index.get(j) {
+                            next[*b] += mass[a] * rate / exit(*i);
+                        }
+                    }
+                }
+                mass = next;
+            }
+            visits
+        };
+
+        for (a, i) in transient.iter().enumerate() {
+            for (j, rate) in &self.rates[*i] {
+                if let Some(class) = class_of[*j] {
+                    absorbed[class] += visits[a] * rate / exit(*i);
+                }
+            }
+        }
+        absorbed
+    }
+
+    /// long run probability of each state starting from the initial
+    /// distribution: the stationary distribution of each closed class
+    /// weighted by the probability of ending in it, solved directly up to
+    /// `DIRECT_LIMIT` states and iterated beyond until the residual `pi Q`
+    /// vanishes
+    pub fn steady_state(&self) -> Vec<f64> {
+        let (closed, class_of) = self.closed_classes();
+        let absorbed = self.absorption(&closed, &class_of);
+        let mut distribution = vec![0.0; self.states.len()];
+        for (class, mass) in closed.iter().zip(absorbed) {
+            if mass == 0.0 {
+                continue;
+            }
+            for (i, p) in class.iter().zip(self.stationary(class)) {
+                distribution[*i] = mass * p;
+            }
+        }
+        distribution
+    }
+
+    /// probability of each state at `time` (uniformization), the time is cut
+    /// in slices short enough for the poisson weights not to underflow
+    pub fn transient(&self, time: f64) -> Vec<f64> {
+        let lambda = self.uniformization_rate();
+        let slices = (lambda * time / 100.0).ceil().max(1.0);
+        let mut distribution = self.initial.clone();
+        for _ in 0..slices as usize {
+            let rate = lambda * time / slices;
+            let mut weight = (-rate).exp();
+            let mut term = distribution.clone();
+            let mut sum = term.iter().map(|x| x * weight).collect::<Vec<_>>();
+            let mut cumulated = weight;
+            let mut k = 0.0;
+            while 1.0 - cumulated > EPSILON && k < MAX_ITERATIONS as f64 {
+                k += 1.0;
+                term = self.jump(&term, lambda);
+                weight *= rate / k;
+                cumulated += weight;
+                for (s, x) in sum.iter_mut().zip(&term) {
+                    *s += x * weight;
+                }
+            }
+            distribution = sum;
+        }
+        distribution
+    }
+}
+
+/// computes for every vanishing marking the probability of reaching each
+/// tangible state by firing immediate transitions
+fn resolve_vanishing(
+    input: &Input,
+    graph: &ReachabilityGraph,
+    vanishing: &[bool],
+    tangible: &[Option<usize>],
+) -> Result<Vec<Resolved>, ErrorTypes> {
+    // probability of each immediate move out of the vanishing markings
+    let moves = graph
+        .edges
+        .iter()
+        .enumerate()
+        .map(|(i, next)| {
+            if !vanishing[i] {
+                return vec![];
+            }
+            let weighted = next
+                .iter()
+                .filter_map(|(step, n)| match input.delay(step[0]) {
+                    Delay::Immediate(w) => Some((*n, w)),
+                    Delay::Exponential(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let total = weighted.iter().map(|(_, w)| w).sum::<f64>();
+            weighted.into_iter().map(|(n, w)| (n, w / total)).collect()
+        })
+        .collect::<Vec<Vec<_>>>();
+
+    let mut reached: Vec<HashMap<usize, f64>> = vec![HashMap::new(); graph.markings.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut change: f64 = 0.0;
+        for i in (0..graph.markings.len()).filter(|i| vanishing[*i]) {
+            let mut next: HashMap<usize, f64> = HashMap::new();
+            for (n, p) in &moves[i] {
+                match tangible[*n] {
+                    Some(to) => *next.entry(to).or_default() += p,
+                    None => {
+                        for (to, q) in &reached[*n] {
+                            *next.entry(*to).or_default() += p * q;
+                        }
+                    }
+                }
+            }
+            let before = reached[i].values().sum::<f64>();
+            change = change.max((next.values().sum::<f64>() - before).abs());
+            reached[i] = next;
+        }
+        if change < EPSILON {
+            break;
+        }
+    }
+
+    (0..graph.markings.len())
+        .map(|i| match tangible[i] {
+            Some(state) => Ok(Resolved::Tangible(state)),
+            None if (reached[i].values().sum::<f64>() - 1.0).abs() > 1e-6 => {
+                Err(ErrorTypes::BadStochasticNet {
+                    reason: "immediate transitions can fire forever".to_string(),
+                })
+            }
+            None => Ok(Resolved::Vanishing(reached[i].clone())),
+        })
+        .collect()
+}
+
+/// solution of the regular system `matrix x = rhs`, by Gaussian
+/// elimination with partial pivoting
+fn solve(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Vec<f64> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))
+            .unwrap();
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let (upper, lower) = matrix.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            if factor == 0.0 {
+                continue;
+            }
+            for (x, y) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * y;
+            }
+            rhs[col + 1 + offset] -= factor * rhs[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let known = matrix[row][row + 1..]
+            .iter()
+            .zip(&x[row + 1..])
+            .map(|(a, b)| a * b)
+            .sum::<f64>();
+        x[row] = (rhs[row] - known) / matrix[row][row];
+    }
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ctmc;
+    use crate::graph_gen::{Delay, Input};
+
+    /// a token moving back and forth between p0 (rate 1) and p1 (rate 3),
+    /// with an immediate choice on the way back
+    fn input() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(1), Some(0), Some(0)],
+            transitions: vec![
+                vec![(1, 0), (0, 1), (0, 0)],
+                vec![(0, 0), (1, 0), (0, 1)],
+                vec![(0, 1), (0, 0), (1, 0)],
+            ],
+            delays: vec![
+                Delay::Exponential(1.0),
+                Delay::Exponential(3.0),
+                Delay::Immediate(1.0),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_vanishing_markings_are_removed() {
+        let ctmc = Ctmc::build(&input(), &Default::default()).unwrap();
+        assert_eq!(ctmc.states.len(), 2);
+        assert_eq!(ctmc.rates, vec![vec![(1, 1.0)], vec![(0, 3.0)]]);
+    }
+
+    #[test]
+    fn test_steady_state_and_transient() {
+        let ctmc = Ctmc::build(&input(), &Default::default()).unwrap();
+        let steady = ctmc.steady_state();
+        assert!((steady[0] - 0.75).abs() < 1e-6);
+        assert!((steady[1] - 0.25).abs() < 1e-6);
+        // p(t) = 3/4 + 1/4 exp(-4t) for the initial state
+        let transient = ctmc.transient(0.5);
+        assert!((transient[0] - (0.75 + 0.25 * (-2.0f64).exp())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_slow_and_reducible_chains() {
+        // a slow exchange between 0 and 1 next to a fast one between 1 and 2
+        let slow = Ctmc {
+            states: vec![vec![]; 3],
+            initial: vec![1.0, 0.0, 0.0],
+            rates: vec![vec![(1, 1e-10)], vec![(0, 3e-10), (2, 1e3)], vec![(1, 1e3)]],
+        };
+        let steady = slow.steady_state();
+        for (p, expected) in steady.iter().zip([0.6, 0.2, 0.2]) {
+            assert!((p - expected).abs() < 1e-6);
+        }
+        // 0 ends in 1 or in 2, which it leaves three times faster for
+        let reducible = Ctmc {
+            states: vec![vec![]; 3],
+            initial: vec![1.0, 0.0, 0.0],
+            rates: vec![vec![(1, 1.0), (2, 3.0)], vec![], vec![]],
+        };
+        let steady = reducible.steady_state();
+        for (p, expected) in steady.iter().zip([0.0, 0.25, 0.75]) {
+            assert!((p - expected).abs() < 1e-9);
+        }
+    }
+}
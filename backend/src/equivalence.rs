@@ -0,0 +1,328 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::{
+    bisimulation::saturate,
+    lts::{Equivalence, Label, Lts},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// how the defender answers a move of the attacker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// by a move with the same label
+    Same,
+    /// by staying put, which answers a tau move in branching bisimulation
+    Stay,
+    /// it cannot, the systems are told apart
+    Unmatched,
+}
+
+/// a move of the attacker in one system and the answer of the other one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Round {
+    /// system the attacker moves in
+    pub side: Side,
+    pub label: Label,
+    pub answer: Answer,
+}
+
+/// rounds of a game telling two systems apart, only the last one is
+/// unmatched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub rounds: Vec<Round>,
+}
+
+impl Difference {
+    /// labels of the moves of the attacker
+    pub fn trace(&self) -> Vec<Label> {
+        self.rounds
+            .iter()
+            .map(|round| round.label.clone())
+            .collect()
+    }
+
+    /// system performing the unmatched move
+    pub fn side(&self) -> Side {
+        self.rounds.last().unwrap().side
+    }
+}
+
+/// shortest trace of visible labels of only one of the systems, `None` when
+/// they are trace equivalent, both systems are determinised on the fly; the
+/// attacker plays the whole trace in that system
+pub fn trace_difference(left: &Lts, right: &Lts) -> Option<Difference> {
+    let start = (
+        left.tau_closure([left.initial]),
+        right.tau_closure([right.initial]),
+    );
+    let mut pairs = vec![start.clone()];
+    let mut indices = HashMap::from([(start, 0)]);
+    let mut parents: Vec<Option<(usize, String)>> = vec![None];
+    let mut queue = VecDeque::from([0]);
+    while let Some(current) = queue.pop_front() {
+        let (states, others) = pairs[current].clone();
+        let labels = left
+            .visible_labels(&states)
+            .union(&right.visible_labels(&others))
+            .map(|label| label.to_string())
+            .collect::<Vec<_>>();
+        for label in labels {
+            let next = (left.after(&states, &label), right.after(&others, &label));
+            if next.0.is_empty() != next.1.is_empty() {
+                let mut trace = vec![Some(label)];
+                let mut pair = current;
+                while let Some((parent, label)) = &parents[pair] {
+                    trace.push(Some(label.clone()));
+                    pair = *parent;
+                }
+                trace.reverse();
+                let side = match next.1.is_empty() {
+                    true => Side::Left,
+                    false => Side::Right,
+                };
+                let last = trace.len() - 1;
+                let rounds = trace
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, label)| Round {
+                        side,
+                        label,
+                        answer: match i == last {
+                            true => Answer::Unmatched,
+                            false => Answer::Same,
+                        },
+                    })
+                    .collect();
+                return Some(Difference { rounds });
+            }
+            if !indices.contains_key(&next) {
+                indices.insert(next.clone(), pairs.len());
+                pairs.push(next);
+                parents.push(Some((current, label)));
+                queue.push_back(pairs.len() - 1);
+            }
+        }
+    }
+    None
+}
+
+/// steps of `s` as seen by the partition `classes`: its own steps, or for
+/// branching bisimulation the steps after tau steps inside its class except
+/// tau steps inside the class
+fn moves(lts: &Lts, classes: &[usize], s: usize, branching: bool) -> Vec<(Label, usize)> {
+    if !branching {
+        return lts.edges[s].clone();
+    }
+    let mut reached = vec![s];
+    let mut seen = BTreeSet::from([s]);
+    let mut moves = vec![];
+    let mut current = 0;
+    while current < reached.len() {
+        for (label, t) in &lts.edges[reached[current]] {
+            let inert = label.is_none() && classes[*t] == classes[s];
+            match inert {
+                true if seen.insert(*t) => reached.push(*t),
+                true => (),
+                false => moves.push((label.clone(), *t)),
+            }
+        }
+        current += 1;
+    }
+    moves
+}
+
+/// partitions of the signature refinement, the k-th one separating the
+/// states told apart in k steps, the last one is the bisimulation
+fn refinement_rounds(lts: &Lts, branching: bool) -> Vec<Vec<usize>> {
+    let n = lts.edges.len();
+    let mut rounds = vec![vec![0; n]];
+    loop {
+        let classes = rounds.last().unwrap();
+        let mut numbers = HashMap::new();
+        let next = (0..n)
+            .map(|s| {
+                let signature = moves(lts, classes, s, branching)
+                    .into_iter()
+                    .map(|(label, t)| (label, classes[t]))
+                    .collect::<BTreeSet<_>>();
+                let count = numbers.len();
+                *numbers.entry((classes[s], signature)).or_insert(count)
+            })
+            .collect::<Vec<_>>();
+        if numbers.len() == classes.iter().max().map_or(0, |c| c + 1) {
+            return rounds;
+        }
+        rounds.push(next);
+    }
+}
+
+/// `None` when the initial states of the systems are equivalent, otherwise
+/// the rounds of a game telling them apart: at each round the attacker moves
+/// on the side the other cannot match as well, the defender answering with
+/// the move keeping the states alike the longest, which in branching
+/// bisimulation may be to stay put on a tau move; for weak trace
+/// equivalence the shortest trace of only one of the systems
+pub fn bisimulation_difference(
+    left: &Lts,
+    right: &Lts,
+    equivalence: Equivalence,
+) -> Option<Difference> {
+    let union = left.union(right);
+    let (lts, branching) = match equivalence {
+        Equivalence::WeakTrace => return trace_difference(left, right),
+        Equivalence::Strong => (union, false),
+        Equivalence::Branching => (union, true),
+        Equivalence::Weak => (
+            Lts {
+                initial: union.initial,
+                edges: saturate(&union.edges),
+            },
+            false,
+        ),
+    };
+    let rounds = refinement_rounds(&lts, branching);
+    let separated = |s: usize, t: usize| rounds.iter().position(|c| c[s] != c[t]);
+
+    let (mut s, mut t) = (left.initial, left.edges.len() + right.initial);
+    let mut played = vec![];
+    while let Some(k) = separated(s, t) {
+        // alike before round k, so their signatures differ in round k - 1
+        let classes = &rounds[k - 1];
+        let signature = |state: usize| {
+            moves(&lts, classes, state, branching)
+                .into_iter()
+                .map(|(label, next)| (label, classes[next]))
+                .collect::<BTreeSet<_>>()
+        };
+        let (side, attacker, defender) = match signature(s).is_subset(&signature(t)) {
+            false => (Side::Left, s, t),
+            true => (Side::Right, t, s),
+        };
+        let others = signature(defender);
+        let (label, next) = moves(&lts, classes, attacker, branching)
+            .into_iter()
+            .find(|(label, next)| !others.contains(&(label.clone(), classes[*next])))
+            .unwrap();
+        let stay = (branching && label.is_none()).then_some((Answer::Stay, defender));
+        let Some((answer, reply)) = moves(&lts, classes, defender, branching)
+            .into_iter()
+            .filter(|(l, _)| *l == label)
+            .map(|(_, reply)| (Answer::Same, reply))
+            .chain(stay)
+            .max_by_key(|(_, reply)| separated(next, *reply))
+        else {
+            played.push(Round {
+                side,
+                label,
+                answer: Answer::Unmatched,
+            });
+            return Some(Difference { rounds: played });
+        };
+        played.push(Round {
+            side,
+            label,
+            answer,
+        });
+        (s, t) = match side {
+            Side::Left => (next, reply),
+            Side::Right => (reply, next),
+        };
+    }
+    // the states stay apart after every move, so only the initial ones can
+    // be equivalent
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bisimulation_difference, trace_difference, Answer, Round, Side};
+    use crate::lts::{Equivalence, Label, Lts};
+
+    fn lts(edges: &[&[(&str, usize)]]) -> Lts {
+        Lts {
+            initial: 0,
+            edges: edges
+                .iter()
+                .map(|next| {
+                    next.iter()
+                        .map(|(label, t)| ((*label != "tau").then(|| label.to_string()), *t))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    fn labels(trace: &[&str]) -> Vec<Label> {
+        trace.iter().map(|l| Some(l.to_string())).collect()
+    }
+
+    #[test]
+    fn test_trace_difference() {
+        let ab = lts(&[&[("a", 1)], &[("b", 2)], &[]]);
+        let ac = lts(&[&[("tau", 1)], &[("a", 2)], &[("c", 3)], &[]]);
+        let difference = trace_difference(&ab, &ac).unwrap();
+        assert_eq!(difference.trace(), labels(&["a", "b"]));
+        assert_eq!(difference.side(), Side::Left);
+        assert_eq!(trace_difference(&ab, &ab), None);
+    }
+
+    #[test]
+    fn test_bisimulation_difference() {
+        // a.(b + c) against a.b + a.c, trace equivalent only
+        let late = lts(&[&[("a", 1)], &[("b", 2), ("c", 2)], &[]]);
+        let early = lts(&[&[("a", 1), ("a", 2)], &[("b", 3)], &[("c", 3)], &[]]);
+        assert_eq!(trace_difference(&late, &early), None);
+        for equivalence in [Equivalence::Strong, Equivalence::Branching] {
+            let difference = bisimulation_difference(&late, &early, equivalence).unwrap();
+            let [first, last] = ["a", "b"].map(|label| Some(label.to_string()));
+            assert_eq!(
+                difference.rounds,
+                vec![
+                    Round {
+                        side: Side::Left,
+                        label: first,
+                        answer: Answer::Same
+                    },
+                    Round {
+                        side: Side::Left,
+                        label: last,
+                        answer: Answer::Unmatched
+                    }
+                ]
+            );
+        }
+        // an inert tau step only matters to strong bisimulation
+        let inert = lts(&[&[("a", 1)], &[("tau", 2)], &[("b", 3)], &[]]);
+        let direct = lts(&[&[("a", 1)], &[("b", 2)], &[]]);
+        assert!(bisimulation_difference(&inert, &direct, Equivalence::Strong).is_some());
+        assert_eq!(
+            bisimulation_difference(&inert, &direct, Equivalence::Branching),
+            None
+        );
+        // τ.a + b against a + b: the tau step is answered by staying put
+        let choice = lts(&[&[("tau", 1), ("b", 2)], &[("a", 2)], &[]]);
+        let both = lts(&[&[("a", 1), ("b", 1)], &[]]);
+        let difference = bisimulation_difference(&choice, &both, Equivalence::Branching).unwrap();
+        assert_eq!(
+            difference.rounds,
+            vec![
+                Round {
+                    side: Side::Left,
+                    label: None,
+                    answer: Answer::Stay
+                },
+                Round {
+                    side: Side::Right,
+                    label: Some("b".to_string()),
+                    answer: Answer::Unmatched
+                }
+            ]
+        );
+    }
+}
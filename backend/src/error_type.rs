@@ -5,6 +5,8 @@ use thiserror::Error;
 pub enum ErrorTypes {
     #[error("Transition vector size not matching number of places: expected {expected:?}")]
     TransitionSizeNotMatching { expected: usize },
+    #[error("Place names not matching number of places: expected {expected:?}")]
+    PlaceNamesNotMatching { expected: usize },
     #[error("Cannot assemble graph reason: {reason:?}")]
     CannotAssembleGraph { reason: String },
     #[error("Cannot generate graph as the transition {transition:?} will generate a graph of infinite nodes")]
@@ -13,4 +15,31 @@ pub enum ErrorTypes {
     BadTransition { reason: String },
     #[error("Repeating place")]
     BadPlace,
+    #[error("Places {first:?} and {second:?} are both written {alias:?} in the outputs")]
+    AliasClash {
+        first: String,
+        second: String,
+        alias: String,
+    },
+    #[error("Initial marking of place {place:?} exceeds its capacity")]
+    CapacityExceeded { place: String },
+    #[error("State space exceeds {limit:?} states, the net may be unbounded")]
+    StateLimitExceeded { limit: usize },
+    #[error("Cannot build the markov chain: {reason:?}")]
+    BadStochasticNet { reason: String },
+    #[error("{reason:?}")]
+    BadColour { reason: String },
+    #[error("{reason:?}")]
+    BadModule { reason: String },
+    #[error("Bad specification: {reason:?}")]
+    BadSpecification { reason: String },
+    #[error("The net is not safe: {reason:?}")]
+    NotSafe { reason: String },
+    #[error("Place {place:?} can hold more than {bound:?} tokens")]
+    NotBounded { place: String, bound: i32 },
+    #[error("{error} {origin}")]
+    InModule {
+        error: Box<ErrorTypes>,
+        origin: String,
+    },
 }
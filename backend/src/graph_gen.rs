@@ -1,17 +1,357 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// description of the input shape
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Input {
     pub m_names: Vec<String>,
     pub m_init: Vec<Option<i32>>,
     pub transitions: Vec<Vec<(i32, i32)>>,
+    /// names of the transitions, `t<indice>` is used when missing
+    #[serde(default)]
+    pub t_names: Vec<String>,
+    /// tokens a transition needs in each place without consuming them
+    #[serde(default)]
+    pub read_arcs: Vec<Vec<i32>>,
+    /// places emptied by a transition when it fires
+    #[serde(default)]
+    pub reset_arcs: Vec<Vec<bool>>,
+    /// maximum number of tokens of each place, `None` when unbounded
+    #[serde(default)]
+    pub capacities: Vec<Option<i32>>,
+    /// priority level of each transition, higher levels fire first
+    #[serde(default)]
+    pub priorities: Vec<i32>,
+    /// static firing interval of each transition, `None` as upper bound for
+    /// infinity, `[0, inf[` when missing
+    #[serde(default)]
+    pub intervals: Vec<(i32, Option<i32>)>,
+    /// stochastic delay of each transition, exponential of rate 1 when missing
+    #[serde(default)]
+    pub delays: Vec<Delay>,
+    /// transitions hidden from the observable behaviour, none when missing
+    #[serde(default)]
+    pub hidden: Vec<bool>,
+}
+
+/// how long a transition of a stochastic petri net waits before firing
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Delay {
+    /// exponentially distributed delay of the given rate
+    Exponential(f64),
+    /// fires without delay, the weight settles conflicts between immediate
+    /// transitions
+    Immediate(f64),
+}
+
+impl Input {
+    /// checks that every transition vector has one value per place
+    pub fn validate(&self) -> Result<(), ErrorTypes> {
+        let expected = self.m_init.len();
+        if self.m_names.len() != expected {
+            return Err(ErrorTypes::PlaceNamesNotMatching { expected });
+        }
+        if self.transitions.iter().any(|t| t.len() != expected)
+            || self.read_arcs.iter().any(|t| t.len() != expected)
+            || self.reset_arcs.iter().any(|t| t.len() != expected)
+        {
+            return Err(ErrorTypes::TransitionSizeNotMatching { expected });
+        }
+        if (!self.read_arcs.is_empty() && self.read_arcs.len() != self.transitions.len())
+            || (!self.reset_arcs.is_empty() && self.reset_arcs.len() != self.transitions.len())
+        {
+            return Err(ErrorTypes::BadTransition {
+                reason: "Read and reset arcs must be given for every transition".to_string(),
+            });
+        }
+        if !self.priorities.is_empty() && self.priorities.len() != self.transitions.len() {
+            return Err(ErrorTypes::BadTransition {
+                reason: "Priorities must be given for every transition".to_string(),
+            });
+        }
+        if !self.intervals.is_empty() && self.intervals.len() != self.transitions.len() {
+            return Err(ErrorTypes::BadTransition {
+                reason: "Firing intervals must be given for every transition".to_string(),
+            });
+        }
+        if let Some(t) = (0..self.intervals.len()).find(|t| {
+            let (lower, upper) = self.interval(*t);
+            lower < 0 || upper.is_some_and(|upper| upper < lower)
+        }) {
+            return Err(ErrorTypes::BadTransition {
+                reason: format!(
+                    "Empty firing interval for transition {}",
+                    self.transition_name(t)
+                ),
+            });
+        }
+        if !self.delays.is_empty() && self.delays.len() != self.transitions.len() {
+            return Err(ErrorTypes::BadTransition {
+                reason: "Delays must be given for every transition".to_string(),
+            });
+        }
+        if let Some(t) = self.delays.iter().position(|delay| match delay {
+            Delay::Exponential(x) | Delay::Immediate(x) => *x <= 0.0,
+        }) {
+            return Err(ErrorTypes::BadTransition {
+                reason: format!(
+                    "Non positive rate for transition {}",
+                    self.transition_name(t)
+                ),
+            });
+        }
+        if !self.hidden.is_empty() && self.hidden.len() != self.transitions.len() {
+            return Err(ErrorTypes::BadTransition {
+                reason: "Hidden transitions must be given for every transition".to_string(),
+            });
+        }
+        if !self.capacities.is_empty() && self.capacities.len() != expected {
+            return Err(ErrorTypes::TransitionSizeNotMatching { expected });
+        }
+        for (p, tokens) in self.m_init.iter().enumerate() {
+            if let (Some(tokens), Some(capacity)) = (tokens, self.capacity(p)) {
+                if *tokens > capacity {
+                    return Err(ErrorTypes::CapacityExceeded {
+                        place: self.m_names[p].clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn transition_name(&self, t: usize) -> String {
+        match self.t_names.get(t) {
+            Some(name) => name.clone(),
+            None => format!("t{}", t),
+        }
+    }
+
+    /// tokens transition `t` reads from place `p`
+    pub fn read_arc(&self, t: usize, p: usize) -> i32 {
+        self.read_arcs.get(t).map_or(0, |reads| reads[p])
+    }
+
+    /// whether transition `t` empties place `p`
+    pub fn reset_arc(&self, t: usize, p: usize) -> bool {
+        self.reset_arcs.get(t).is_some_and(|resets| resets[p])
+    }
+
+    pub fn capacity(&self, p: usize) -> Option<i32> {
+        self.capacities.get(p).copied().flatten()
+    }
+
+    pub fn priority(&self, t: usize) -> i32 {
+        self.priorities.get(t).copied().unwrap_or(0)
+    }
+
+    pub fn interval(&self, t: usize) -> (i32, Option<i32>) {
+        self.intervals.get(t).copied().unwrap_or((0, None))
+    }
+
+    pub fn delay(&self, t: usize) -> Delay {
+        self.delays
+            .get(t)
+            .copied()
+            .unwrap_or(Delay::Exponential(1.0))
+    }
+
+    /// whether transition `t` is internal (tau)
+    pub fn is_hidden(&self, t: usize) -> bool {
+        self.hidden.get(t).copied().unwrap_or(false)
+    }
+
+    /// name of a step, the multiset of its transitions names for real steps
+    pub fn step_name(&self, step: &[usize]) -> String {
+        match step {
+            [t] => self.transition_name(*t),
+            _ => format!(
+                "{{{}}}",
+                step.iter()
+                    .map(|t| self.transition_name(*t))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+}
+
+/// how place capacities restrict the firing of a transition
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityRule {
+    /// the produced tokens must fit before the consumed ones leave:
+    /// `M(p) + post(p) <= K(p)`
+    #[default]
+    Strict,
+    /// only the marking reached after firing must respect the capacities:
+    /// `M(p) - pre(p) + post(p) <= K(p)`
+    Weak,
+}
+
+impl FromStr for CapacityRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(CapacityRule::Strict),
+            "weak" => Ok(CapacityRule::Weak),
+            _ => Err(format!(
+                "unknown capacity rule {}, expected strict or weak",
+                s
+            )),
+        }
+    }
+}
+
+/// which sets of transitions may fire together from a marking
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FiringSemantics {
+    /// one transition at a time
+    #[default]
+    Interleaving,
+    /// any multiset of transitions that are enabled together
+    Step,
+    /// only the steps no other enabled transition can be added to
+    MaximalStep,
+}
+
+impl FromStr for FiringSemantics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interleaving" => Ok(FiringSemantics::Interleaving),
+            "step" => Ok(FiringSemantics::Step),
+            "maximal-step" => Ok(FiringSemantics::MaximalStep),
+            _ => Err(format!(
+                "unknown semantics {}, expected interleaving, step or maximal-step",
+                s
+            )),
+        }
+    }
+}
+
+/// partial order reduction of the interleavings explored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    #[default]
+    None,
+    /// fires a stubborn set of each marking, keeping the deadlocks
+    Deadlocks,
+    /// also keeps the next-free LTL properties of the visible transitions:
+    /// a reduced set only holds internal transitions and a marking leading
+    /// back to a visited one is fully expanded
+    Ltl,
+}
+
+impl FromStr for Reduction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Reduction::None),
+            "deadlocks" => Ok(Reduction::Deadlocks),
+            "ltl" => Ok(Reduction::Ltl),
+            _ => Err(format!(
+                "unknown reduction {}, expected none, deadlocks or ltl",
+                s
+            )),
+        }
+    }
+}
+
+/// what the partial order and symmetry reductions left out and the memory
+/// the markings took
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExplorationStats {
+    /// states where some enabled transitions were not fired
+    pub reduced_states: usize,
+    /// enabled firings that were not explored
+    pub pruned_firings: usize,
+    /// automorphisms the markings were reduced by, 1 without symmetry,
+    /// `None` when too many to count
+    pub group_size: Option<usize>,
+    /// markings symmetric to the explored ones, `None` when too many to
+    /// count or when the group is too big for the explored markings to be
+    /// one per orbit
+    pub represented_states: Option<usize>,
+    pub memory: StoreStats,
+}
+
+/// settings of the state space exploration
+#[derive(Debug, Default, Clone)]
+pub struct ExplorationOptions {
+    pub capacity_rule: CapacityRule,
+    pub semantics: FiringSemantics,
+    /// builds the state class graph of the time petri net instead of the
+    /// marking graph
+    pub timed: bool,
+    /// merges the strongly bisimilar states of the graph before generating
+    /// the outputs
+    pub minimise: bool,
+    /// also writes the firing language as a regular expression
+    pub regex: bool,
+    /// only applies to the interleaving semantics of nets without
+    /// priorities
+    pub reduction: Reduction,
+    /// explores one marking out of each orbit of the automorphisms of the
+    /// net, meant for bounded nets
+    pub symmetry: bool,
+    /// threads exploring the marking graph, it is explored sequentially
+    /// below two
+    pub jobs: usize,
 }
 
 use iter_tools::Itertools;
-use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::{
+    bisimulation::Quotient,
+    error_type::ErrorTypes,
+    ilp::{solve_linear, LinearProgram, LpResult},
+    output_generators::{generate_state_map, vector_to_string, Output},
+    parallel::{explore_parallel, sequential_reason},
+    query::Relation,
+    regex::firing_language,
+    state_class::build_state_class_graph,
+    store::{MarkingStore, StoreStats},
+    stubborn::StubbornSets,
+    symmetry::Symmetries,
+};
+
+/// a marking of the net, `None` stands for an unbounded number of tokens
+pub type Marking = Vec<Option<i32>>;
 
-use crate::output_generators::Output;
+/// transitions fired together, sorted, a transition appears once per firing
+pub type Step = Vec<usize>;
+
+/// the explored state space with numbered states, several states can share
+/// the same marking (the state classes of a time petri net for instance)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReachabilityGraph {
+    pub initial: usize,
+    pub markings: Vec<Marking>,
+    pub edges: Vec<Vec<(Step, usize)>>,
+}
+
+impl ReachabilityGraph {
+    /// names of the states made of their markings, states sharing a marking
+    /// get their index appended
+    pub fn state_names(&self, sep: &str) -> Vec<String> {
+        let counts = self.markings.iter().counts();
+        self.markings
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                if counts[m] > 1 {
+                    format!("{}{}c{}", vector_to_string(m, sep), sep, i)
+                } else {
+                    vector_to_string(m, sep)
+                }
+            })
+            .collect()
+    }
+}
 
 /// internal representation of a place node in a petri network
 #[derive(Debug, Clone)]
@@ -23,26 +363,51 @@ pub struct Place {
 }
 
 impl Place {
-    pub fn build(
-        m_names: &[String],
-        m_init: &[Option<i32>],
-        marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    ) -> Vec<Self> {
-        let places = m_init
+    /// computes the range of every place, places with a capacity get the
+    /// exact `0..capacity` range, the others the bounds seen in the graph;
+    /// fails when two places get the same alias
+    pub fn build(input: &Input, graph: &ReachabilityGraph) -> Result<Vec<Self>, ErrorTypes> {
+        let places = input
+            .m_init
             .iter()
-            .zip(m_names)
+            .zip(&input.m_names)
             .enumerate()
             .map(|(i, (v, s))| Place {
-                alias: s.clone(),
+                // unfolded coloured places are named like `buf[1,2]` and
+                // flattened or composed ones like `b1.slot`, which are not
+                // valid smv identifiers
+                alias: s.replace(['[', ',', '.'], "_").replace(']', ""),
                 indice: i,
                 max: v.unwrap(),
                 min: v.unwrap(),
             })
             .collect::<Vec<_>>();
+        for (i, place) in places.iter().enumerate() {
+            if let Some(other) = places[..i].iter().find(|p| p.alias == place.alias) {
+                return Err(ErrorTypes::AliasClash {
+                    first: input.m_names[other.indice].clone(),
+                    second: input.m_names[i].clone(),
+                    alias: place.alias.clone(),
+                });
+            }
+        }
 
-        marking_graph.keys().fold(places, |ps, k| {
-            ps.iter().zip(k).map(|(p, x)| p.update(*x)).collect()
-        })
+        Ok(graph
+            .markings
+            .iter()
+            .fold(places, |ps, k| {
+                ps.iter().zip(k).map(|(p, x)| p.update(*x)).collect()
+            })
+            .into_iter()
+            .map(|p| match input.capacity(p.indice) {
+                Some(capacity) => Place {
+                    min: 0,
+                    max: capacity,
+                    ..p
+                },
+                None => p,
+            })
+            .collect())
     }
     /// updates the k border values (min and max) of the node
     pub fn update(&self, v: Option<i32>) -> Self {
@@ -52,7 +417,7 @@ impl Place {
                 indice: self.indice,
                 min: self.min,
                 alias: self.alias.to_string(),
-            }
+            };
         };
         if v > self.max {
             Self {
@@ -74,165 +439,810 @@ impl Place {
     }
 }
 
-/// recursive function that get's the parents of a certain node
-/// ## Inputs
-///
-/// ` m: &'a Vec<Option<i32>>` :the marquage to fetch the parents of
-///
-///  `marquage_graph: &'a HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>` :current state of the marquage_graph
-///
-/// `acc: &mut Vec<&'a Vec<Option<i32>>>` : accumulates the parents and return it
-///
-/// ## Returns
+/// the steps fired in `marking` with the markings they reach, only the ones
+/// of a stubborn set when reducing, and the number of firings left out;
+/// `revisits` tells whether a reached marking may close a cycle, in which
+/// case the LTL proviso fires every step
+pub(crate) fn fire_enabled(
+    input: &Input,
+    options: &ExplorationOptions,
+    stubborn: Option<&StubbornSets>,
+    marking: &[Option<i32>],
+    revisits: impl Fn(&Marking) -> bool,
+) -> (Vec<(Step, Marking)>, usize) {
+    let fire = |steps: Vec<Step>| {
+        steps
+            .into_iter()
+            .filter_map(|step| {
+                fire_step(input, &step, marking, options.capacity_rule).map(|n| (step, n))
+            })
+            .collect::<Vec<_>>()
+    };
+    let all_steps = steps(input, marking, options);
+    match stubborn {
+        Some(stubborn) if all_steps.len() > 1 => {
+            let enabled = all_steps.iter().map(|step| step[0]).collect::<Vec<_>>();
+            let reduced = stubborn.reduce(input, marking, &enabled, options.capacity_rule, |set| {
+                options.reduction != Reduction::Ltl
+                    || set.len() == enabled.len()
+                    || set.iter().all(|t| input.is_hidden(*t))
+            });
+            let mut next_ms = fire(reduced.iter().map(|t| vec![*t]).collect());
+            if options.reduction == Reduction::Ltl && next_ms.iter().any(|(_, n)| revisits(n)) {
+                next_ms = fire(all_steps.clone());
+            }
+            let pruned = all_steps.len().saturating_sub(next_ms.len());
+            (next_ms, pruned)
+        }
+        _ => (fire(all_steps), 0),
+    }
+}
+
+/// state of the exploration: the reached markings packed in a store and the
+/// edges between their numbers
+struct Explorer<'a> {
+    input: &'a Input,
+    options: &'a ExplorationOptions,
+    /// restricts the fired transitions when reducing
+    stubborn: Option<&'a StubbornSets>,
+    /// replaces the reached markings by their canonical representatives
+    symmetries: Option<&'a Symmetries>,
+    store: MarkingStore,
+    /// successors of each marking, `None` until it is expanded
+    successors: Vec<Option<Vec<(Step, usize)>>>,
+    /// expanded markings with an edge to each marking
+    predecessors: Vec<Vec<usize>>,
+    /// whether a marking may cover an ancestor, the ancestors are only
+    /// walked to accelerate when it may
+    accelerates: bool,
+    /// number of the last walk through each marking, to tell the markings
+    /// seen by the current walk without clearing anything
+    visits: Vec<usize>,
+    walks: usize,
+    stats: ExplorationStats,
+}
+
+impl<'a> Explorer<'a> {
+    fn insert(&mut self, marking: &[Option<i32>]) -> usize {
+        let (id, new) = self.store.insert(marking);
+        if new {
+            self.successors.push(None);
+            self.predecessors.push(vec![]);
+            self.visits.push(0);
+        }
+        id
+    }
+
+    fn is_expanded(&self, marking: &[Option<i32>]) -> bool {
+        self.store
+            .get(marking)
+            .is_some_and(|id| self.successors[id].is_some())
+    }
+
+    /// the markings other than `target` with a path to it, a marking not
+    /// stored yet is only reached from `from`
+    fn ancestors(&mut self, target: Option<usize>, from: usize) -> Vec<usize> {
+        if !self.accelerates {
+            return vec![];
+        }
+        self.walks += 1;
+        let walk = self.walks;
+        let mut stack = match target {
+            Some(target) => {
+                self.visits[target] = walk;
+                self.predecessors[target].clone()
+            }
+            None => vec![from],
+        };
+        let mut ancestors = vec![];
+        while let Some(p) = stack.pop() {
+            if self.visits[p] == walk {
+                continue;
+            }
+            self.visits[p] = walk;
+            ancestors.push(p);
+            let visits = &self.visits;
+            stack.extend(self.predecessors[p].iter().filter(|q| visits[**q] != walk));
+        }
+        ancestors
+    }
+
+    /// `n` with an unbounded number of tokens in the places without
+    /// capacity where it holds more tokens than an ancestor it covers
+    fn accelerate(&self, n: &[Option<i32>], ancestors: &[usize]) -> Marking {
+        let mut accelerated = n.to_vec();
+        for p in ancestors {
+            let covered = n.iter().enumerate().all(|(i, xn)| {
+                xn.is_none_or(|xn| self.store.value(*p, i).is_none_or(|xp| xn - xp >= 0))
+            });
+            if !covered {
+                continue;
+            }
+            for (i, xn) in n.iter().enumerate() {
+                if let (Some(xn), Some(xp)) = (xn, self.store.value(*p, i)) {
+                    if xn - xp > 0 && self.input.capacity(i).is_none() {
+                        accelerated[i] = None;
+                    }
+                }
+            }
+        }
+        accelerated
+    }
+
+    /// fires the steps of marking `m`, or the ones of a stubborn set, and
+    /// stores the reached markings once accelerated
+    fn expand(&mut self, m: usize) {
+        let (input, options) = (self.input, self.options);
+        let marking = self.store.marking(m);
+        let (next_ms, pruned) = fire_enabled(input, options, self.stubborn, &marking, |n| {
+            *n == marking || self.is_expanded(n)
+        });
+        if pruned > 0 {
+            self.stats.reduced_states += 1;
+            self.stats.pruned_firings += pruned;
+        }
+
+        // the edges of `m` count for the ancestors while accelerating
+        let stored = next_ms
+            .iter()
+            .map(|(_, n)| self.store.get(n))
+            .collect::<Vec<_>>();
+        for n in stored.iter().flatten() {
+            self.predecessors[*n].push(m);
+        }
+        let mut accelerated = vec![];
+        for ((step, n), id) in next_ms.iter().zip(&stored) {
+            let ancestors = self.ancestors(*id, m);
+            accelerated.push((step.clone(), self.accelerate(n, &ancestors)));
+        }
+        let accelerated = accelerated
+            .into_iter()
+            .map(|(step, n)| match self.symmetries {
+                Some(symmetries) => (step, symmetries.canonical(&n)),
+                None => (step, n),
+            })
+            .unique()
+            .collect::<Vec<_>>();
+        for n in stored.iter().flatten() {
+            self.predecessors[*n].pop();
+        }
+
+        let successors = accelerated
+            .into_iter()
+            .map(|(step, n)| {
+                let n = self.insert(&n);
+                self.predecessors[n].push(m);
+                (step, n)
+            })
+            .collect();
+        self.successors[m] = Some(successors);
+    }
+
+    /// expands every marking reached from `initial`, depth first
+    fn run(&mut self, initial: usize) {
+        self.expand(initial);
+        let mut stack = vec![(initial, 0)];
+        while let Some((m, position)) = stack.last_mut() {
+            let next = self.successors[*m].as_ref().unwrap().get(*position);
+            let Some((_, n)) = next.cloned() else {
+                stack.pop();
+                continue;
+            };
+            *position += 1;
+            if self.successors[n].is_none() {
+                self.expand(n);
+                stack.push((n, 0));
+            }
+        }
+    }
+
+    /// numbers the markings in breadth first order from `initial`
+    fn graph(&self, initial: usize) -> ReachabilityGraph {
+        let mut indices = vec![None; self.store.len()];
+        indices[initial] = Some(0);
+        let mut order = vec![initial];
+        let mut edges = vec![];
+        let mut current = 0;
+        while current < order.len() {
+            let next = self.successors[order[current]]
+                .iter()
+                .flatten()
+                .map(|(step, n)| {
+                    let index = *indices[*n].get_or_insert_with(|| {
+                        order.push(*n);
+                        order.len() - 1
+                    });
+                    (step.clone(), index)
+                })
+                .collect::<Vec<_>>();
+            edges.push(next);
+            current += 1;
+        }
+        ReachabilityGraph {
+            initial: 0,
+            markings: order.iter().map(|m| self.store.marking(*m)).collect(),
+            edges,
+        }
+    }
+}
+
+/// why a place prevents a transition from firing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shortage {
+    /// the place holds fewer tokens than the transition consumes or reads
+    Tokens {
+        place: usize,
+        required: i32,
+        available: i32,
+    },
+    /// the tokens produced in the place would exceed its capacity
+    Capacity {
+        place: usize,
+        capacity: i32,
+        reached: i32,
+    },
+}
+
+/// the places keeping transition `t` from firing in `marking`: the ones
+/// holding fewer tokens than the transition consumes or reads, and the ones
+/// the produced tokens overflow according to `rule`
+pub fn shortages<'a>(
+    input: &'a Input,
+    t: usize,
+    marking: &'a [Option<i32>],
+    rule: CapacityRule,
+) -> impl Iterator<Item = Shortage> + 'a {
+    input.transitions[t]
+        .iter()
+        .zip(marking)
+        .enumerate()
+        .filter_map(|(p, (arc, tokens))| tokens.map(|tokens| (p, arc, tokens)))
+        .flat_map(move |(p, (pre, post), tokens)| {
+            let required = (*pre).max(input.read_arc(t, p));
+            let kept = match (input.reset_arc(t, p), rule) {
+                (true, CapacityRule::Weak) => 0,
+                (_, CapacityRule::Strict) => tokens,
+                (false, CapacityRule::Weak) => tokens - pre,
+            };
+            let lacking = (tokens < required).then_some(Shortage::Tokens {
+                place: p,
+                required,
+                available: tokens,
+            });
+            let overflow = input
+                .capacity(p)
+                .filter(|capacity| *post > 0 && kept + post > *capacity)
+                .map(|capacity| Shortage::Capacity {
+                    place: p,
+                    capacity,
+                    reached: kept + post,
+                });
+            lacking.into_iter().chain(overflow)
+        })
+}
+
+/// whether transition `t` can fire in `marking`, no place keeps it from
+/// firing
+pub fn is_enabled(input: &Input, t: usize, marking: &[Option<i32>], rule: CapacityRule) -> bool {
+    shortages(input, t, marking, rule).next().is_none()
+}
+
+/// Does the addition between a transition and a marquage
 ///
-/// a `Vec<&'a Vec<Option<i32>>>` the parents of the node
-fn get_parents_of_marking<'a>(
-    m: &'a Vec<Option<i32>>,
-    marquage_graph: &'a HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    acc: &mut Vec<&'a Vec<Option<i32>>>,
-) -> Vec<&'a Vec<Option<i32>>> {
-    let mut parents: Vec<&Vec<Option<i32>>> = vec![];
-    acc.push(&m);
-    let keys = marquage_graph
+/// reset places are emptied before the produced tokens are added,
+/// returns `None` when the transition is not enabled
+pub fn activate_transition(
+    input: &Input,
+    t: usize,
+    marking: &[Option<i32>],
+    rule: CapacityRule,
+) -> Option<Marking> {
+    if !is_enabled(input, t, marking, rule) {
+        return None;
+    }
+    let next = input.transitions[t]
         .iter()
-        .filter(|(_, value)| value.iter().map(|(_, v)| v).contains(&m))
-        .map(|(key, _)| key)
-        .filter(|key| !acc.contains(key))
+        .zip(marking)
+        .enumerate()
+        .map(|(p, ((x1, x2), y))| {
+            if input.reset_arc(t, p) {
+                Some(*x2)
+            } else {
+                y.map(|y| y + x2 - x1)
+            }
+        })
+        .collect::<Vec<_>>();
+    next.iter().all(|x| x.unwrap_or(0) >= 0).then_some(next)
+}
+
+/// the transitions enabled in `marking` that are not blocked by an enabled
+/// transition of a higher priority level
+pub fn enabled_transitions(
+    input: &Input,
+    marking: &[Option<i32>],
+    rule: CapacityRule,
+) -> Vec<usize> {
+    let enabled = (0..input.transitions.len())
+        .filter(|t| activate_transition(input, *t, marking, rule).is_some())
         .collect::<Vec<_>>();
-    parents.append(&mut keys.clone());
-    for k in keys {
-        let mut local_parents = get_parents_of_marking(k, marquage_graph, acc);
-        parents.append(&mut local_parents);
+    match enabled.iter().map(|t| input.priority(*t)).max() {
+        Some(top) => enabled
+            .into_iter()
+            .filter(|t| input.priority(*t) == top)
+            .collect(),
+        None => enabled,
     }
-    parents
 }
 
-/// main function
+/// Fires all the transitions of `step` at once
 ///
-/// ## Usage
-/// Generates the graph recursively by mutating a `HashMap`
-/// ## Inputs
-/// `m: Vec<Option<i32>>` : the initial marquage
-///
-/// `transitions: &Vec<Vec<(i32, i32)>>` : the vector of transitions
-///
-/// ## Returns
-/// `()`: use mutation of the `marquage_graph` to store its value   
-fn generate_graph(
-    m: Vec<Option<i32>>,
-    transitions: &Vec<Vec<(i32, i32)>>,
-    marquage_graph: &mut HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-) {
-    let mut next_ms = transitions
+/// the marking must hold the tokens consumed by the whole step plus the
+/// largest read of each place, a place reset by one of the transitions only
+/// keeps the tokens the step produces in it,
+/// returns `None` when the step is not enabled
+pub fn fire_step(
+    input: &Input,
+    step: &[usize],
+    marking: &[Option<i32>],
+    rule: CapacityRule,
+) -> Option<Marking> {
+    if let [t] = step {
+        return activate_transition(input, *t, marking, rule);
+    }
+    marking
         .iter()
-        .map(|t| {
-            (
-                t.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
-                activate_transition(t, &m),
-            )
+        .enumerate()
+        .map(|(p, y)| {
+            let pre = step.iter().map(|t| input.transitions[*t][p].0).sum::<i32>();
+            let post = step.iter().map(|t| input.transitions[*t][p].1).sum::<i32>();
+            let read = step
+                .iter()
+                .map(|t| input.read_arc(*t, p))
+                .max()
+                .unwrap_or(0);
+            let reset = step.iter().any(|t| input.reset_arc(*t, p));
+            let Some(y) = y else {
+                return Some(if reset { Some(post) } else { None });
+            };
+            let next = if reset { post } else { y - pre + post };
+            let fits = match (input.capacity(p), rule) {
+                (None, _) => true,
+                (Some(_), _) if post == 0 => true,
+                (Some(k), CapacityRule::Strict) => y + post <= k,
+                (Some(k), CapacityRule::Weak) => next <= k,
+            };
+            (*y >= pre + read && next >= 0 && fits).then_some(Some(next))
         })
-        .filter(|(_, m)| {
-            m.iter().all(|x| match x {
-                Some(x) => *x >= 0,
-                None => true,
-            })
-        })
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+/// whether `t` may fire several times in the same step: only transitions
+/// consuming from a bounded place, others would give infinitely many steps
+fn auto_concurrent(input: &Input, t: usize, marking: &[Option<i32>]) -> bool {
+    input.transitions[t]
+        .iter()
+        .zip(marking)
+        .any(|((x1, _), y)| *x1 > 0 && y.is_some())
+}
+
+/// the steps that can fire from `marking` under the chosen semantics
+pub fn steps(input: &Input, marking: &[Option<i32>], options: &ExplorationOptions) -> Vec<Step> {
+    let enabled = enabled_transitions(input, marking, options.capacity_rule);
+    if options.semantics == FiringSemantics::Interleaving {
+        return enabled.into_iter().map(|t| vec![t]).collect();
+    }
 
-    marquage_graph.insert(m.clone(), next_ms.clone());
+    // extends `step` with transitions of `enabled` from `from` onwards
+    fn extend(
+        input: &Input,
+        marking: &[Option<i32>],
+        rule: CapacityRule,
+        enabled: &[usize],
+        from: usize,
+        step: &mut Step,
+        acc: &mut Vec<Step>,
+    ) {
+        for (i, t) in enabled.iter().enumerate().skip(from) {
+            if step.contains(t) && !auto_concurrent(input, *t, marking) {
+                continue;
+            }
+            step.push(*t);
+            if fire_step(input, step, marking, rule).is_some() {
+                acc.push(step.clone());
+                extend(input, marking, rule, enabled, i, step, acc);
+            }
+            step.pop();
+        }
+    }
 
-    let binding = next_ms.clone();
-    let parents = binding
+    let mut all_steps = vec![];
+    extend(
+        input,
+        marking,
+        options.capacity_rule,
+        &enabled,
+        0,
+        &mut vec![],
+        &mut all_steps,
+    );
+    if options.semantics == FiringSemantics::Step {
+        return all_steps;
+    }
+    all_steps
         .iter()
-        .map(|(t, n)| {
-            (
-                (t, n),
-                get_parents_of_marking(n, marquage_graph, &mut vec![]),
-            )
+        .filter(|step| {
+            !enabled.iter().any(|t| {
+                if step.contains(t) && !auto_concurrent(input, *t, marking) {
+                    return false;
+                }
+                let mut bigger = step.to_vec();
+                bigger.push(*t);
+                bigger.sort();
+                fire_step(input, &bigger, marking, options.capacity_rule).is_some()
+            })
         })
-        .collect::<Vec<_>>();
+        .cloned()
+        .collect()
+}
 
-    next_ms = parents
-        .into_iter()
-        .map(|((t, n), ps)| {
-            ps.into_iter()
-                .map(|p| {
-                    if p.iter()
-                        .zip(n)
-                        .all(|(xp, xn)| xn.map_or(true, |xn| xp.map_or(true, |xp| xn - xp >= 0)))
-                    {
-                        (
-                            t.to_vec(),
-                            n.iter()
-                                .zip(p)
-                                .map(|(xn, xp)| match (xn, xp) {
-                                    (Some(xn), Some(xp)) => {
-                                        if xn - xp > 0 {
-                                            None
-                                        } else {
-                                            Some(*xn)
-                                        }
-                                    }
-                                    (xn, _) => *xn,
-                                })
-                                .collect::<Vec<_>>(),
-                        )
-                    } else {
-                        (t.to_vec(), n.to_vec())
-                    }
-                })
+/// whether a reached marking may strictly cover one it is reached from in
+/// a place without capacity; it cannot when the initial marking is finite,
+/// no arc resets and a weighting of the places, positive on the ones without
+/// capacity, is kept by every transition
+fn may_cover_ancestor(input: &Input) -> bool {
+    if input.m_init.iter().any(Option::is_none) || input.reset_arcs.iter().flatten().any(|r| *r) {
+        return true;
+    }
+    let places = input.m_names.len();
+    let effects = input
+        .transitions
+        .iter()
+        .map(|arcs| {
+            arcs.iter()
+                .map(|(pre, post)| f64::from(post - pre))
                 .collect::<Vec<_>>()
         })
-        .map(|ns| {
-            ns.into_iter().reduce(|(t, a), (_, n)| {
-                (
-                    t,
-                    a.into_iter()
-                        .zip(n)
-                        .map(|(xa, xn)| match (xa, xn) {
-                            (None, _) | (_, None) => None,
-                            (_, xn) => xn,
-                        })
-                        .collect_vec(),
-                )
-            })
-        })
-        .flatten()
-        .unique()
         .collect::<Vec<_>>();
+    let uncapacitated = (0..places)
+        .filter(|p| input.capacity(*p).is_none())
+        .collect::<Vec<_>>();
+    let mut rows = effects
+        .iter()
+        .map(|effect| (effect.clone(), Relation::Equal, 0.0))
+        .collect::<Vec<_>>();
+    rows.extend(uncapacitated.iter().map(|p| {
+        let unit = (0..places).map(|q| f64::from(u8::from(q == *p))).collect();
+        (unit, Relation::AtLeast, 1.0)
+    }));
+    let program = LinearProgram {
+        objective: vec![0.0; places],
+        rows,
+    };
+    // the weighting is checked again against the rounding of the solver
+    match solve_linear(&program) {
+        LpResult::Optimal(y) => {
+            let kept = effects.iter().all(|effect| {
+                let change: f64 = effect.iter().zip(&y).map(|(e, w)| e * w).sum();
+                change.abs() < 1e-6
+            });
+            !(kept && uncapacitated.iter().all(|p| y[*p] > 0.5))
+        }
+        _ => true,
+    }
+}
 
-    marquage_graph.insert(m, next_ms.clone());
+/// builds the marking graph of `input` from its initial marking
+pub fn explore(input: &Input, options: &ExplorationOptions) -> ReachabilityGraph {
+    explore_reduced(input, options).0
+}
 
-    for (_, mi) in next_ms {
-        match marquage_graph.get(&mi) {
-            Some(_) => (),
-            None => generate_graph(mi, transitions, marquage_graph),
+/// the marking graph with what the partial order and symmetry reductions
+/// left out and the memory taken by its markings
+pub fn explore_reduced(
+    input: &Input,
+    options: &ExplorationOptions,
+) -> (ReachabilityGraph, ExplorationStats) {
+    let uniform_priorities = (0..input.transitions.len())
+        .map(|t| input.priority(t))
+        .all_equal();
+    let stubborn = (options.reduction != Reduction::None
+        && options.semantics == FiringSemantics::Interleaving
+        && uniform_priorities)
+        .then(|| StubbornSets::new(input));
+    let symmetries = options.symmetry.then(|| Symmetries::find(input));
+    let group_size = symmetries.as_ref().map_or(Some(1), |s| s.group_size);
+    let parallel = (options.jobs > 1 && sequential_reason(input, options).is_none())
+        .then(|| explore_parallel(input, options, symmetries.as_ref()))
+        .flatten();
+    let (graph, mut stats) = match parallel {
+        Some((graph, memory)) => (
+            graph,
+            ExplorationStats {
+                group_size,
+                memory,
+                ..Default::default()
+            },
+        ),
+        None => {
+            // CONSTRUCTION DU GRAPH DES MARQUAGES
+            let mut explorer = Explorer {
+                input,
+                options,
+                stubborn: stubborn.as_ref(),
+                symmetries: symmetries.as_ref(),
+                store: MarkingStore::new(input.m_names.len()),
+                successors: vec![],
+                predecessors: vec![],
+                accelerates: may_cover_ancestor(input),
+                visits: vec![],
+                walks: 0,
+                stats: ExplorationStats {
+                    group_size,
+                    ..Default::default()
+                },
+            };
+            let initial = explorer.insert(&input.m_init);
+            explorer.run(initial);
+            let graph = explorer.graph(initial);
+            let mut stats = explorer.stats;
+            stats.memory = explorer.store.stats();
+            (graph, stats)
         }
-    }
+    };
+    stats.represented_states = match &symmetries {
+        // two markings of an orbit may both have been explored
+        Some(symmetries) if !symmetries.is_canonical() => None,
+        Some(symmetries) => graph.markings.iter().try_fold(0usize, |total, m| {
+            total.checked_add(symmetries.orbit_size(input, m)?)
+        }),
+        None => Some(graph.markings.len()),
+    };
+    (graph, stats)
 }
 
-/// Does the addition between a transition and a marquage
-fn activate_transition(transition: &[(i32, i32)], marking: &[Option<i32>]) -> Vec<Option<i32>> {
-    transition
-        .iter()
-        .zip(marking)
-        .map(|((x1, x2), y)| y.map(|y| if y - x1 >= 0 { y + x2 - x1 } else { -1 }))
-        .collect()
+/// the marking graph, or the state class graph for timed explorations
+pub fn build_graph(
+    input: &Input,
+    options: &ExplorationOptions,
+) -> Result<ReachabilityGraph, ErrorTypes> {
+    Ok(if options.timed {
+        build_state_class_graph(input, options.capacity_rule)?.to_reachability_graph()
+    } else {
+        explore(input, options)
+    })
 }
 
-pub fn compile_to_output(input: Input) -> Result<Output, anyhow::Error> {
-    let Input {
-        m_names,
-        m_init,
-        transitions,
-    } = input;
+pub fn compile_to_output(
+    input: Input,
+    options: &ExplorationOptions,
+) -> Result<Output, anyhow::Error> {
+    let (graph, memory) = match options.timed {
+        true => (build_graph(&input, options)?, None),
+        false => {
+            let (graph, stats) = explore_reduced(&input, options);
+            (graph, Some(stats.memory))
+        }
+    };
+    let regex = options
+        .regex
+        .then(|| format!("{}\n", firing_language(&input, &graph)));
 
-    // CONSTRUCTION DU GRAPH DES MARQUAGES
-    let mut marking_graph = HashMap::new();
-    generate_graph(m_init.clone(), &transitions, &mut marking_graph);
+    let (graph, state_map) = match options.minimise {
+        true => {
+            let quotient = Quotient::strong(&graph);
+            let state_map = generate_state_map(&graph, &quotient);
+            (quotient.graph, Some(state_map))
+        }
+        false => (graph, None),
+    };
 
     // GENERATION DES BORNES DES PLACES
-    let places = Place::build(&m_names, &m_init, &marking_graph);
+    let places = Place::build(&input, &graph)?;
+
+    let mut output = Output::generate(&input, &graph, &places)?;
+    output.state_map = state_map;
+    output.regex = regex;
+    output.memory = memory;
+    Ok(output)
+}
+
+/// `n` independent workers, the transition `t<i>` of each moving the token
+/// of its own place `p<2i>` to `p<2i+1>`
+#[cfg(test)]
+pub(crate) fn independent_workers(n: usize) -> Input {
+    Input {
+        m_names: (0..2 * n).map(|p| format!("p{}", p)).collect(),
+        m_init: (0..2 * n).map(|p| Some(i32::from(p % 2 == 0))).collect(),
+        transitions: (0..n)
+            .map(|t| {
+                (0..2 * n)
+                    .map(|p| (i32::from(p == 2 * t), i32::from(p == 2 * t + 1)))
+                    .collect()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
 
-    Output::generate(&m_names, &m_init, &marking_graph, &places, &transitions)
+#[cfg(test)]
+mod test {
+    use super::{
+        activate_transition, explore, explore_reduced, may_cover_ancestor, steps, CapacityRule,
+        ExplorationOptions, FiringSemantics, Input, Place, ReachabilityGraph,
+    };
+
+    fn input() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(2)],
+            transitions: vec![vec![(1, 0), (0, 1)]],
+            t_names: vec!["t0".to_string()],
+            read_arcs: vec![vec![0, 3]],
+            reset_arcs: vec![vec![false, false]],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_arc_does_not_consume() {
+        let mut input = input();
+        assert_eq!(
+            activate_transition(&input, 0, &[Some(1), Some(2)], CapacityRule::Strict),
+            None
+        );
+        assert_eq!(
+            activate_transition(&input, 0, &[Some(1), Some(3)], CapacityRule::Strict),
+            Some(vec![Some(0), Some(4)])
+        );
+        input.read_arcs = vec![vec![0, 0]];
+        assert_eq!(
+            activate_transition(&input, 0, &[Some(1), Some(2)], CapacityRule::Strict),
+            Some(vec![Some(0), Some(3)])
+        );
+    }
+
+    #[test]
+    fn test_reset_arc_empties_place() {
+        let mut input = input();
+        input.read_arcs = vec![];
+        input.reset_arcs = vec![vec![false, true]];
+        assert_eq!(
+            activate_transition(&input, 0, &[Some(1), Some(5)], CapacityRule::Strict),
+            Some(vec![Some(0), Some(1)])
+        );
+        assert_eq!(
+            activate_transition(&input, 0, &[Some(1), None], CapacityRule::Strict),
+            Some(vec![Some(0), Some(1)])
+        );
+    }
+
+    #[test]
+    fn test_validate_checks_sizes_first() {
+        let mut unnamed = input();
+        unnamed.capacities = vec![Some(0), Some(0)];
+        unnamed.m_names.pop();
+        assert!(unnamed.validate().is_err());
+        let mut short = input();
+        short.transitions = vec![vec![(1, 0)]];
+        assert!(short.validate().is_err());
+    }
+
+    #[test]
+    fn test_clashing_aliases() {
+        let mut input = input();
+        input.m_names = vec!["buf[1]".to_string(), "buf_1".to_string()];
+        let graph = ReachabilityGraph::default();
+        assert!(Place::build(&input, &graph).is_err());
+        input.m_names[1] = "buf[2]".to_string();
+        let places = Place::build(&input, &graph).unwrap();
+        assert_eq!(places[1].alias, "buf_2");
+        input.m_names = vec!["b1.slot".to_string(), "b1_slot".to_string()];
+        assert!(Place::build(&input, &graph).is_err());
+    }
+
+    #[test]
+    fn test_strict_and_weak_capacities() {
+        let mut input = input();
+        input.read_arcs = vec![];
+        input.transitions = vec![vec![(1, 0), (1, 2)]];
+        input.capacities = vec![None, Some(3)];
+        let marking = [Some(1), Some(2)];
+        assert_eq!(
+            activate_transition(&input, 0, &marking, CapacityRule::Strict),
+            None
+        );
+        assert_eq!(
+            activate_transition(&input, 0, &marking, CapacityRule::Weak),
+            Some(vec![Some(0), Some(3)])
+        );
+    }
+
+    #[test]
+    fn test_priorities_and_maximal_steps() {
+        // t0 and t1 share nothing, t2 competes with t0 for p0
+        let mut input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(1)],
+            transitions: vec![
+                vec![(1, 0), (0, 0)],
+                vec![(0, 0), (1, 0)],
+                vec![(1, 0), (0, 0)],
+            ],
+            ..Default::default()
+        };
+        let marking = [Some(1), Some(1)];
+        let mut options = ExplorationOptions {
+            semantics: FiringSemantics::MaximalStep,
+            ..Default::default()
+        };
+        assert_eq!(
+            steps(&input, &marking, &options),
+            vec![vec![0, 1], vec![1, 2]]
+        );
+
+        options.semantics = FiringSemantics::Step;
+        assert_eq!(steps(&input, &marking, &options).len(), 5);
+
+        input.priorities = vec![0, 0, 1];
+        options.semantics = FiringSemantics::Interleaving;
+        assert_eq!(steps(&input, &marking, &options), vec![vec![2]]);
+    }
+
+    #[test]
+    fn test_markings_are_stored_packed() {
+        // t0 keeps adding tokens to p1, t1 moves the token of p0 to p2
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(1), Some(0), Some(0)],
+            transitions: vec![vec![(1, 1), (0, 1), (0, 0)], vec![(1, 0), (0, 0), (0, 1)]],
+            ..Default::default()
+        };
+        let (graph, stats) = explore_reduced(&input, &Default::default());
+        assert_eq!(
+            graph.markings,
+            vec![
+                vec![Some(1), Some(0), Some(0)],
+                vec![Some(1), None, Some(0)],
+                vec![Some(0), Some(0), Some(1)],
+                vec![Some(0), None, Some(1)],
+            ]
+        );
+        assert_eq!(stats.memory.markings, 4);
+        // two bits for p0 and p2, omega fits in the single bit of p1
+        assert_eq!((stats.memory.bits, stats.memory.words), (5, 1));
+        assert!(stats.memory.bytes < stats.memory.unpacked_bytes);
+    }
+
+    #[test]
+    fn test_loop_on_initial_marking() {
+        // t0 reads the token of p0 by taking and putting it back
+        let input = Input {
+            m_names: vec!["p0".to_string()],
+            m_init: vec![Some(1)],
+            transitions: vec![vec![(1, 1)]],
+            ..Default::default()
+        };
+        assert!(!may_cover_ancestor(&input));
+        let graph = explore(&input, &Default::default());
+        assert_eq!(graph.markings, vec![vec![Some(1)]]);
+        assert_eq!(graph.edges, vec![vec![(vec![0], 0)]]);
+    }
+
+    #[test]
+    fn test_ancestors_walked_when_covering() {
+        // the tokens of p1 are conserved, the ones of p2 can grow
+        let mut input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(1), Some(0), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1), (0, 0)], vec![(0, 1), (1, 0), (0, 0)]],
+            ..Default::default()
+        };
+        assert!(!may_cover_ancestor(&input));
+        input.transitions[1][2] = (0, 1);
+        assert!(may_cover_ancestor(&input));
+        input.capacities = vec![None, None, Some(3)];
+        assert!(!may_cover_ancestor(&input));
+        input.m_init[2] = None;
+        assert!(may_cover_ancestor(&input));
+    }
 }
@@ -0,0 +1,263 @@
+use crate::query::Relation;
+
+/// tolerance of the floating point comparisons of the simplex
+const EPSILON: f64 = 1e-9;
+
+/// minimises `objective · x` over `x >= 0` subject to the rows
+/// `coefficients · x relation rhs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearProgram {
+    pub objective: Vec<f64>,
+    pub rows: Vec<(Vec<f64>, Relation, f64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LpResult {
+    Optimal(Vec<f64>),
+    Infeasible,
+    Unbounded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IlpResult {
+    /// an integer solution, optimal unless the node limit was reached
+    Solution(Vec<i64>),
+    Infeasible,
+    /// the relaxation of a branch has no optimum, whether the program has an
+    /// integer solution is not decided
+    Unbounded,
+    /// the node limit was reached before any integer solution was found
+    Unknown,
+}
+
+/// dense simplex tableau, the last column holds the right hand sides
+struct Tableau {
+    rows: Vec<Vec<f64>>,
+    basis: Vec<usize>,
+}
+
+impl Tableau {
+    fn pivot(&mut self, row: usize, column: usize) {
+        let factor = self.rows[row][column];
+        for x in self.rows[row].iter_mut() {
+            *x /= factor;
+        }
+        let pivot_row = self.rows[row].clone();
+        for (i, other) in self.rows.iter_mut().enumerate() {
+            let factor = other[column];
+            if i != row && factor.abs() > EPSILON {
+                for (x, p) in other.iter_mut().zip(&pivot_row) {
+                    *x -= factor * p;
+                }
+            }
+        }
+        self.basis[row] = column;
+    }
+
+    /// minimises `cost` letting only the first `columns` variables enter
+    /// the basis (Bland's rule), `false` when unbounded
+    fn minimise(&mut self, cost: &[f64], columns: usize) -> bool {
+        let rhs = self.rows.first().map_or(0, |row| row.len() - 1);
+        loop {
+            let entering = (0..columns).find(|j| {
+                let reduced = cost[*j]
+                    - self
+                        .rows
+                        .iter()
+                        .zip(&self.basis)
+                        .map(|(row, b)| cost[*b] * row[*j])
+                        .sum::<f64>();
+                reduced < -EPSILON
+            });
+            let Some(j) = entering else {
+                return true;
+            };
+            let leaving = (0..self.rows.len())
+                .filter(|i| self.rows[*i][j] > EPSILON)
+                .min_by(|a, b| {
+                    let ratio = |i: usize| self.rows[i][rhs] / self.rows[i][j];
+                    ratio(*a)
+                        .partial_cmp(&ratio(*b))
+                        .unwrap()
+                        .then(self.basis[*a].cmp(&self.basis[*b]))
+                });
+            match leaving {
+                Some(i) => self.pivot(i, j),
+                None => return false,
+            }
+        }
+    }
+}
+
+/// two phase simplex
+pub fn solve_linear(program: &LinearProgram) -> LpResult {
+    let n = program.objective.len();
+    let rows = program
+        .rows
+        .iter()
+        .map(|(coefficients, relation, rhs)| match *rhs < 0.0 {
+            true => {
+                let flipped = match relation {
+                    Relation::AtLeast => Relation::AtMost,
+                    Relation::AtMost => Relation::AtLeast,
+                    Relation::Equal => Relation::Equal,
+                };
+                (coefficients.iter().map(|a| -a).collect(), flipped, -rhs)
+            }
+            false => (coefficients.clone(), *relation, *rhs),
+        })
+        .collect::<Vec<(Vec<f64>, Relation, f64)>>();
+    let m = rows.len();
+    let slacks = rows.iter().filter(|r| r.1 != Relation::Equal).count();
+    let artificials = rows.iter().filter(|r| r.1 != Relation::AtMost).count();
+    let width = n + slacks + artificials;
+
+    let mut tableau = Tableau {
+        rows: vec![vec![0.0; width + 1]; m],
+        basis: vec![0; m],
+    };
+    let (mut slack, mut artificial) = (n, n + slacks);
+    for (i, (coefficients, relation, rhs)) in rows.iter().enumerate() {
+        tableau.rows[i][..n].copy_from_slice(coefficients);
+        tableau.rows[i][width] = *rhs;
+        match relation {
+            Relation::AtMost => {
+                tableau.rows[i][slack] = 1.0;
+                tableau.basis[i] = slack;
+                slack += 1;
+            }
+            Relation::AtLeast | Relation::Equal => {
+                if *relation == Relation::AtLeast {
+                    tableau.rows[i][slack] = -1.0;
+                    slack += 1;
+                }
+                tableau.rows[i][artificial] = 1.0;
+                tableau.basis[i] = artificial;
+                artificial += 1;
+            }
+        }
+    }
+
+    let phase_one = (0..width)
+        .map(|j| if j >= n + slacks { 1.0 } else { 0.0 })
+        .collect::<Vec<_>>();
+    tableau.minimise(&phase_one, width);
+    let infeasibility = tableau
+        .rows
+        .iter()
+        .zip(&tableau.basis)
+        .map(|(row, b)| phase_one[*b] * row[width])
+        .sum::<f64>();
+    if infeasibility > EPSILON {
+        return LpResult::Infeasible;
+    }
+    // drives the artificial variables left at 0 out of the basis
+    for i in 0..m {
+        if tableau.basis[i] >= n + slacks {
+            if let Some(j) = (0..n + slacks).find(|j| tableau.rows[i][*j].abs() > EPSILON) {
+                tableau.pivot(i, j);
+            }
+        }
+    }
+
+    let mut cost = program.objective.clone();
+    cost.resize(width, 0.0);
+    if !tableau.minimise(&cost, n + slacks) {
+        return LpResult::Unbounded;
+    }
+    let mut solution = vec![0.0; n];
+    for (row, b) in tableau.rows.iter().zip(&tableau.basis) {
+        if *b < n {
+            solution[*b] = row[width];
+        }
+    }
+    LpResult::Optimal(solution)
+}
+
+/// branch and bound over the linear relaxations, depth first, exploring at
+/// most `node_limit` relaxations
+pub fn solve_integer(program: &LinearProgram, node_limit: usize) -> IlpResult {
+    let value = |x: &[f64]| {
+        program
+            .objective
+            .iter()
+            .zip(x)
+            .map(|(c, x)| c * x)
+            .sum::<f64>()
+    };
+    let mut best: Option<(f64, Vec<i64>)> = None;
+    let mut stack = vec![program.clone()];
+    let mut nodes = 0;
+    while let Some(node) = stack.pop() {
+        if nodes >= node_limit {
+            return match best {
+                Some((_, solution)) => IlpResult::Solution(solution),
+                None => IlpResult::Unknown,
+            };
+        }
+        nodes += 1;
+        let x = match solve_linear(&node) {
+            LpResult::Optimal(x) => x,
+            LpResult::Infeasible => continue,
+            LpResult::Unbounded => return IlpResult::Unbounded,
+        };
+        if best
+            .as_ref()
+            .is_some_and(|(bound, _)| value(&x) >= bound - EPSILON)
+        {
+            continue;
+        }
+        match x.iter().position(|v| (v - v.round()).abs() > 1e-6) {
+            None => {
+                let solution = x.iter().map(|v| v.round() as i64).collect::<Vec<_>>();
+                best = Some((value(&x), solution));
+            }
+            Some(j) => {
+                let unit = (0..x.len())
+                    .map(|k| f64::from(u8::from(k == j)))
+                    .collect::<Vec<_>>();
+                for (relation, bound) in [
+                    (Relation::AtLeast, x[j].ceil()),
+                    (Relation::AtMost, x[j].floor()),
+                ] {
+                    let mut branch = node.clone();
+                    branch.rows.push((unit.clone(), relation, bound));
+                    stack.push(branch);
+                }
+            }
+        }
+    }
+    match best {
+        Some((_, solution)) => IlpResult::Solution(solution),
+        None => IlpResult::Infeasible,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{solve_integer, solve_linear, IlpResult, LinearProgram, LpResult};
+    use crate::query::Relation;
+
+    #[test]
+    fn test_integer_solution_differs_from_relaxation() {
+        // min x + y with 2x + 2y = 3 has no integer solution
+        let mut program = LinearProgram {
+            objective: vec![1.0, 1.0],
+            rows: vec![(vec![2.0, 2.0], Relation::Equal, 3.0)],
+        };
+        assert!(matches!(solve_linear(&program), LpResult::Optimal(_)));
+        assert_eq!(solve_integer(&program, 1000), IlpResult::Infeasible);
+        // min x + 2y with x + y >= 2.5, the relaxation stops at x = 2.5
+        program.objective = vec![1.0, 2.0];
+        program.rows = vec![(vec![1.0, 1.0], Relation::AtLeast, 2.5)];
+        assert_eq!(
+            solve_integer(&program, 1000),
+            IlpResult::Solution(vec![3, 0])
+        );
+        // min -x with x >= 0.5 is feasible but has no optimum
+        program.objective = vec![-1.0, 0.0];
+        program.rows = vec![(vec![1.0, 0.0], Relation::AtLeast, 0.5)];
+        assert_eq!(solve_linear(&program), LpResult::Unbounded);
+        assert_eq!(solve_integer(&program, 1000), IlpResult::Unbounded);
+    }
+}
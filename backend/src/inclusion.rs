@@ -0,0 +1,146 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::{
+    automaton::Automaton,
+    error_type::ErrorTypes,
+    graph_gen::{fire_step, steps, ExplorationOptions, Input, Marking, Step},
+    lts::step_label,
+    query::STATE_LIMIT,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// the specification does not allow the last step
+    Unexpected,
+    /// the specification allows the steps but ends outside its final states
+    NotAccepted,
+}
+
+/// a shortest firing sequence of the net outside the language of the
+/// specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub steps: Vec<Step>,
+    pub kind: ViolationKind,
+}
+
+/// pairs of a marking and the states the specification can be in
+#[derive(Default)]
+struct Search {
+    nodes: Vec<(Marking, BTreeSet<usize>)>,
+    parents: Vec<Option<(usize, Step)>>,
+    /// smallest sets of specification states seen with each marking
+    antichain: HashMap<Marking, Vec<BTreeSet<usize>>>,
+    queue: VecDeque<usize>,
+}
+
+impl Search {
+    /// queues `node` unless a pair with its marking and a subset of its
+    /// states was seen, such a pair fails whenever `node` does
+    fn push(&mut self, node: (Marking, BTreeSet<usize>), parent: Option<(usize, Step)>) {
+        let seen = self.antichain.entry(node.0.clone()).or_default();
+        if seen.iter().any(|states| states.is_subset(&node.1)) {
+            return;
+        }
+        seen.retain(|states| !node.1.is_subset(states));
+        seen.push(node.1.clone());
+        self.nodes.push(node);
+        self.parents.push(parent);
+        self.queue.push_back(self.nodes.len() - 1);
+    }
+
+    fn steps_to(&self, mut node: usize) -> Vec<Step> {
+        let mut steps = vec![];
+        while let Some((parent, step)) = &self.parents[node] {
+            steps.push(step.clone());
+            node = *parent;
+        }
+        steps.reverse();
+        steps
+    }
+}
+
+/// checks that every firing sequence of `input`, read through the names of
+/// its visible transitions, is a word of `spec`; the markings are generated
+/// on the fly next to the sets of states the specification can be in
+/// (subset construction), a pair being skipped when a pair with the same
+/// marking and fewer states was seen (antichain)
+pub fn find_violation(
+    input: &Input,
+    spec: &Automaton,
+    options: &ExplorationOptions,
+) -> Result<Option<Violation>, ErrorTypes> {
+    let mut search = Search::default();
+    search.push(
+        (
+            input.m_init.clone(),
+            spec.lts.tau_closure([spec.lts.initial]),
+        ),
+        None,
+    );
+    while let Some(current) = search.queue.pop_front() {
+        let (marking, states) = search.nodes[current].clone();
+        let kind = match states.is_empty() {
+            true => Some(ViolationKind::Unexpected),
+            false if !states.iter().any(|s| spec.accepting[*s]) => Some(ViolationKind::NotAccepted),
+            false => None,
+        };
+        if let Some(kind) = kind {
+            return Ok(Some(Violation {
+                steps: search.steps_to(current),
+                kind,
+            }));
+        }
+        for step in steps(input, &marking, options) {
+            let next = fire_step(input, &step, &marking, options.capacity_rule).unwrap();
+            let next_states = match step_label(input, &step) {
+                Some(label) => spec.lts.after(&states, &label),
+                None => states.clone(),
+            };
+            search.push((next, next_states), Some((current, step)));
+            if search.nodes.len() > STATE_LIMIT {
+                return Err(ErrorTypes::StateLimitExceeded { limit: STATE_LIMIT });
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_violation, ViolationKind};
+    use crate::{automaton::Automaton, graph_gen::Input};
+
+    /// `make` moves the token of idle to ready, `send` moves it back
+    fn input() -> Input {
+        Input {
+            m_names: vec!["idle".to_string(), "ready".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1)], vec![(0, 1), (1, 0)]],
+            t_names: vec!["make".to_string(), "send".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shortest_violation() {
+        let options = Default::default();
+        let spec = Automaton::from_dsl("a -make-> b\nb -send-> a\n").unwrap();
+        assert_eq!(find_violation(&input(), &spec, &options).unwrap(), None);
+        let once = Automaton::from_dsl("a -make-> b\nb -send-> c\nc -make-> d\n").unwrap();
+        let violation = find_violation(&input(), &once, &options).unwrap().unwrap();
+        assert_eq!(violation.steps, vec![vec![0], vec![1], vec![0], vec![1]]);
+        assert_eq!(violation.kind, ViolationKind::Unexpected);
+        let finals = Automaton::from_dsl("a -make-> b\nb -send-> a\nfinal a\n").unwrap();
+        let violation = find_violation(&input(), &finals, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(violation.steps, vec![vec![0]]);
+        assert_eq!(violation.kind, ViolationKind::NotAccepted);
+        // hiding send makes make, make possible
+        let mut hidden = input();
+        hidden.hidden = vec![false, true];
+        let violation = find_violation(&hidden, &spec, &options).unwrap().unwrap();
+        assert_eq!(violation.steps.len(), 3);
+    }
+}
@@ -1,5 +1,24 @@
+pub mod automaton;
+pub mod bdd;
+pub mod bisimulation;
+pub mod ctmc;
+pub mod equivalence;
 pub mod error_type;
 pub mod graph_gen;
+pub mod ilp;
+pub mod inclusion;
+pub mod lts;
 mod ndr_parser;
 pub mod output_generators;
+pub mod parallel;
 pub mod petri_parser;
+pub mod query;
+pub mod regex;
+pub mod simulation;
+pub mod state_class;
+pub mod state_equation;
+pub mod store;
+pub mod stubborn;
+pub mod symbolic;
+pub mod symmetry;
+pub mod unfolding;
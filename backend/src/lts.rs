@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
+
+use crate::{
+    bisimulation::{branching_bisimulation, strong_bisimulation, weak_bisimulation},
+    graph_gen::{Input, ReachabilityGraph},
+};
+
+/// a label of an lts, `None` standing for the internal action tau
+pub type Label = Option<String>;
+
+/// equivalence an lts is reduced modulo
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Equivalence {
+    Strong,
+    #[default]
+    Branching,
+    Weak,
+    /// same sequences of visible labels
+    WeakTrace,
+}
+
+impl FromStr for Equivalence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strong" => Ok(Equivalence::Strong),
+            "branching" => Ok(Equivalence::Branching),
+            "weak" => Ok(Equivalence::Weak),
+            "weak-trace" => Ok(Equivalence::WeakTrace),
+            _ => Err(format!(
+                "unknown equivalence {}, expected strong, branching, weak or weak-trace",
+                s
+            )),
+        }
+    }
+}
+
+/// name of a step without its hidden transitions, tau when all of them
+/// are hidden
+pub fn step_label(input: &Input, step: &[usize]) -> Label {
+    let visible = step
+        .iter()
+        .copied()
+        .filter(|t| !input.is_hidden(*t))
+        .collect::<Vec<_>>();
+    (!visible.is_empty()).then(|| input.step_name(&visible))
+}
+
+/// labelled transition system over the names of the fired steps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lts {
+    pub initial: usize,
+    /// labelled successors of each state
+    pub edges: Vec<Vec<(Label, usize)>>,
+}
+
+impl Lts {
+    /// labels the edges of `graph` by their steps
+    pub fn from_graph(input: &Input, graph: &ReachabilityGraph) -> Self {
+        Lts {
+            initial: graph.initial,
+            edges: graph
+                .edges
+                .iter()
+                .map(|next| {
+                    next.iter()
+                        .map(|(step, n)| (step_label(input, step), *n))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn transitions_count(&self) -> usize {
+        self.edges.iter().map(Vec::len).sum()
+    }
+
+    /// both systems side by side, the states of `other` come after the ones
+    /// of `self` and the initial state is the one of `self`
+    pub fn union(&self, other: &Lts) -> Self {
+        let offset = self.edges.len();
+        let mut edges = self.edges.clone();
+        edges.extend(other.edges.iter().map(|next| {
+            next.iter()
+                .map(|(label, t)| (label.clone(), t + offset))
+                .collect()
+        }));
+        Lts {
+            initial: self.initial,
+            edges,
+        }
+    }
+
+    /// `states` and the states reachable from them by tau steps
+    pub fn tau_closure(&self, states: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut stack = states.into_iter().collect::<Vec<_>>();
+        while let Some(s) = stack.pop() {
+            if closure.insert(s) {
+                stack.extend(
+                    self.edges[s]
+                        .iter()
+                        .filter(|(label, _)| label.is_none())
+                        .map(|(_, t)| *t),
+                );
+            }
+        }
+        closure
+    }
+
+    /// states reached from `states` by `label` then any number of tau steps
+    pub fn after(&self, states: &BTreeSet<usize>, label: &str) -> BTreeSet<usize> {
+        self.tau_closure(states.iter().flat_map(|s| {
+            self.edges[*s]
+                .iter()
+                .filter(|(l, _)| l.as_deref() == Some(label))
+                .map(|(_, t)| *t)
+        }))
+    }
+
+    /// visible labels of the steps leaving `states`, sorted
+    pub fn visible_labels(&self, states: &BTreeSet<usize>) -> BTreeSet<&str> {
+        states
+            .iter()
+            .flat_map(|s| &self.edges[*s])
+            .filter_map(|(label, _)| label.as_deref())
+            .collect()
+    }
+
+    /// class of every state modulo `equivalence`, numbered by smallest state
+    pub fn classes(&self, equivalence: Equivalence) -> Vec<usize> {
+        match equivalence {
+            Equivalence::Strong => strong_bisimulation(&self.edges),
+            Equivalence::Branching => branching_bisimulation(&self.edges),
+            Equivalence::Weak => weak_bisimulation(&self.edges),
+            Equivalence::WeakTrace => self.weak_trace_classes(),
+        }
+    }
+
+    /// classes of the states with the same visible traces: the system is
+    /// determinised from every state, a state having the traces of its tau
+    /// closure, and the deterministic system is strongly minimised
+    fn weak_trace_classes(&self) -> Vec<usize> {
+        let mut subsets = vec![];
+        let mut indices = HashMap::new();
+        let mut number = |subset: BTreeSet<usize>, subsets: &mut Vec<BTreeSet<usize>>| {
+            *indices.entry(subset.clone()).or_insert_with(|| {
+                subsets.push(subset);
+                subsets.len() - 1
+            })
+        };
+        let starts = (0..self.edges.len())
+            .map(|s| number(self.tau_closure([s]), &mut subsets))
+            .collect::<Vec<_>>();
+        let mut edges = vec![];
+        while edges.len() < subsets.len() {
+            let subset = subsets[edges.len()].clone();
+            let next = self
+                .visible_labels(&subset)
+                .into_iter()
+                .map(|label| (label, number(self.after(&subset, label), &mut subsets)))
+                .collect::<Vec<_>>();
+            edges.push(next);
+        }
+        let classes = strong_bisimulation(&edges);
+        let mut numbers = HashMap::new();
+        starts
+            .iter()
+            .map(|subset| {
+                let count = numbers.len();
+                *numbers.entry(classes[*subset]).or_insert(count)
+            })
+            .collect()
+    }
+
+    /// merges the states of each class, tau steps inside a class are dropped
+    /// unless the classes come from strong bisimulation
+    pub fn quotient(&self, classes: &[usize], equivalence: Equivalence) -> Self {
+        let count = classes.iter().max().map_or(0, |c| c + 1);
+        let mut edges = vec![vec![]; count];
+        for (s, next) in self.edges.iter().enumerate() {
+            for (label, t) in next {
+                let inert = label.is_none() && classes[s] == classes[*t];
+                if !inert || equivalence == Equivalence::Strong {
+                    edges[classes[s]].push((label.clone(), classes[*t]));
+                }
+            }
+        }
+        for next in edges.iter_mut() {
+            next.sort_unstable();
+            next.dedup();
+        }
+        Lts {
+            initial: classes[self.initial],
+            edges,
+        }
+    }
+
+    pub fn reduce(&self, equivalence: Equivalence) -> Self {
+        self.quotient(&self.classes(equivalence), equivalence)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Equivalence, Lts};
+    use crate::{
+        equivalence::trace_difference,
+        graph_gen::{explore, Input},
+    };
+
+    #[test]
+    fn test_hidden_transitions_reduce() {
+        // a token going round p0 -> p1 -> p2 -> p0, only `back` is visible
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(1), Some(0), Some(0)],
+            transitions: vec![
+                vec![(1, 0), (0, 1), (0, 0)],
+                vec![(0, 0), (1, 0), (0, 1)],
+                vec![(0, 1), (0, 0), (1, 0)],
+            ],
+            t_names: vec!["go".to_string(), "on".to_string(), "back".to_string()],
+            hidden: vec![true, true, false],
+            ..Default::default()
+        };
+        let lts = Lts::from_graph(&input, &explore(&input, &Default::default()));
+        assert_eq!(lts.transitions_count(), 3);
+        for equivalence in [
+            Equivalence::Branching,
+            Equivalence::Weak,
+            Equivalence::WeakTrace,
+        ] {
+            let reduced = lts.reduce(equivalence);
+            assert_eq!(reduced.edges, vec![vec![(Some("back".to_string()), 0)]]);
+        }
+        assert_eq!(lts.reduce(Equivalence::Strong).edges.len(), 3);
+    }
+
+    #[test]
+    fn test_weak_trace_reduction() {
+        // a.b + a.c against a.(b + c): the same traces but not bisimilar
+        let label = |name: &str| Some(name.to_string());
+        let lts = Lts {
+            initial: 0,
+            edges: vec![
+                vec![(label("a"), 1), (label("a"), 2), (None, 5)],
+                vec![(label("b"), 3)],
+                vec![(label("c"), 4)],
+                vec![],
+                vec![],
+                vec![(label("a"), 6)],
+                vec![(label("b"), 3), (label("c"), 4)],
+            ],
+        };
+        let classes = lts.classes(Equivalence::WeakTrace);
+        assert_eq!(classes, vec![0, 1, 2, 3, 3, 0, 4]);
+        let weak = lts.classes(Equivalence::Weak);
+        assert_ne!(weak[0], weak[5]);
+        let reduced = lts.reduce(Equivalence::WeakTrace);
+        assert_eq!(reduced.edges.len(), 5);
+        assert!(trace_difference(&lts, &reduced).is_none());
+    }
+}
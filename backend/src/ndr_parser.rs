@@ -177,5 +177,6 @@ pub fn get_input_from_ndr(code: &str) -> Input {
         m_names,
         m_init,
         transitions,
+        ..Default::default()
     }
 }
@@ -1,12 +1,22 @@
 use std::{
-    collections::HashMap,
     fs, io,
     process::{Command, Stdio},
 };
 
 use graphviz_rust::{cmd::Format, printer::PrinterContext};
+use serde_json::json;
 
-use crate::{error_type::ErrorTypes, graph_gen::Place};
+use crate::{
+    automaton::Automaton,
+    bisimulation::Quotient,
+    ctmc::Ctmc,
+    error_type::ErrorTypes,
+    graph_gen::{Input, Place, ReachabilityGraph},
+    lts::Lts,
+    simulation::Run,
+    store::StoreStats,
+    unfolding::Prefix,
+};
 
 const DOT_TEMPLATE: &str = r#"
     digraph {
@@ -15,6 +25,15 @@ const DOT_TEMPLATE: &str = r#"
     }
 "#;
 
+const NET_DOT_TEMPLATE: &str = r#"
+    digraph {
+        rankdir=LR
+        PLACES
+        TRANSITIONS
+        ARCS
+    }
+"#;
+
 const CODE_TEMPLATE: &str = r#"
 MODULE main
     VAR
@@ -35,21 +54,30 @@ STATE_TRANSITION
 pub struct Output {
     smv: String,
     svg: String,
+    net_dot: String,
     pub png: Vec<u8>,
+    /// markings merged into each state of a minimised automaton
+    pub state_map: Option<String>,
+    /// firing language of the automaton
+    pub regex: Option<String>,
+    /// memory taken by the explored markings, `None` for a state class graph
+    pub memory: Option<StoreStats>,
 }
 
 impl Output {
     pub fn generate(
-        m_names: &Vec<String>,
-        m_init: &Vec<Option<i32>>,
-        marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
+        input: &Input,
+        graph: &ReachabilityGraph,
         places: &Vec<Place>,
-        transitions: &Vec<Vec<(i32, i32)>>,
     ) -> Result<Self, anyhow::Error> {
         Ok(Self {
-            smv: generate_smv_code(m_init, marking_graph, places),
-            svg: generate_svg(m_names, marking_graph, transitions)?,
-            png: generate_png(m_names, marking_graph, transitions)?,
+            smv: generate_smv_code(graph, places),
+            svg: generate_svg(input, graph)?,
+            net_dot: generate_net_dot(input),
+            png: generate_png(input, graph)?,
+            state_map: None,
+            regex: None,
+            memory: None,
         })
     }
 
@@ -64,6 +92,26 @@ impl Output {
     pub fn save_png(&self, svg_file_path: &str) -> Result<(), io::Error> {
         fs::write(svg_file_path, &self.png)
     }
+
+    pub fn save_net_dot(&self, dot_file_path: &str) -> Result<(), io::Error> {
+        fs::write(dot_file_path, &self.net_dot)
+    }
+
+    /// does nothing when the automaton was not minimised
+    pub fn save_state_map(&self, map_file_path: &str) -> Result<(), io::Error> {
+        match &self.state_map {
+            Some(state_map) => fs::write(map_file_path, state_map),
+            None => Ok(()),
+        }
+    }
+
+    /// does nothing when the regular expression was not asked for
+    pub fn save_regex(&self, regex_file_path: &str) -> Result<(), io::Error> {
+        match &self.regex {
+            Some(regex) => fs::write(regex_file_path, regex),
+            None => Ok(()),
+        }
+    }
 }
 
 pub fn vector_to_string(v: &Vec<Option<i32>>, sep: &str) -> String {
@@ -76,19 +124,26 @@ pub fn vector_to_string(v: &Vec<Option<i32>>, sep: &str) -> String {
         .join(sep)
 }
 
-pub fn generate_smv_code(
-    m_init: &Vec<Option<i32>>,
-    marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    places: &Vec<Place>,
-) -> String {
+/// inverse of `vector_to_string`, `None` when a value is not a number or `n`
+pub fn string_to_vector(s: &str, sep: &str) -> Option<Vec<Option<i32>>> {
+    s.split(sep)
+        .map(|x| match x.trim() {
+            "n" => Some(None),
+            x => x.parse::<i32>().ok().map(Some),
+        })
+        .collect()
+}
+
+pub fn generate_smv_code(graph: &ReachabilityGraph, places: &[Place]) -> String {
+    let names = graph.state_names("_");
     CODE_TEMPLATE
         .replace(
             "STATES",
             &format!(
                 "{{{}}}",
-                marking_graph
-                    .keys()
-                    .map(|m| format!("s_{}", vector_to_string(m, "_")))
+                names
+                    .iter()
+                    .map(|name| format!("s_{}", name))
                     .collect::<Vec<_>>()
                     .join(",")
             ),
@@ -112,21 +167,20 @@ pub fn generate_smv_code(
                 .collect::<Vec<_>>()
                 .join("\n"),
         )
-        .replace(
-            "STATE_ASSIGN",
-            &format!("s_{}", vector_to_string(&m_init, "_")),
-        )
+        .replace("STATE_ASSIGN", &format!("s_{}", names[graph.initial]))
         .replace(
             "STATE_TRANSITION",
-            &marking_graph
+            &graph
+                .edges
                 .iter()
+                .enumerate()
                 .map(|(current, next)| {
                     format!(
-                        "\t\ts={} : {{{}}};",
-                        format!("s_{}", vector_to_string(current, "_")),
-                        if next.len() > 0 {
+                        "\t\ts=s_{} : {{{}}};",
+                        names[current],
+                        if !next.is_empty() {
                             next.iter()
-                                .map(|(_, v)| format!("s_{}", vector_to_string(v, "_")))
+                                .map(|(_, v)| format!("s_{}", names[*v]))
                                 .collect::<Vec<_>>()
                                 .join(",")
                         } else {
@@ -145,12 +199,14 @@ pub fn generate_smv_code(
                     format!(
                         "{} := case\n {} \n\t\tesac;",
                         p.alias,
-                        marking_graph
-                            .keys()
-                            .map(|current| format!(
+                        graph
+                            .markings
+                            .iter()
+                            .enumerate()
+                            .map(|(current, marking)| format!(
                                 "\t\ts=s_{} : {{{}}};",
-                                vector_to_string(current, "_"),
-                                match current[p.indice] {
+                                names[current],
+                                match marking[p.indice] {
                                     Some(x) => x,
                                     None => p.max,
                                 }
@@ -164,30 +220,24 @@ pub fn generate_smv_code(
         )
 }
 
-fn generate_dot_template(
-    m_names: &Vec<String>,
-    marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    transitions: &Vec<Vec<(i32, i32)>>,
-) -> String {
+fn generate_dot_template(input: &Input, graph: &ReachabilityGraph) -> String {
+    let names = graph.state_names("-");
     DOT_TEMPLATE
-        .replace("NAMING", &format!("\"{}\"", m_names.join("-")))
+        .replace("NAMING", &format!("\"{}\"", input.m_names.join("-")))
         .replace(
             "GRAPH",
-            &marking_graph
+            &graph
+                .edges
                 .iter()
+                .enumerate()
                 .map(|(k, v)| {
                     v.iter()
                         .map(|(t, n)| {
                             format!(
-                                " \"{}\" -> \"{}\" [label = \"t{}\"]",
-                                vector_to_string(k, "-"),
-                                vector_to_string(n, "-"),
-                                transitions
-                                    .iter()
-                                    .enumerate()
-                                    .find(|(_, x)| x.iter().zip(t).all(|((x, _), t)| t == x))
-                                    .unwrap()
-                                    .0
+                                " \"{}\" -> \"{}\" [label = \"{}\"]",
+                                names[k],
+                                names[*n],
+                                input.step_name(t)
                             )
                         })
                         .collect::<Vec<_>>()
@@ -199,12 +249,335 @@ fn generate_dot_template(
         .to_string()
 }
 
-pub fn generate_svg(
-    m_names: &Vec<String>,
-    marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    transitions: &Vec<Vec<(i32, i32)>>,
-) -> Result<String, anyhow::Error> {
-    let dot_template = generate_dot_template(m_names, marking_graph, transitions);
+/// one line per state of the minimised automaton, named as in the smv
+/// code, followed by the markings of the original states it merges
+pub fn generate_state_map(original: &ReachabilityGraph, quotient: &Quotient) -> String {
+    let names = quotient.graph.state_names("_");
+    names
+        .iter()
+        .enumerate()
+        .map(|(state, name)| {
+            format!(
+                "s_{}: {}\n",
+                name,
+                quotient
+                    .markings(original, state)
+                    .iter()
+                    .map(|m| vector_to_string(m, "-"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        })
+        .collect()
+}
+
+/// transitions of the markov chain in PRISM explicit format (`.tra`)
+pub fn generate_prism_tra(ctmc: &Ctmc) -> String {
+    let mut lines = vec![format!(
+        "{} {}",
+        ctmc.states.len(),
+        ctmc.transitions_count()
+    )];
+    for (from, row) in ctmc.rates.iter().enumerate() {
+        for (to, rate) in row {
+            lines.push(format!("{} {} {}", from, to, rate));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// states of the markov chain in PRISM explicit format (`.sta`)
+pub fn generate_prism_sta(m_names: &[String], ctmc: &Ctmc) -> String {
+    let mut lines = vec![format!("({})", m_names.join(","))];
+    for (i, marking) in ctmc.states.iter().enumerate() {
+        lines.push(format!("{}:({})", i, vector_to_string(marking, ",")));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// labels of the markov chain in PRISM explicit format (`.lab`), marks the
+/// states the chain can start in
+pub fn generate_prism_lab(ctmc: &Ctmc) -> String {
+    let mut lines = vec!["0=\"init\"".to_string()];
+    for (i, p) in ctmc.initial.iter().enumerate() {
+        if *p > 0.0 {
+            lines.push(format!("{}: 0", i));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// random runs as json, each step holds the fired transition (`null` for
+/// the initial marking) and the token count of every place after it
+pub fn generate_trace_json(input: &Input, runs: &[Run]) -> Result<String, serde_json::Error> {
+    let runs = runs
+        .iter()
+        .map(|run| {
+            let steps = run
+                .markings
+                .iter()
+                .enumerate()
+                .map(|(i, marking)| {
+                    json!({
+                        "transition": i
+                            .checked_sub(1)
+                            .map(|k| input.transition_name(run.transitions[k])),
+                        "marking": marking,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({ "deadlock": run.deadlock, "steps": steps })
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&json!({ "places": input.m_names, "runs": runs }))
+}
+
+/// random runs as csv, one line per step with the token count of every
+/// place, `n` standing for omega
+pub fn generate_trace_csv(input: &Input, runs: &[Run]) -> String {
+    let mut lines = vec![format!("run,step,transition,{}", input.m_names.join(","))];
+    for (r, run) in runs.iter().enumerate() {
+        for (i, marking) in run.markings.iter().enumerate() {
+            let transition = match i {
+                0 => String::new(),
+                i => input.transition_name(run.transitions[i - 1]),
+            };
+            lines.push(format!(
+                "{},{},{},{}",
+                r,
+                i,
+                transition,
+                vector_to_string(marking, ",")
+            ));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// draws the net itself: places as circles holding their initial tokens,
+/// transitions as boxes, read arcs as dashed lines without arrow heads and
+/// reset arcs as bold double headed arrows
+pub fn generate_net_dot(input: &Input) -> String {
+    let weight = |w: i32| {
+        if w == 1 {
+            String::new()
+        } else {
+            format!(", label = \"{}\"", w)
+        }
+    };
+    NET_DOT_TEMPLATE
+        .replace(
+            "PLACES",
+            &input
+                .m_names
+                .iter()
+                .zip(&input.m_init)
+                .enumerate()
+                .map(|(p, (name, tokens))| {
+                    format!(
+                        "p{} [shape = circle, label = \"{}\\n{}{}\"]",
+                        p,
+                        name,
+                        tokens.map_or("n".to_string(), |x| x.to_string()),
+                        input
+                            .capacity(p)
+                            .map_or(String::new(), |k| format!("/{}", k))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n\t\t"),
+        )
+        .replace(
+            "TRANSITIONS",
+            &(0..input.transitions.len())
+                .map(|t| {
+                    format!(
+                        "t{} [shape = box, label = \"{}{}\"]",
+                        t,
+                        input.transition_name(t),
+                        match input.priority(t) {
+                            0 => String::new(),
+                            level => format!("\\npriority {}", level),
+                        }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n\t\t"),
+        )
+        .replace(
+            "ARCS",
+            &input
+                .transitions
+                .iter()
+                .enumerate()
+                .flat_map(|(t, arcs)| {
+                    arcs.iter().enumerate().flat_map(move |(p, (x1, x2))| {
+                        let mut lines = vec![];
+                        if *x1 > 0 {
+                            lines.push(format!("p{} -> t{} [style = solid{}]", p, t, weight(*x1)));
+                        }
+                        if *x2 > 0 {
+                            lines.push(format!("t{} -> p{} [style = solid{}]", t, p, weight(*x2)));
+                        }
+                        if input.read_arc(t, p) > 0 {
+                            lines.push(format!(
+                                "p{} -> t{} [style = dashed, arrowhead = none{}]",
+                                p,
+                                t,
+                                weight(input.read_arc(t, p))
+                            ));
+                        }
+                        if input.reset_arc(t, p) {
+                            lines.push(format!(
+                                "t{} -> p{} [style = bold, arrowhead = normalnormal]",
+                                t, p
+                            ));
+                        }
+                        lines
+                    })
+                })
+                .collect::<Vec<_>>()
+                .join("\n\t\t"),
+        )
+}
+
+/// lts in the aldebaran format of CADP (`.aut`), tau is written `i`
+pub fn generate_aut(lts: &Lts) -> String {
+    let mut lines = vec![format!(
+        "des ({}, {}, {})",
+        lts.initial,
+        lts.transitions_count(),
+        lts.edges.len()
+    )];
+    for (from, next) in lts.edges.iter().enumerate() {
+        for (label, to) in next {
+            lines.push(format!(
+                "({}, \"{}\", {})",
+                from,
+                label.as_deref().unwrap_or("i"),
+                to
+            ));
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// lts as a dot graph with numbered states, the initial one drawn twice
+pub fn generate_lts_dot(lts: &Lts) -> String {
+    let mut lines = vec![format!("{} [shape = doublecircle]", lts.initial)];
+    for (from, next) in lts.edges.iter().enumerate() {
+        for (label, to) in next {
+            lines.push(format!(
+                "{} -> {} [label = \"{}\"]",
+                from,
+                to,
+                label.as_deref().unwrap_or("τ")
+            ));
+        }
+    }
+    format!("digraph {{\n\t{}\n}}\n", lines.join("\n\t"))
+}
+
+/// transition table of the automaton separated by tabs, one column per
+/// label (`τ` for tau moves) and one line per state, the initial state is
+/// marked by `->` and the final ones by `*`; a cell lists the successors
+pub fn generate_transition_table(automaton: &Automaton) -> String {
+    let mut labels = automaton
+        .alphabet()
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<_>>();
+    if automaton
+        .lts
+        .edges
+        .iter()
+        .flatten()
+        .any(|(l, _)| l.is_none())
+    {
+        labels.push(None);
+    }
+    let mut lines = vec![format!(
+        "state\t{}",
+        labels
+            .iter()
+            .map(|label| label.as_deref().unwrap_or("τ"))
+            .collect::<Vec<_>>()
+            .join("\t")
+    )];
+    for (s, next) in automaton.lts.edges.iter().enumerate() {
+        let cells = labels.iter().map(|label| {
+            let targets = next
+                .iter()
+                .filter(|(l, _)| l == label)
+                .map(|(_, t)| automaton.names[*t].as_str())
+                .collect::<Vec<_>>();
+            match targets.len() {
+                0 => "-".to_string(),
+                1 => targets[0].to_string(),
+                _ => format!("{{{}}}", targets.join(", ")),
+            }
+        });
+        lines.push(format!(
+            "{}{}{}\t{}",
+            if s == automaton.lts.initial { "->" } else { "" },
+            if automaton.accepting[s] { "*" } else { "" },
+            automaton.names[s],
+            cells.collect::<Vec<_>>().join("\t")
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// automaton as a dot graph readable by `Automaton::from_dot`, the final
+/// states drawn as double circles and the initial one pointed at
+pub fn generate_automaton_dot(automaton: &Automaton) -> String {
+    let mut lines = vec![
+        "start [shape = point]".to_string(),
+        format!("start -> \"{}\"", automaton.names[automaton.lts.initial]),
+    ];
+    for (name, accepting) in automaton.names.iter().zip(&automaton.accepting) {
+        let shape = if *accepting { "doublecircle" } else { "circle" };
+        lines.push(format!("\"{}\" [shape = {}]", name, shape));
+    }
+    for (from, next) in automaton.lts.edges.iter().enumerate() {
+        for (label, to) in next {
+            lines.push(format!(
+                "\"{}\" -> \"{}\" [label = \"{}\"]",
+                automaton.names[from],
+                automaton.names[*to],
+                label.as_deref().unwrap_or("τ")
+            ));
+        }
+    }
+    format!("digraph {{\n\t{}\n}}\n", lines.join("\n\t"))
+}
+
+/// prefix of an unfolding as a dot graph, the conditions drawn as circles
+/// named after their places and the events as boxes named after their
+/// transitions, dashed for the cut-offs
+pub fn generate_prefix_dot(input: &Input, prefix: &Prefix) -> String {
+    let mut lines = vec![];
+    for (b, condition) in prefix.conditions.iter().enumerate() {
+        lines.push(format!(
+            "b{} [shape = circle, label = \"{}\"]",
+            b, input.m_names[condition.place]
+        ));
+    }
+    for (e, event) in prefix.events.iter().enumerate() {
+        lines.push(format!(
+            "e{} [shape = box, label = \"{}\"{}]",
+            e,
+            input.transition_name(event.transition),
+            if event.cutoff { ", style = dashed" } else { "" }
+        ));
+        lines.extend(event.preset.iter().map(|b| format!("b{} -> e{}", b, e)));
+        lines.extend(event.postset.iter().map(|b| format!("e{} -> b{}", e, b)));
+    }
+    format!("digraph {{\n\t{}\n}}\n", lines.join("\n\t"))
+}
+
+pub fn generate_svg(input: &Input, graph: &ReachabilityGraph) -> Result<String, anyhow::Error> {
+    let dot_template = generate_dot_template(input, graph);
 
     let graph = graphviz_rust::parse(&dot_template)
         .map_err(|e| ErrorTypes::CannotAssembleGraph { reason: e })?;
@@ -215,12 +588,8 @@ pub fn generate_svg(
     )?)
 }
 
-pub fn generate_png(
-    m_names: &Vec<String>,
-    marking_graph: &HashMap<Vec<Option<i32>>, Vec<(Vec<i32>, Vec<Option<i32>>)>>,
-    transitions: &Vec<Vec<(i32, i32)>>,
-) -> Result<Vec<u8>, anyhow::Error> {
-    let dot_template = generate_dot_template(m_names, marking_graph, transitions);
+pub fn generate_png(input: &Input, graph: &ReachabilityGraph) -> Result<Vec<u8>, anyhow::Error> {
+    let dot_template = generate_dot_template(input, graph);
     let echo_child = Command::new("echo")
         .arg(format!("{}", dot_template))
         .stdout(Stdio::piped())
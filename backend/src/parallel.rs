@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    iter,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use iter_tools::Itertools;
+
+use crate::{
+    graph_gen::{
+        fire_enabled, ExplorationOptions, FiringSemantics, Input, Marking, ReachabilityGraph,
+        Reduction, Step,
+    },
+    store::{fingerprint, MarkingStore, StoreStats},
+    symmetry::Symmetries,
+};
+
+/// shards of the table of the reached markings, many more than threads so
+/// that they seldom wait for each other
+const SHARDS: usize = 64;
+
+/// parent of the initial marking
+const ROOT: usize = usize::MAX;
+
+/// why the marking graph is explored by a single thread, `None` when it
+/// does not depend on the order its markings are expanded in: the
+/// sequential explorer only accelerates a marking covering one of its
+/// ancestors, which never happens in a finite graph of a net whose firings
+/// add constant vectors and are never blocked by more tokens
+pub fn sequential_reason(input: &Input, options: &ExplorationOptions) -> Option<&'static str> {
+    if options.timed {
+        Some("the state class graph is built by a single thread")
+    } else if options.reduction != Reduction::None {
+        Some("the partial order reductions expand the markings in order")
+    } else if options.semantics == FiringSemantics::MaximalStep {
+        Some("more tokens can block a maximal step")
+    } else if !(0..input.transitions.len())
+        .map(|t| input.priority(t))
+        .all_equal()
+    {
+        Some("more tokens can let a transition of higher priority block the others")
+    } else if (0..input.m_names.len()).any(|p| input.capacity(p).is_some()) {
+        Some("more tokens can overflow a capacity")
+    } else if input.reset_arcs.iter().flatten().any(|reset| *reset) {
+        Some("the reset arcs do not add constant vectors")
+    } else if input.m_init.iter().any(Option::is_none) {
+        Some("the initial marking is unbounded")
+    } else {
+        None
+    }
+}
+
+/// markings of a shard and the marking each one was first reached from
+#[derive(Debug)]
+struct Shard {
+    store: MarkingStore,
+    parents: Vec<usize>,
+}
+
+/// the reached markings spread over shards by fingerprint, the number of a
+/// marking is its number in its shard times `SHARDS` plus its shard
+struct Table {
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Table {
+    fn new(places: usize) -> Self {
+        let shard = || Shard {
+            store: MarkingStore::new(places),
+            parents: vec![],
+        };
+        Table {
+            shards: (0..SHARDS).map(|_| Mutex::new(shard())).collect(),
+        }
+    }
+
+    fn shard(&self, id: usize) -> MutexGuard<'_, Shard> {
+        self.shards[id % SHARDS].lock().unwrap()
+    }
+
+    /// number of `marking`, and whether it was not reached yet
+    fn claim(&self, marking: &[Option<i32>], parent: usize) -> (usize, bool) {
+        let index = fingerprint(marking) as usize % SHARDS;
+        let mut shard = self.shards[index].lock().unwrap();
+        let (id, new) = shard.store.insert(marking);
+        if new {
+            shard.parents.push(parent);
+        }
+        (id * SHARDS + index, new)
+    }
+
+    fn marking(&self, id: usize) -> Marking {
+        self.shard(id).store.marking(id / SHARDS)
+    }
+
+    /// whether `marking` strictly covers `parent` or a marking `parent` was
+    /// first reached from, the net is then unbounded
+    fn covers_ancestor(&self, marking: &[Option<i32>], mut parent: usize) -> bool {
+        while parent != ROOT {
+            let shard = self.shard(parent);
+            let id = parent / SHARDS;
+            let values = (0..marking.len()).map(|p| shard.store.value(id, p));
+            if values.clone().zip(marking).all(|(x, y)| x <= *y)
+                && values.zip(marking).any(|(x, y)| x != *y)
+            {
+                return true;
+            }
+            parent = shard.parents[id];
+        }
+        false
+    }
+
+    fn stats(&self) -> StoreStats {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().store.stats())
+            .fold(StoreStats::default(), |total, stats| StoreStats {
+                markings: total.markings + stats.markings,
+                bits: total.bits.max(stats.bits),
+                words: total.words.max(stats.words),
+                bytes: total.bytes + stats.bytes,
+                unpacked_bytes: total.unpacked_bytes + stats.unpacked_bytes,
+                collisions: total.collisions + stats.collisions,
+                repacks: total.repacks + stats.repacks,
+            })
+    }
+}
+
+/// wakes the threads waiting for markings to expand, each notification
+/// starts a new round
+#[derive(Default)]
+struct Wakeup {
+    round: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    fn round(&self) -> usize {
+        *self.round.lock().unwrap()
+    }
+
+    fn notify(&self) {
+        *self.round.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+
+    /// sleeps until a round after `round` or until `done`, which must only
+    /// become true before a notification
+    fn wait(&self, round: usize, done: impl Fn() -> bool) {
+        let mut current = self.round.lock().unwrap();
+        while *current == round && !done() {
+            current = self.condvar.wait(current).unwrap();
+        }
+    }
+}
+
+/// a marking to expand: the last one the thread reached, else a batch of the
+/// shared ones, else one stolen from an other thread
+fn find_task<T>(local: &Worker<T>, injector: &Injector<T>, stealers: &[Stealer<T>]) -> Option<T> {
+    local.pop().or_else(|| {
+        iter::repeat_with(|| {
+            injector
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(Stealer::steal).collect())
+        })
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+    })
+}
+
+/// the marking graph explored by `options.jobs` threads stealing markings
+/// from each other, numbered in breadth first order like the sequential
+/// explorer numbers it, with the memory its markings took; `None` when a
+/// marking covers one of its ancestors, which the sequential explorer
+/// accelerates
+pub fn explore_parallel(
+    input: &Input,
+    options: &ExplorationOptions,
+    symmetries: Option<&Symmetries>,
+) -> Option<(ReachabilityGraph, StoreStats)> {
+    let table = Table::new(input.m_names.len());
+    let (initial, _) = table.claim(&input.m_init, ROOT);
+    let injector = Injector::new();
+    injector.push((initial, input.m_init.clone()));
+    // markings reached and not expanded yet
+    let pending = AtomicUsize::new(1);
+    let covering = AtomicBool::new(false);
+    let wakeup = Wakeup::default();
+    let workers = (0..options.jobs.max(1))
+        .map(|_| Worker::new_lifo())
+        .collect::<Vec<_>>();
+    let stealers = workers.iter().map(Worker::stealer).collect::<Vec<_>>();
+
+    let expand = |m: usize, marking: &Marking, local: &Worker<(usize, Marking)>| {
+        let (next_ms, _) = fire_enabled(input, options, None, marking, |_| false);
+        let next_ms = next_ms
+            .into_iter()
+            .map(|(step, n)| match symmetries {
+                Some(symmetries) => (step, symmetries.canonical(&n)),
+                None => (step, n),
+            })
+            .unique()
+            .collect::<Vec<_>>();
+        let mut successors = vec![];
+        let mut pushed = false;
+        for (step, n) in next_ms {
+            let (id, new) = table.claim(&n, m);
+            if new {
+                if table.covers_ancestor(&n, m) {
+                    covering.store(true, Ordering::Release);
+                }
+                pending.fetch_add(1, Ordering::AcqRel);
+                local.push((id, n));
+                pushed = true;
+            }
+            successors.push((step, id));
+        }
+        (successors, pushed)
+    };
+
+    let expanded = thread::scope(|scope| {
+        let handles = workers
+            .into_iter()
+            .map(|local| {
+                let (expand, injector, stealers) = (&expand, &injector, &stealers);
+                let (pending, covering, wakeup) = (&pending, &covering, &wakeup);
+                let done =
+                    || pending.load(Ordering::Acquire) == 0 || covering.load(Ordering::Acquire);
+                scope.spawn(move || {
+                    let mut expanded: Vec<(usize, Vec<(Step, usize)>)> = vec![];
+                    while !covering.load(Ordering::Acquire) {
+                        let round = wakeup.round();
+                        match find_task(&local, injector, stealers) {
+                            Some((m, marking)) => {
+                                let (successors, pushed) = expand(m, &marking, &local);
+                                expanded.push((m, successors));
+                                // the others may steal the new markings, or
+                                // stop when none is left
+                                let left = pending.fetch_sub(1, Ordering::AcqRel) - 1;
+                                if pushed || left == 0 || covering.load(Ordering::Acquire) {
+                                    wakeup.notify();
+                                }
+                            }
+                            None if done() => break,
+                            None => wakeup.wait(round, done),
+                        }
+                    }
+                    expanded
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<HashMap<_, _>>()
+    });
+    if covering.into_inner() {
+        return None;
+    }
+
+    let mut indices = HashMap::from([(initial, 0)]);
+    let mut order = vec![initial];
+    let mut edges = vec![];
+    while edges.len() < order.len() {
+        let m = order[edges.len()];
+        let next = expanded[&m]
+            .iter()
+            .map(|(step, n)| {
+                let index = *indices.entry(*n).or_insert_with(|| {
+                    order.push(*n);
+                    order.len() - 1
+                });
+                (step.clone(), index)
+            })
+            .collect::<Vec<_>>();
+        edges.push(next);
+    }
+    let graph = ReachabilityGraph {
+        initial: 0,
+        markings: order.iter().map(|m| table.marking(*m)).collect(),
+        edges,
+    };
+    Some((graph, table.stats()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{explore_parallel, sequential_reason};
+    use crate::graph_gen::{explore, explore_reduced, ExplorationOptions, Input};
+
+    /// `n` workers sharing `locks` locks, each counting its rounds up to 2
+    fn workers(n: usize, locks: i32) -> Input {
+        // idle, busy and rounds of each worker, then the locks
+        let places = 3 * n + 1;
+        let transitions = (0..n)
+            .flat_map(|w| {
+                let take = (0..places)
+                    .map(|p| match p {
+                        _ if p == 3 * w => (1, 0),
+                        _ if p == 3 * w + 1 => (0, 1),
+                        _ if p == 3 * n => (1, 0),
+                        _ => (0, 0),
+                    })
+                    .collect::<Vec<_>>();
+                let release = (0..places)
+                    .map(|p| match p {
+                        _ if p == 3 * w => (0, 1),
+                        _ if p == 3 * w + 1 => (1, 0),
+                        _ if p == 3 * w + 2 => (1, 0),
+                        _ if p == 3 * n => (0, 1),
+                        _ => (0, 0),
+                    })
+                    .collect::<Vec<_>>();
+                [take, release]
+            })
+            .collect();
+        Input {
+            m_names: (0..places).map(|p| format!("p{}", p)).collect(),
+            m_init: (0..places)
+                .map(|p| match p % 3 {
+                    _ if p == 3 * n => Some(locks),
+                    0 => Some(1),
+                    2 => Some(2),
+                    _ => Some(0),
+                })
+                .collect(),
+            transitions,
+            t_names: (0..2 * n).map(|t| format!("t{}", t)).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_same_graph_as_sequential() {
+        let input = workers(5, 2);
+        assert_eq!(sequential_reason(&input, &Default::default()), None);
+        for symmetry in [false, true] {
+            let sequential = ExplorationOptions {
+                symmetry,
+                ..Default::default()
+            };
+            let parallel = ExplorationOptions {
+                jobs: 4,
+                ..sequential.clone()
+            };
+            let (expected, stats) = explore_reduced(&input, &sequential);
+            let (graph, parallel_stats) = explore_reduced(&input, &parallel);
+            assert_eq!(graph, expected);
+            assert_eq!(parallel_stats.represented_states, stats.represented_states);
+            assert_eq!(parallel_stats.memory.markings, graph.markings.len());
+        }
+    }
+
+    #[test]
+    fn test_covering_falls_back() {
+        // a generator of tokens is accelerated by the sequential explorer
+        let input = Input {
+            m_names: vec!["a".to_string(), "b".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 1), (0, 1)]],
+            t_names: vec!["gen".to_string()],
+            ..Default::default()
+        };
+        let options = ExplorationOptions {
+            jobs: 2,
+            ..Default::default()
+        };
+        assert!(explore_parallel(&input, &options, None).is_none());
+        assert_eq!(
+            explore_reduced(&input, &options).0,
+            explore(&input, &Default::default())
+        );
+    }
+
+    #[test]
+    fn test_loop_on_initial_marking() {
+        // t0 takes and puts back the token of p0, t1 moves it to p1
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 1), (0, 0)], vec![(1, 0), (0, 1)]],
+            ..Default::default()
+        };
+        let options = ExplorationOptions {
+            jobs: 3,
+            ..Default::default()
+        };
+        let (graph, _) = explore_parallel(&input, &options, None).unwrap();
+        assert_eq!(graph.edges[0], vec![(vec![0], 0), (vec![1], 1)]);
+        assert_eq!(graph, explore(&input, &Default::default()));
+    }
+}
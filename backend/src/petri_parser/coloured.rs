@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use iter_tools::Itertools;
+use pest::iterators::{Pair, Pairs};
+
+use super::{
+    hierarchy::locate,
+    parser::{Entry, PetriNet, Place, Rule, Transition},
+};
+use crate::error_type::ErrorTypes;
+
+type Identifier = String;
+
+/// a finite colour set, integer ranges are stored as the list of their values
+#[derive(Debug, Clone)]
+pub(super) struct ColourSet {
+    pub(super) name: Identifier,
+    pub(super) values: Vec<String>,
+}
+
+impl ColourSet {
+    pub(super) fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Result<Self, ErrorTypes> {
+        let name = inner_rules.next().unwrap().as_str().to_string();
+        let definition = inner_rules.next().unwrap();
+        let values = match definition.as_rule() {
+            Rule::colour_range => {
+                let mut bounds = definition.into_inner();
+                let lower = bounds.next().unwrap().as_str().parse::<i32>().unwrap();
+                let upper = bounds.next().unwrap().as_str().parse::<i32>().unwrap();
+                if upper < lower {
+                    return Err(ErrorTypes::BadColour {
+                        reason: format!("Empty colour range for {}", name),
+                    });
+                }
+                (lower..=upper).map(|v| v.to_string()).collect()
+            }
+            _ => definition
+                .into_inner()
+                .map(|v| v.as_str().to_string())
+                .collect(),
+        };
+        Ok(ColourSet { name, values })
+    }
+
+    fn index_of(&self, value: &str) -> Option<usize> {
+        self.values.iter().position(|v| v == value)
+    }
+}
+
+/// a colour constant or variable, offsets wrap around the colour set
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Term {
+    name: Identifier,
+    offset: i32,
+}
+
+impl Term {
+    pub(super) fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
+        Term {
+            name: inner_rules.next().unwrap().as_str().to_string(),
+            offset: inner_rules
+                .next()
+                .map_or(0, |offset| offset.as_str().parse::<i32>().unwrap()),
+        }
+    }
+
+    /// colour of the term in `set` under `binding`, variables must range over `set`
+    fn resolve(&self, set: &ColourSet, binding: &Binding) -> Result<usize, ErrorTypes> {
+        let index = match binding.get(self.name.as_str()) {
+            Some((variable_set, _)) if variable_set.name != set.name => {
+                return Err(ErrorTypes::BadColour {
+                    reason: format!(
+                        "Variable {} of colour {} used as colour {}",
+                        self.name, variable_set.name, set.name
+                    ),
+                })
+            }
+            Some((_, index)) => *index,
+            None => set.index_of(&self.name).ok_or(ErrorTypes::BadColour {
+                reason: format!("Unknown colour {} in colour set {}", self.name, set.name),
+            })?,
+        };
+        Ok((index as i32 + self.offset).rem_euclid(set.values.len() as i32) as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparator {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+#[derive(Debug, Clone)]
+struct Comparison {
+    left: Term,
+    comparator: Comparator,
+    right: Term,
+}
+
+impl Comparison {
+    fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
+        let left = Term::from_rule(&mut inner_rules.next().unwrap().into_inner());
+        let comparator = match inner_rules.next().unwrap().as_str() {
+            "==" => Comparator::Eq,
+            "!=" => Comparator::Ne,
+            "<=" => Comparator::Le,
+            ">=" => Comparator::Ge,
+            "<" => Comparator::Lt,
+            _ => Comparator::Gt,
+        };
+        let right = Term::from_rule(&mut inner_rules.next().unwrap().into_inner());
+        Comparison {
+            left,
+            comparator,
+            right,
+        }
+    }
+
+    /// colours are compared by their position in the colour set of the
+    /// variable, constants are looked up in that same set
+    fn holds(&self, binding: &Binding) -> Result<bool, ErrorTypes> {
+        let set = [&self.left, &self.right]
+            .iter()
+            .find_map(|term| binding.get(term.name.as_str()))
+            .map(|(set, _)| *set)
+            .ok_or(ErrorTypes::BadColour {
+                reason: format!(
+                    "Guard compares the constants {} and {}",
+                    self.left.name, self.right.name
+                ),
+            })?;
+        let left = self.left.resolve(set, binding)?;
+        let right = self.right.resolve(set, binding)?;
+        Ok(match self.comparator {
+            Comparator::Eq => left == right,
+            Comparator::Ne => left != right,
+            Comparator::Le => left <= right,
+            Comparator::Ge => left >= right,
+            Comparator::Lt => left < right,
+            Comparator::Gt => left > right,
+        })
+    }
+}
+
+/// disjunction of conjunctions of comparisons
+#[derive(Debug, Clone)]
+pub(super) struct Guard(Vec<Vec<Comparison>>);
+
+impl Guard {
+    pub(super) fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
+        Guard(
+            inner_rules
+                .map(|conjunction| {
+                    conjunction
+                        .into_inner()
+                        .map(|c| Comparison::from_rule(&mut c.into_inner()))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    fn holds(&self, binding: &Binding) -> Result<bool, ErrorTypes> {
+        for conjunction in &self.0 {
+            let mut holds = true;
+            for comparison in conjunction {
+                holds = holds && comparison.holds(binding)?;
+            }
+            if holds {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// colour set of a typed place with its initial tokens, `None` for one
+/// token of every colour
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ColourInit {
+    set: Identifier,
+    tokens: Option<Vec<(Term, i32)>>,
+}
+
+impl ColourInit {
+    pub(super) fn from_rule(set: Pair<'_, Rule>, tokens: &mut Pairs<'_, Rule>) -> Self {
+        let mut init = ColourInit {
+            set: set.as_str().to_string(),
+            tokens: Some(vec![]),
+        };
+        for token in tokens.take_while_ref(|r| r.as_rule() != Rule::capacity) {
+            match token.as_rule() {
+                Rule::all_colours => init.tokens = None,
+                _ => {
+                    let mut parts = token.into_inner();
+                    let term = Term::from_rule(&mut parts.next().unwrap().into_inner());
+                    let count = parts
+                        .next()
+                        .map_or(1, |n| n.as_str().parse::<i32>().unwrap());
+                    init.tokens.as_mut().unwrap().push((term, count));
+                }
+            }
+        }
+        init
+    }
+}
+
+/// value of each variable of a transition with the colour set it ranges over
+type Binding<'a> = HashMap<&'a str, (&'a ColourSet, usize)>;
+
+/// name of the place holding the tokens of colour `index`
+fn coloured_name(name: &str, set: &ColourSet, index: usize) -> String {
+    format!("{}[{}]", name, set.values[index])
+}
+
+impl PetriNet {
+    /// expands the coloured places and transitions into plain ones, a place
+    /// `buf` over `{1 2}` becomes `buf[1]` and `buf[2]` and a transition gets
+    /// one copy per binding of its variables satisfying its guard
+    pub(super) fn unfold(self) -> Result<PetriNet, ErrorTypes> {
+        if self.colours.iter().map(|c| &c.name).unique().count() != self.colours.len() {
+            return Err(ErrorTypes::BadColour {
+                reason: "Repeating colour set".to_string(),
+            });
+        }
+        let set_of = |name: &str| {
+            self.colours
+                .iter()
+                .find(|c| c.name == name)
+                .ok_or(ErrorTypes::BadColour {
+                    reason: format!("Undeclared colour set {}", name),
+                })
+        };
+        let mut place_sets = HashMap::new();
+        let mut places = vec![];
+        for place in &self.places {
+            let Some(init) = &place.colour else {
+                places.push(place.clone());
+                continue;
+            };
+            let set = set_of(&init.set).map_err(|error| locate(error, &place.origin))?;
+            place_sets.insert(place.name.as_str(), set);
+            let mut tokens = vec![0; set.values.len()];
+            match &init.tokens {
+                None => tokens.fill(1),
+                Some(initial) => {
+                    for (term, count) in initial {
+                        let colour = term
+                            .resolve(set, &HashMap::new())
+                            .map_err(|error| locate(error, &place.origin))?;
+                        tokens[colour] += count;
+                    }
+                }
+            }
+            places.extend(tokens.into_iter().enumerate().map(|(i, tokens)| Place {
+                name: coloured_name(&place.name, set, i),
+                tokens,
+                capacity: place.capacity,
+                colour: None,
+                origin: place.origin.clone(),
+            }));
+        }
+
+        let unfold_transition = |transition: &Transition| {
+            let mut transitions = vec![];
+            let variables = transition
+                .variables
+                .iter()
+                .map(|(name, set)| Ok((name.as_str(), set_of(set)?)))
+                .collect::<Result<Vec<_>, ErrorTypes>>()?;
+            // every combination of the variable values
+            let bindings = variables.iter().fold(vec![vec![]], |bindings, (_, set)| {
+                bindings
+                    .into_iter()
+                    .flat_map(|b: Vec<usize>| {
+                        (0..set.values.len()).map(move |v| [b.clone(), vec![v]].concat())
+                    })
+                    .collect()
+            });
+            for values in bindings {
+                let binding = variables
+                    .iter()
+                    .zip(&values)
+                    .map(|((name, set), v)| (*name, (*set, *v)))
+                    .collect::<Binding>();
+                if let Some(guard) = &transition.guard {
+                    if !guard.holds(&binding)? {
+                        continue;
+                    }
+                }
+                let arcs = |entries: &[Entry]| {
+                    unfold_entries(entries, &place_sets, &binding, &transition.name)
+                };
+                let mut resets: Vec<Entry> = vec![];
+                for reset in &transition.resets {
+                    let unfolded = match (place_sets.get(reset.0.as_str()), &reset.2) {
+                        (Some(set), None) => (0..set.values.len())
+                            .map(|i| Entry(coloured_name(&reset.0, set, i), 1, None))
+                            .collect(),
+                        (None, None) => vec![reset.clone()],
+                        _ => arcs(std::slice::from_ref(reset))?,
+                    };
+                    for entry in unfolded {
+                        let coloured = place_sets.contains_key(reset.0.as_str());
+                        if !coloured || resets.iter().all(|Entry(name, _, _)| *name != entry.0) {
+                            resets.push(entry);
+                        }
+                    }
+                }
+                transitions.push(Transition {
+                    name: match values.is_empty() {
+                        true => transition.name.clone(),
+                        false => format!(
+                            "{}[{}]",
+                            transition.name,
+                            variables
+                                .iter()
+                                .zip(&values)
+                                .map(|((_, set), v)| &set.values[*v])
+                                .join(",")
+                        ),
+                    },
+                    inputs: arcs(&transition.inputs)?,
+                    outputs: arcs(&transition.outputs)?,
+                    reads: arcs(&transition.reads)?,
+                    resets,
+                    variables: vec![],
+                    guard: None,
+                    ..transition.clone()
+                });
+            }
+            Ok::<_, ErrorTypes>(transitions)
+        };
+        let mut transitions = vec![];
+        for transition in &self.transitions {
+            transitions.extend(
+                unfold_transition(transition).map_err(|error| locate(error, &transition.origin))?,
+            );
+        }
+
+        Ok(PetriNet {
+            colours: vec![],
+            places,
+            transitions,
+            ..self
+        })
+    }
+}
+
+/// replaces the arcs to coloured places by arcs to the unfolded place of the
+/// colour they carry, arcs ending on the same unfolded place add up while a
+/// repeated plain place is left for the validation to reject
+fn unfold_entries(
+    entries: &[Entry],
+    place_sets: &HashMap<&str, &ColourSet>,
+    binding: &Binding,
+    t_id: &str,
+) -> Result<Vec<Entry>, ErrorTypes> {
+    let mut unfolded: Vec<Entry> = vec![];
+    for Entry(place, weight, term) in entries {
+        let name = match (place_sets.get(place.as_str()), term) {
+            (Some(set), Some(term)) => coloured_name(place, set, term.resolve(set, binding)?),
+            (None, None) => {
+                unfolded.push(Entry(place.clone(), *weight, None));
+                continue;
+            }
+            (Some(_), None) => {
+                return Err(ErrorTypes::BadColour {
+                    reason: format!("Missing colour on place {} in transition {}", place, t_id),
+                })
+            }
+            (None, Some(_)) => {
+                return Err(ErrorTypes::BadColour {
+                    reason: format!(
+                        "Colour on uncoloured place {} in transition {}",
+                        place, t_id
+                    ),
+                })
+            }
+        };
+        match unfolded.iter_mut().find(|Entry(n, _, _)| *n == name) {
+            Some(entry) => entry.1 += weight,
+            None => unfolded.push(Entry(name, *weight, None)),
+        }
+    }
+    Ok(unfolded)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::petri_parser::parser::PetriNet;
+
+    #[test]
+    fn test_unfolding() {
+        let code = "colour pid = 1..3\n\nplace buf : pid = 1 3:2 cap 4\nplace token : pid = all\nplace done = 0\n\ntransition send(i: pid, j: pid) if i != j and j == i+1\ninputs: buf(i) token(j)\noutputs: buf(i+1)\n\ntransition clear\ninputs: \noutputs: done\nresets: buf\n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(
+            input.m_names,
+            vec!["buf[1]", "buf[2]", "buf[3]", "token[1]", "token[2]", "token[3]", "done"]
+        );
+        assert_eq!(
+            input.m_init,
+            vec![
+                Some(1),
+                Some(0),
+                Some(2),
+                Some(1),
+                Some(1),
+                Some(1),
+                Some(0)
+            ]
+        );
+        assert_eq!(input.capacities[0], Some(4));
+        assert_eq!(
+            input.t_names,
+            vec!["send[1,2]", "send[2,3]", "send[3,1]", "clear"]
+        );
+        // send[3,1] moves a token from buf[3] to buf[1] using token[1]
+        assert_eq!(
+            input.transitions[2],
+            vec![(0, 1), (0, 0), (1, 0), (1, 0), (0, 0), (0, 0), (0, 0)]
+        );
+        assert_eq!(input.reset_arcs[3][..3], [true, true, true]);
+    }
+
+    #[test]
+    fn test_bad_colours() {
+        let undeclared = "place buf : pid = all\n";
+        assert!(PetriNet::new(undeclared).is_err());
+        let missing =
+            "colour pid = {a b}\nplace buf : pid = a\n\ntransition t\ninputs: buf\noutputs: \n";
+        assert!(PetriNet::new(missing).is_err());
+    }
+
+    #[test]
+    fn test_repeated_places() {
+        let plain = "place p0 = 1\n\ntransition t\ninputs: p0 p0\noutputs: \n";
+        assert!(PetriNet::new(plain).is_err());
+        let mixed = "colour pid = 1..2\nplace buf : pid = all\nplace p0 = 1\n\ntransition t\ninputs: p0\noutputs: \nresets: buf p0 p0\n";
+        assert!(PetriNet::new(mixed).is_err());
+        // two coloured arcs carrying the same colour add up
+        let coloured = "colour pid = 1..2\nplace buf : pid = all\n\ntransition t(i: pid, j: pid)\ninputs: buf(i) buf(j)\noutputs: \n";
+        let input = PetriNet::new(coloured).unwrap().generate_input();
+        assert_eq!(input.t_names[0], "t[1,1]");
+        assert_eq!(input.transitions[0], vec![(2, 0), (0, 0)]);
+    }
+}
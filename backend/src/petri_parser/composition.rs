@@ -0,0 +1,126 @@
+use anyhow::Error;
+
+use super::{
+    hierarchy::rename_transition,
+    parser::{PetriNet, Place, Transition},
+};
+
+/// places and transitions of a component, its places named `component.place`
+fn qualify(name: &str, net: PetriNet) -> (Vec<Place>, Vec<Transition>) {
+    let rename = |place: &str| format!("{}.{}", name, place);
+    let places = net
+        .places
+        .into_iter()
+        .map(|p| Place {
+            name: rename(&p.name),
+            ..p
+        })
+        .collect();
+    let transitions = net
+        .transitions
+        .iter()
+        .map(|t| rename_transition(t, t.name.clone(), &rename))
+        .collect();
+    (places, transitions)
+}
+
+/// a transition firing `left` and `right` at once, it keeps the delay of
+/// `left`, the highest priority and the intersection of the intervals, it is
+/// internal when both are
+fn synchronise(left: &Transition, right: &Transition) -> Transition {
+    let upper = match (left.interval.1, right.interval.1) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    Transition {
+        interval: (left.interval.0.max(right.interval.0), upper),
+        priority: left.priority.max(right.priority),
+        internal: left.internal && right.internal,
+        inputs: [left.inputs.clone(), right.inputs.clone()].concat(),
+        outputs: [left.outputs.clone(), right.outputs.clone()].concat(),
+        reads: [left.reads.clone(), right.reads.clone()].concat(),
+        resets: [left.resets.clone(), right.resets.clone()].concat(),
+        ..left.clone()
+    }
+}
+
+impl PetriNet {
+    /// synchronous product of two named components: the places are kept
+    /// apart as `left.p` and `right.p`, transitions with the same name fire
+    /// together and the others on their own, then the places of each fusion
+    /// set (named `component.place`) are merged
+    pub fn compose(
+        left: (&str, PetriNet),
+        right: (&str, PetriNet),
+        fusions: &[Vec<String>],
+    ) -> Result<Self, Error> {
+        let (mut places, left) = qualify(left.0, left.1);
+        let (right_places, right) = qualify(right.0, right.1);
+        places.extend(right_places);
+
+        let mut transitions = vec![];
+        for t in &left {
+            let partners = right
+                .iter()
+                .filter(|r| r.name == t.name)
+                .collect::<Vec<_>>();
+            match partners.is_empty() {
+                true => transitions.push(t.clone()),
+                false => transitions.extend(partners.into_iter().map(|r| synchronise(t, r))),
+            }
+        }
+        transitions.extend(
+            right
+                .iter()
+                .filter(|r| left.iter().all(|t| t.name != r.name))
+                .cloned(),
+        );
+
+        let mut net = PetriNet {
+            places,
+            transitions,
+            ..Default::default()
+        };
+        for fusion in fusions {
+            net.fuse(fusion)?;
+        }
+        net.validate_petri_net()?;
+        Ok(net)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::petri_parser::parser::PetriNet;
+
+    #[test]
+    fn test_synchronous_product() {
+        let producer = PetriNet::new(
+            "place idle = 1\nplace ready = 0\n\ntransition make\ninputs: idle\noutputs: ready\n\ntransition send\ninputs: ready\noutputs: idle\n",
+        )
+        .unwrap();
+        let consumer = PetriNet::new(
+            "place wait = 1\nplace busy = 0\n\ntransition send\ninputs: wait\noutputs: busy\n\ntransition use\ninputs: busy\noutputs: wait\n",
+        )
+        .unwrap();
+        let input = PetriNet::compose(("p", producer), ("c", consumer), &[])
+            .unwrap()
+            .generate_input();
+        assert_eq!(input.m_names, vec!["p.idle", "p.ready", "c.wait", "c.busy"]);
+        assert_eq!(input.t_names, vec!["make", "send", "use"]);
+        assert_eq!(input.transitions[1], vec![(0, 1), (1, 0), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn test_fusion_of_components() {
+        let net = || PetriNet::new("place p = 1\n\ntransition t\ninputs: p\noutputs: \n").unwrap();
+        let fusion = vec!["a.p".to_string(), "b.p".to_string()];
+        let input = PetriNet::compose(("a", net()), ("b", net()), &[fusion])
+            .unwrap()
+            .generate_input();
+        assert_eq!(input.m_names, vec!["a.p"]);
+        assert_eq!(input.transitions, vec![vec![(2, 0)]]);
+        let missing = vec!["a.p".to_string(), "b.q".to_string()];
+        assert!(PetriNet::compose(("a", net()), ("b", net()), &[missing]).is_err());
+    }
+}
@@ -0,0 +1,424 @@
+use std::fmt;
+
+use iter_tools::Itertools;
+use pest::iterators::Pair;
+
+use super::parser::{Entry, PetriNet, Place, Rule, Transition};
+use crate::error_type::ErrorTypes;
+
+type Identifier = String;
+
+/// where a flattened place or transition was declared, and the instance it
+/// was copied for
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Origin {
+    module: Identifier,
+    line: usize,
+    column: usize,
+    instance: Option<Identifier>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "in module {} at line {}, column {}",
+            self.module, self.line, self.column
+        )?;
+        match &self.instance {
+            Some(instance) => write!(f, " (instance {})", instance),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `error` raised by a place, transition or fusion declared at `origin`
+pub(super) fn locate(error: ErrorTypes, origin: &Option<Origin>) -> ErrorTypes {
+    match origin {
+        Some(origin) => ErrorTypes::InModule {
+            error: Box::new(error),
+            origin: origin.to_string(),
+        },
+        None => error,
+    }
+}
+
+/// a reusable sub-net, its interface places are provided by each instance
+#[derive(Debug)]
+pub(super) struct Module {
+    name: Identifier,
+    interface: Vec<Identifier>,
+    places: Vec<Place>,
+    transitions: Vec<Transition>,
+    instances: Vec<Instance>,
+    fusions: Vec<(Vec<Identifier>, Option<Origin>)>,
+    line: usize,
+    column: usize,
+}
+
+impl Module {
+    pub(super) fn from_rule(rule: Pair<'_, Rule>) -> Self {
+        let (line, column) = rule.as_span().start_pos().line_col();
+        let mut inner_rules = rule.into_inner();
+        let mut module = Module {
+            name: inner_rules.next().unwrap().as_str().to_string(),
+            interface: inner_rules
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(|p| p.as_str().to_string())
+                .collect(),
+            places: vec![],
+            transitions: vec![],
+            instances: vec![],
+            fusions: vec![],
+            line,
+            column,
+        };
+        for part in inner_rules {
+            let (line, column) = part.as_span().start_pos().line_col();
+            let origin = Some(Origin {
+                module: module.name.clone(),
+                line,
+                column,
+                instance: None,
+            });
+            match part.as_rule() {
+                Rule::place => module.places.push(Place {
+                    origin,
+                    ..Place::from_rule(&mut part.into_inner())
+                }),
+                Rule::coloured_place => module.places.push(Place {
+                    origin,
+                    ..Place::from_coloured_rule(&mut part.into_inner())
+                }),
+                Rule::transition => module.transitions.push(Transition {
+                    origin,
+                    ..Transition::from_rule(&mut part.into_inner())
+                }),
+                Rule::instance => module.instances.push(Instance::from_rule(part)),
+                Rule::fusion => module.fusions.push((fusion_from_rule(part), origin)),
+                _ => unreachable!(),
+            }
+        }
+        module
+    }
+}
+
+/// a copy of a module whose interface places are bound to `arguments`
+#[derive(Debug)]
+pub(super) struct Instance {
+    name: Identifier,
+    module: Identifier,
+    arguments: Vec<Identifier>,
+    line: usize,
+    column: usize,
+}
+
+impl Instance {
+    pub(super) fn from_rule(rule: Pair<'_, Rule>) -> Self {
+        let (line, column) = rule.as_span().start_pos().line_col();
+        let mut inner_rules = rule.into_inner();
+        Instance {
+            name: inner_rules.next().unwrap().as_str().to_string(),
+            module: inner_rules.next().unwrap().as_str().to_string(),
+            arguments: inner_rules
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(|p| p.as_str().to_string())
+                .collect(),
+            line,
+            column,
+        }
+    }
+
+    fn error(&self, reason: &str) -> ErrorTypes {
+        ErrorTypes::BadModule {
+            reason: format!(
+                "Instance {} at line {}, column {}: {}",
+                self.name, self.line, self.column, reason
+            ),
+        }
+    }
+}
+
+pub(super) fn fusion_from_rule(rule: Pair<'_, Rule>) -> Vec<Identifier> {
+    rule.into_inner().map(|p| p.as_str().to_string()).collect()
+}
+
+/// renames the places of `entries`, uncoloured arcs to distinct places
+/// ending on the same place add up while a repeated place stays repeated
+/// for the validation to reject
+fn rename_entries(entries: &[Entry], rename: impl Fn(&str) -> String) -> Vec<Entry> {
+    let mut renamed: Vec<Entry> = vec![];
+    for (k, Entry(place, weight, term)) in entries.iter().enumerate() {
+        let name = rename(place);
+        let repeated = entries[..k].iter().any(|Entry(p, _, _)| p == place);
+        let merged = renamed
+            .iter_mut()
+            .find(|Entry(n, _, t)| *n == name && t.is_none() && term.is_none());
+        match merged {
+            Some(entry) if !repeated => entry.1 += weight,
+            _ => renamed.push(Entry(name, *weight, term.clone())),
+        }
+    }
+    renamed
+}
+
+pub(super) fn rename_transition(
+    transition: &Transition,
+    name: String,
+    rename: &dyn Fn(&str) -> String,
+) -> Transition {
+    Transition {
+        name,
+        inputs: rename_entries(&transition.inputs, rename),
+        outputs: rename_entries(&transition.outputs, rename),
+        reads: rename_entries(&transition.reads, rename),
+        resets: rename_entries(&transition.resets, rename),
+        ..transition.clone()
+    }
+}
+
+impl PetriNet {
+    /// replaces every instance by a copy of its module whose places and
+    /// transitions are named `instance.name`, then merges the fused places
+    pub(super) fn flatten(mut self) -> Result<PetriNet, ErrorTypes> {
+        if self.modules.iter().map(|m| &m.name).unique().count() != self.modules.len() {
+            return Err(ErrorTypes::BadModule {
+                reason: "Repeating module".to_string(),
+            });
+        }
+        let modules = std::mem::take(&mut self.modules);
+        let mut fusions = std::mem::take(&mut self.fusions)
+            .into_iter()
+            .map(|fusion| (fusion, None))
+            .collect();
+        for instance in std::mem::take(&mut self.instances) {
+            let arguments = instance.arguments.clone();
+            self.instantiate(
+                &modules,
+                &instance,
+                "",
+                arguments,
+                &mut fusions,
+                &mut vec![],
+            )?;
+        }
+        for (fusion, origin) in fusions {
+            self.fuse(&fusion).map_err(|error| locate(error, &origin))?;
+        }
+        Ok(self)
+    }
+
+    fn instantiate(
+        &mut self,
+        modules: &[Module],
+        instance: &Instance,
+        prefix: &str,
+        arguments: Vec<Identifier>,
+        fusions: &mut Vec<(Vec<Identifier>, Option<Origin>)>,
+        stack: &mut Vec<Identifier>,
+    ) -> Result<(), ErrorTypes> {
+        let module = modules
+            .iter()
+            .find(|m| m.name == instance.module)
+            .ok_or_else(|| instance.error(&format!("undeclared module {}", instance.module)))?;
+        if stack.contains(&module.name) {
+            return Err(instance.error(&format!("module {} instantiates itself", module.name)));
+        }
+        if arguments.len() != module.interface.len() {
+            return Err(instance.error(&format!(
+                "module {} declared at line {}, column {} expects {} places, got {}",
+                module.name,
+                module.line,
+                module.column,
+                module.interface.len(),
+                arguments.len()
+            )));
+        }
+        let path = format!("{}{}", prefix, instance.name);
+        let prefix = format!("{}.", path);
+        let rename = |name: &str| match module.interface.iter().position(|i| i == name) {
+            Some(i) => arguments[i].clone(),
+            None => format!("{}{}", prefix, name),
+        };
+        let origin = |origin: &Option<Origin>| {
+            origin.clone().map(|origin| Origin {
+                instance: Some(path.clone()),
+                ..origin
+            })
+        };
+
+        for place in &module.places {
+            if module.interface.contains(&place.name) {
+                return Err(ErrorTypes::BadModule {
+                    reason: format!(
+                        "Interface place {} declared {}",
+                        place.name,
+                        place.origin.as_ref().unwrap()
+                    ),
+                });
+            }
+            self.places.push(Place {
+                name: rename(&place.name),
+                origin: origin(&place.origin),
+                ..place.clone()
+            });
+        }
+        for transition in &module.transitions {
+            let name = rename(&transition.name);
+            self.transitions.push(Transition {
+                origin: origin(&transition.origin),
+                ..rename_transition(transition, name, &rename)
+            });
+        }
+        stack.push(module.name.clone());
+        for nested in &module.instances {
+            let arguments = nested.arguments.iter().map(|a| rename(a)).collect();
+            self.instantiate(modules, nested, &prefix, arguments, fusions, stack)?;
+        }
+        stack.pop();
+        fusions.extend(module.fusions.iter().map(|(fusion, declared)| {
+            (fusion.iter().map(|p| rename(p)).collect(), origin(declared))
+        }));
+        Ok(())
+    }
+
+    /// merges the places of `fusion` into the first one, they must agree on
+    /// their initial marking, capacity and colour
+    pub(super) fn fuse(&mut self, fusion: &[Identifier]) -> Result<(), ErrorTypes> {
+        let members = fusion
+            .iter()
+            .map(|name| {
+                self.places
+                    .iter()
+                    .find(|p| &p.name == name)
+                    .ok_or(ErrorTypes::BadModule {
+                        reason: format!("Fusion of undeclared place {}", name),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(p) = members.iter().find(|p| {
+            (p.tokens, p.capacity, &p.colour)
+                != (members[0].tokens, members[0].capacity, &members[0].colour)
+        }) {
+            return Err(ErrorTypes::BadModule {
+                reason: format!(
+                    "Fused places {} and {} have different declarations",
+                    members[0].name, p.name
+                ),
+            });
+        }
+        let representative = fusion[0].clone();
+        let rename = |name: &str| match fusion.iter().any(|f| f == name) {
+            true => representative.clone(),
+            false => name.to_string(),
+        };
+        self.places
+            .retain(|p| p.name == representative || !fusion.contains(&p.name));
+        self.transitions = self
+            .transitions
+            .iter()
+            .map(|t| rename_transition(t, t.name.clone(), &rename))
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        graph_gen::{explore, Place},
+        output_generators::generate_smv_code,
+        petri_parser::parser::PetriNet,
+    };
+
+    const BUFFER: &str = "module Buffer(src, dst)\nplace slot = 0 cap 1\nplace lock = 1\n\ntransition put\ninputs: src lock\noutputs: slot lock\n\ntransition get\ninputs: slot\noutputs: dst\nend\n\n";
+
+    #[test]
+    fn test_flattening() {
+        let code = format!(
+            "{}place a = 1\nplace b = 0\nplace c = 0\ninstance b1 = Buffer(a, b)\ninstance b2 = Buffer(b, c)\nfuse b1.lock b2.lock\n\ntransition peek\ninputs: \noutputs: \nreads: b2.slot\n",
+            BUFFER
+        );
+        let input = PetriNet::new(&code).unwrap().generate_input();
+        assert_eq!(
+            input.m_names,
+            vec!["a", "b", "c", "b1.slot", "b1.lock", "b2.slot"]
+        );
+        assert_eq!(input.capacities[3], Some(1));
+        assert_eq!(
+            input.t_names,
+            vec!["peek", "b1.put", "b1.get", "b2.put", "b2.get"]
+        );
+        // both instances share the lock of b1
+        assert_eq!(input.transitions[3][4], (1, 1));
+        assert_eq!(input.transitions[3][1], (1, 0));
+        assert_eq!(input.read_arcs[0][5], 1);
+    }
+
+    #[test]
+    fn test_flattened_places_in_smv() {
+        let code = format!(
+            "{}place a = 1\nplace b = 0\ninstance b1 = Buffer(a, b)\n",
+            BUFFER
+        );
+        let input = PetriNet::new(&code).unwrap().generate_input();
+        let graph = explore(&input, &Default::default());
+        let smv = generate_smv_code(&graph, &Place::build(&input, &graph).unwrap());
+        assert!(smv.contains("\t\tb1_slot : 0..1;"), "{}", smv);
+        assert!(!smv.contains("b1."), "{}", smv);
+    }
+
+    #[test]
+    fn test_errors_point_to_the_module() {
+        let code =
+            "module M()\n\ntransition t\ninputs: nowhere\noutputs: \nend\ninstance m = M()\n";
+        let error = PetriNet::new(code).unwrap_err().to_string();
+        assert!(
+            error.contains("in module M at line 3, column 1"),
+            "{}",
+            error
+        );
+        let code = format!("{}place a = 1\ninstance b1 = Buffer(a)\n", BUFFER);
+        assert!(PetriNet::new(&code).is_err());
+        // places, capacities and fusions are traced back as well
+        let code = format!(
+            "{}place a = 1\nplace b = 0\ninstance b1 = Buffer(a, b)\ninstance b1 = Buffer(b, a)\n",
+            BUFFER
+        );
+        let error = PetriNet::new(&code).unwrap_err().to_string();
+        assert!(
+            error.contains("Repeating place in module Buffer"),
+            "{}",
+            error
+        );
+        assert!(error.contains("(instance b1)"), "{}", error);
+        let code = "module M()\nplace full = 3 cap 2\nend\ninstance m = M()\n";
+        let error = PetriNet::new(code).unwrap_err().to_string();
+        assert!(
+            error.contains("in module M at line 2, column 1 (instance m)"),
+            "{}",
+            error
+        );
+        let code = "module M()\nplace p = 1\nfuse p q\nend\ninstance m = M()\n";
+        let error = PetriNet::new(code).unwrap_err().to_string();
+        assert!(
+            error.contains("in module M at line 3, column 1"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_repeated_places_in_modules() {
+        let code = "module M(x)\n\ntransition t\ninputs: x x\noutputs: \nend\nplace a = 1\ninstance m = M(a)\n";
+        assert!(PetriNet::new(code).is_err());
+        // two interface places bound to the same place add up
+        let code = "module M(x, y)\n\ntransition t\ninputs: x y\noutputs: \nend\nplace a = 2\ninstance m = M(a, a)\n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.transitions[0], vec![(2, 0)]);
+    }
+}
@@ -1 +1,4 @@
+mod coloured;
+mod composition;
+mod hierarchy;
 pub mod parser;
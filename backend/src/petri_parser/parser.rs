@@ -1,9 +1,19 @@
 use anyhow::Error;
 use iter_tools::Itertools;
-use pest::{iterators::Pairs, Parser};
+use pest::{
+    iterators::{Pair, Pairs},
+    Parser,
+};
 use pest_derive::Parser;
 
-use crate::{error_type::ErrorTypes, graph_gen::Input};
+use super::{
+    coloured::{ColourInit, ColourSet, Guard, Term},
+    hierarchy::{fusion_from_rule, locate, Instance, Module, Origin},
+};
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{Delay, Input},
+};
 
 #[derive(Debug, Parser)]
 #[grammar = "parser.pest"]
@@ -11,10 +21,25 @@ pub struct PestParser;
 
 type Identifier = String;
 
-#[derive(Debug)]
-struct Place {
-    name: Identifier,
-    tokens: i32,
+#[derive(Debug, Clone)]
+pub(super) struct Place {
+    pub(super) name: Identifier,
+    pub(super) tokens: i32,
+    pub(super) capacity: Option<i32>,
+    /// colour set and initial tokens of a coloured place before unfolding
+    pub(super) colour: Option<ColourInit>,
+    /// declaration of the place when it comes from a module
+    pub(super) origin: Option<Origin>,
+}
+
+fn parse_capacity(capacity: Pair<'_, Rule>) -> i32 {
+    capacity
+        .into_inner()
+        .next()
+        .unwrap()
+        .as_str()
+        .parse::<i32>()
+        .unwrap()
 }
 
 impl Place {
@@ -22,6 +47,28 @@ impl Place {
         Place {
             name: inner_rules.next().unwrap().as_str().to_string(),
             tokens: inner_rules.next().unwrap().as_str().parse::<i32>().unwrap(),
+            capacity: inner_rules.next().map(parse_capacity),
+            colour: None,
+            origin: None,
+        }
+    }
+    pub fn from_coloured_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
+        let name = inner_rules.next().unwrap().as_str().to_string();
+        let set = inner_rules.next().unwrap();
+        Place {
+            name,
+            tokens: 0,
+            colour: Some(ColourInit::from_rule(set, inner_rules)),
+            capacity: inner_rules.next().map(parse_capacity),
+            origin: None,
+        }
+    }
+    pub fn validate_place(&self) -> Result<(), ErrorTypes> {
+        match self.capacity {
+            Some(capacity) if self.tokens > capacity => Err(ErrorTypes::CapacityExceeded {
+                place: self.name.clone(),
+            }),
+            _ => Ok(()),
         }
     }
 }
@@ -42,23 +89,36 @@ impl NoPlaceRepetition for Vec<Place> {
         {
             Ok(())
         } else {
-            Err(ErrorTypes::BadPlace)
+            let repeated = self
+                .iter()
+                .enumerate()
+                .find(|(i, p)| self[..*i].iter().any(|q| q.name == p.name))
+                .map(|(_, p)| p)
+                .unwrap();
+            Err(locate(ErrorTypes::BadPlace, &repeated.origin))
         }
     }
 }
 
-#[derive(Debug)]
-struct Entry(String, i32);
+/// arc to a place with its weight and, for coloured places, the colour of
+/// the tokens it carries
+#[derive(Debug, Clone)]
+pub(super) struct Entry(pub(super) String, pub(super) i32, pub(super) Option<Term>);
 
 impl Entry {
     pub fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
-        Entry(
-            inner_rules.next().unwrap().as_str().to_string(),
-            match inner_rules.next() {
-                Some(i) => i.as_str().parse::<i32>().unwrap(),
-                None => 1,
-            },
-        )
+        let mut entry = Entry(inner_rules.next().unwrap().as_str().to_string(), 1, None);
+        for part in inner_rules {
+            match part.as_rule() {
+                Rule::colour_arg => {
+                    entry.2 = Some(Term::from_rule(
+                        &mut part.into_inner().next().unwrap().into_inner(),
+                    ))
+                }
+                _ => entry.1 = part.as_str().parse::<i32>().unwrap(),
+            }
+        }
+        entry
     }
     pub fn place_exists(&self, place: &[Place], t_id: &str) -> Result<(), ErrorTypes> {
         match place.iter().any(|p| p.name == self.0) {
@@ -73,11 +133,23 @@ impl Entry {
     }
 }
 
-#[derive(Debug)]
-struct Transition {
-    name: Identifier,
-    inputs: Vec<Entry>,
-    outputs: Vec<Entry>,
+#[derive(Debug, Clone)]
+pub(super) struct Transition {
+    pub(super) name: Identifier,
+    pub(super) interval: (i32, Option<i32>),
+    pub(super) priority: i32,
+    pub(super) delay: Delay,
+    /// hidden from the observable behaviour (tau)
+    pub(super) internal: bool,
+    pub(super) inputs: Vec<Entry>,
+    pub(super) outputs: Vec<Entry>,
+    pub(super) reads: Vec<Entry>,
+    pub(super) resets: Vec<Entry>,
+    /// colour variables with their colour set
+    pub(super) variables: Vec<(Identifier, Identifier)>,
+    pub(super) guard: Option<Guard>,
+    /// declaration of the transition when it comes from a module
+    pub(super) origin: Option<Origin>,
 }
 
 fn non_repeating_entry(entries: &[Entry]) -> Result<(), ErrorTypes> {
@@ -97,72 +169,178 @@ fn non_repeating_entry(entries: &[Entry]) -> Result<(), ErrorTypes> {
     }
 }
 
+/// weight of the arc between `place` and a transition, 0 when there is none
+fn arc_weight(entries: &[Entry], place: &Place) -> i32 {
+    match entries.iter().find(|Entry(name, _, _)| name == &place.name) {
+        Some(&Entry(_, w, _)) => w,
+        None => 0,
+    }
+}
+
 impl Transition {
     pub fn from_rule(inner_rules: &mut Pairs<'_, Rule>) -> Self {
-        Transition {
+        let mut transition = Transition {
             name: inner_rules.next().unwrap().as_str().to_string(),
-            inputs: inner_rules
-                .next()
-                .unwrap()
-                .into_inner()
-                .into_iter()
-                .map(|r| Entry::from_rule(&mut r.into_inner()))
-                .collect(),
-            outputs: inner_rules
-                .next()
-                .unwrap()
-                .into_inner()
-                .into_iter()
+            interval: (0, None),
+            priority: 0,
+            delay: Delay::Exponential(1.0),
+            internal: false,
+            inputs: vec![],
+            outputs: vec![],
+            reads: vec![],
+            resets: vec![],
+            variables: vec![],
+            guard: None,
+            origin: None,
+        };
+        let entries = |arcs: Pair<'_, Rule>| {
+            arcs.into_inner()
                 .map(|r| Entry::from_rule(&mut r.into_inner()))
-                .collect(),
+                .collect()
+        };
+        for part in inner_rules {
+            match part.as_rule() {
+                Rule::interval => {
+                    let mut bounds = part.into_inner();
+                    let lower = bounds.next().unwrap().as_str().parse::<i32>().unwrap();
+                    let upper = bounds.next().unwrap();
+                    transition.interval = match upper.as_rule() {
+                        Rule::number => (lower, Some(upper.as_str().parse::<i32>().unwrap())),
+                        _ => (lower, None),
+                    }
+                }
+                Rule::priority => {
+                    transition.priority = part
+                        .into_inner()
+                        .next()
+                        .unwrap()
+                        .as_str()
+                        .parse::<i32>()
+                        .unwrap()
+                }
+                Rule::rate => {
+                    transition.delay = Delay::Exponential(
+                        part.into_inner().next().unwrap().as_str().parse().unwrap(),
+                    )
+                }
+                Rule::immediate => {
+                    transition.delay = Delay::Immediate(
+                        part.into_inner()
+                            .next()
+                            .map_or(1.0, |weight| weight.as_str().parse().unwrap()),
+                    )
+                }
+                Rule::internal => transition.internal = true,
+                Rule::inputs => transition.inputs = entries(part),
+                Rule::outputs => transition.outputs = entries(part),
+                Rule::reads => transition.reads = entries(part),
+                Rule::resets => transition.resets = entries(part),
+                Rule::variables => {
+                    transition.variables = part
+                        .into_inner()
+                        .map(|v| {
+                            let mut v = v.into_inner();
+                            let name = v.next().unwrap().as_str().to_string();
+                            (name, v.next().unwrap().as_str().to_string())
+                        })
+                        .collect()
+                }
+                Rule::guard => transition.guard = Some(Guard::from_rule(&mut part.into_inner())),
+                _ => unreachable!(),
+            }
         }
+        transition
     }
     pub fn validate_transition(&self, places: &[Place]) -> Result<(), ErrorTypes> {
+        let (Delay::Exponential(x) | Delay::Immediate(x)) = self.delay;
+        if x <= 0.0 {
+            return Err(ErrorTypes::BadTransition {
+                reason: format!("Non positive rate for transition {}", self.name),
+            });
+        }
+        if let (lower, Some(upper)) = self.interval {
+            if upper < lower {
+                return Err(ErrorTypes::BadTransition {
+                    reason: format!("Empty firing interval for transition {}", self.name),
+                });
+            }
+        }
         non_repeating_entry(&self.inputs)?;
         non_repeating_entry(&self.outputs)?;
-        for entry in &self.inputs {
-            entry.place_exists(places, &self.name)?;
-        }
-        for entry in &self.outputs {
+        non_repeating_entry(&self.reads)?;
+        non_repeating_entry(&self.resets)?;
+        for entry in self
+            .inputs
+            .iter()
+            .chain(&self.outputs)
+            .chain(&self.reads)
+            .chain(&self.resets)
+        {
             entry.place_exists(places, &self.name)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct PetriNet {
-    places: Vec<Place>,
-    transitions: Vec<Transition>,
+    pub(super) colours: Vec<ColourSet>,
+    pub(super) modules: Vec<Module>,
+    pub(super) instances: Vec<Instance>,
+    pub(super) fusions: Vec<Vec<Identifier>>,
+    pub(super) places: Vec<Place>,
+    pub(super) transitions: Vec<Transition>,
 }
 
 impl PetriNet {
     pub fn new(code: &str) -> Result<Self, Error> {
         let rules = PestParser::parse(Rule::petri_net, code)?.next().unwrap();
-        let net = Self::from_rule(&mut rules.into_inner());
+        let net = Self::from_rule(&mut rules.into_inner())?
+            .flatten()?
+            .unfold()?;
         net.validate_petri_net()?;
         Ok(net)
     }
-    fn from_rule(inner_rule: &mut Pairs<'_, Rule>) -> Self {
+    fn from_rule(inner_rule: &mut Pairs<'_, Rule>) -> Result<Self, ErrorTypes> {
+        let mut colours = vec![];
+        let mut modules = vec![];
+        let mut instances = vec![];
+        let mut fusions = vec![];
         let mut transitions = vec![];
         let mut places = vec![];
         for line in inner_rule {
             match line.as_rule() {
+                Rule::colour => colours.push(ColourSet::from_rule(&mut line.into_inner())?),
+                Rule::module => modules.push(Module::from_rule(line)),
+                Rule::instance => instances.push(Instance::from_rule(line)),
+                Rule::fusion => fusions.push(fusion_from_rule(line)),
                 Rule::place => places.push(Place::from_rule(&mut line.into_inner())),
+                Rule::coloured_place => {
+                    places.push(Place::from_coloured_rule(&mut line.into_inner()))
+                }
                 Rule::transition => transitions.push(Transition::from_rule(&mut line.into_inner())),
                 Rule::EOI => (),
                 _ => unreachable!(),
             }
         }
-        PetriNet {
+        Ok(PetriNet {
+            colours,
+            modules,
+            instances,
+            fusions,
             places,
             transitions,
-        }
+        })
     }
-    fn validate_petri_net(&self) -> Result<(), Error> {
+    pub(super) fn validate_petri_net(&self) -> Result<(), Error> {
         self.places.check_for_place_repetion()?;
+        for p in &self.places {
+            p.validate_place()
+                .map_err(|error| locate(error, &p.origin))?;
+        }
         for t in &self.transitions {
-            t.validate_transition(&self.places)?;
+            t.validate_transition(&self.places)
+                .map_err(|error| locate(error, &t.origin))?;
         }
         Ok(())
     }
@@ -175,33 +353,78 @@ impl PetriNet {
 
         let transitions = self
             .transitions
-            .into_iter()
+            .iter()
             .map(|transition| {
                 self.places
                     .iter()
                     .map(|p| {
-                        let input = match transition
-                            .inputs
-                            .iter()
-                            .find(|&Entry(name, _)| name == &p.name)
-                        {
-                            Some(&Entry(_, i)) => i,
-                            None => 0,
-                        };
-                        let output = match transition
-                            .outputs
-                            .iter()
-                            .find(|&Entry(name, _)| name == &p.name)
-                        {
-                            Some(&Entry(_, o)) => o,
-                            None => 0,
-                        };
-                        (input, output)
+                        (
+                            arc_weight(&transition.inputs, p),
+                            arc_weight(&transition.outputs, p),
+                        )
                     })
                     .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
 
+        let read_arcs = self
+            .transitions
+            .iter()
+            .map(|transition| {
+                self.places
+                    .iter()
+                    .map(|p| arc_weight(&transition.reads, p))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let reset_arcs = self
+            .transitions
+            .iter()
+            .map(|transition| {
+                self.places
+                    .iter()
+                    .map(|p| transition.resets.iter().any(|r| r.0 == p.name))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let priorities = self
+            .transitions
+            .iter()
+            .map(|transition| transition.priority)
+            .collect::<Vec<_>>();
+
+        let intervals = self
+            .transitions
+            .iter()
+            .map(|transition| transition.interval)
+            .collect::<Vec<_>>();
+
+        let delays = self
+            .transitions
+            .iter()
+            .map(|transition| transition.delay)
+            .collect::<Vec<_>>();
+
+        let hidden = self
+            .transitions
+            .iter()
+            .map(|transition| transition.internal)
+            .collect::<Vec<_>>();
+
+        let t_names = self
+            .transitions
+            .into_iter()
+            .map(|transition| transition.name)
+            .collect::<Vec<_>>();
+
+        let capacities = self
+            .places
+            .iter()
+            .map(|place| place.capacity)
+            .collect::<Vec<_>>();
+
         let m_names = self
             .places
             .into_iter()
@@ -212,6 +435,14 @@ impl PetriNet {
             m_names,
             m_init,
             transitions,
+            t_names,
+            read_arcs,
+            reset_arcs,
+            capacities,
+            priorities,
+            intervals,
+            delays,
+            hidden,
         }
     }
 }
@@ -221,6 +452,7 @@ mod test {
     use std::fs;
 
     use super::PetriNet;
+    use crate::graph_gen::Delay;
 
     #[test]
     fn test_reading() {
@@ -229,4 +461,65 @@ mod test {
         println!("{:?}", net);
         assert!(true)
     }
+
+    #[test]
+    fn test_read_and_reset_arcs() {
+        let code = "place p0 = 1\nplace p1 = 3\n\ntransition t0\ninputs: p0\noutputs: \nreads: p1:2\nresets: p1\n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.t_names, vec!["t0".to_string()]);
+        assert_eq!(input.transitions, vec![vec![(1, 0), (0, 0)]]);
+        assert_eq!(input.read_arcs, vec![vec![0, 2]]);
+        assert_eq!(input.reset_arcs, vec![vec![false, true]]);
+    }
+
+    #[test]
+    fn test_undeclared_read_place() {
+        let code = "place p0 = 1\n\ntransition t0\ninputs: p0\noutputs: \nreads: p9\n";
+        assert!(PetriNet::new(code).is_err());
+    }
+
+    #[test]
+    fn test_place_capacity() {
+        let code = "place buf = 0 cap 3\nplace p1 = 2\n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.capacities, vec![Some(3), None]);
+        assert!(PetriNet::new("place buf = 4 cap 3\n").is_err());
+    }
+
+    #[test]
+    fn test_transition_priority() {
+        let code = "place p0 = 1\n\ntransition t0 priority 2\ninputs: p0\noutputs: \n\ntransition t1\ninputs: p0\noutputs: \n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.priorities, vec![2, 0]);
+    }
+
+    #[test]
+    fn test_firing_intervals() {
+        let code = "place p0 = 1\n\ntransition t0 [2,5]\ninputs: p0\noutputs: \n\ntransition t1 [0, inf] priority 1\ninputs: p0\noutputs: \n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.intervals, vec![(2, Some(5)), (0, None)]);
+        assert_eq!(input.priorities, vec![0, 1]);
+        assert!(PetriNet::new("transition t0 [5,2]\ninputs: \noutputs: \n").is_err());
+    }
+
+    #[test]
+    fn test_stochastic_delays() {
+        let code = "place p0 = 1\n\ntransition t0 rate 2.5\ninputs: p0\noutputs: \n\ntransition t1 immediate weight 3\ninputs: p0\noutputs: \n\ntransition t2 priority 1 immediate\ninputs: p0\noutputs: \n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(
+            input.delays,
+            vec![
+                Delay::Exponential(2.5),
+                Delay::Immediate(3.0),
+                Delay::Immediate(1.0)
+            ]
+        );
+        assert!(PetriNet::new("transition t0 rate 0\ninputs: \noutputs: \n").is_err());
+    }
+    #[test]
+    fn test_internal_transitions() {
+        let code = "place p0 = 1\n\ntransition t0 internal\ninputs: p0\noutputs: \n\ntransition t1 priority 1 internal\ninputs: p0\noutputs: \n\ntransition t2\ninputs: p0\noutputs: \n";
+        let input = PetriNet::new(code).unwrap().generate_input();
+        assert_eq!(input.hidden, vec![true, true, false]);
+    }
 }
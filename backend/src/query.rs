@@ -0,0 +1,388 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{activate_transition, enabled_transitions, CapacityRule, Input, Marking},
+    state_equation::{check_state_equation, StateEquation},
+};
+
+/// maximum number of markings visited before giving up on a query
+pub const STATE_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    AtLeast,
+    AtMost,
+    Equal,
+}
+
+/// `Σ coefficients[p] * M(p) relation bound`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub coefficients: Vec<i32>,
+    pub relation: Relation,
+    pub bound: i64,
+}
+
+impl Constraint {
+    /// value of the weighted sum, `None` when it involves an omega place
+    fn value(&self, marking: &[Option<i32>]) -> Option<i64> {
+        self.coefficients
+            .iter()
+            .zip(marking)
+            .filter(|(a, _)| **a != 0)
+            .map(|(a, x)| x.map(|x| i64::from(*a) * i64::from(x)))
+            .sum()
+    }
+
+    /// omega places are taken as holding as many tokens as needed
+    fn holds(&self, marking: &[Option<i32>]) -> bool {
+        match (self.value(marking), self.relation) {
+            (None, relation) => relation == Relation::AtLeast,
+            (Some(value), Relation::AtLeast) => value >= self.bound,
+            (Some(value), Relation::AtMost) => value <= self.bound,
+            (Some(value), Relation::Equal) => value == self.bound,
+        }
+    }
+
+    /// largest increase and decrease of the sum a single firing can cause,
+    /// `None` when unbounded (resets of weighted places)
+    fn max_changes(&self, input: &Input) -> (Option<i64>, Option<i64>) {
+        let mut increase = Some(0);
+        let mut decrease = Some(0);
+        for t in 0..input.transitions.len() {
+            let mut change = 0;
+            for (p, (pre, post)) in input.transitions[t].iter().enumerate() {
+                let a = i64::from(self.coefficients[p]);
+                match input.reset_arc(t, p) {
+                    true if a > 0 => decrease = None,
+                    true if a < 0 => increase = None,
+                    _ => change += a * i64::from(post - pre),
+                }
+            }
+            increase = increase.map(|x: i64| x.max(change));
+            decrease = decrease.map(|x: i64| x.max(-change));
+        }
+        (increase, decrease)
+    }
+}
+
+/// what a query searches for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// exactly this marking
+    Marking(Marking),
+    /// at least the tokens of this marking in every place
+    Cover(Marking),
+    /// every constraint holds
+    Predicate(Vec<Constraint>),
+}
+
+impl Target {
+    pub fn constraints(&self) -> Vec<Constraint> {
+        let per_place = |marking: &Marking, relation: Relation| {
+            marking
+                .iter()
+                .enumerate()
+                .filter_map(|(p, x)| {
+                    x.map(|x| Constraint {
+                        coefficients: (0..marking.len()).map(|q| i32::from(p == q)).collect(),
+                        relation,
+                        bound: i64::from(x),
+                    })
+                })
+                .collect()
+        };
+        match self {
+            Target::Marking(marking) => per_place(marking, Relation::Equal),
+            Target::Cover(marking) => per_place(marking, Relation::AtLeast),
+            Target::Predicate(constraints) => constraints.clone(),
+        }
+    }
+
+    /// whether `marking` is one of the searched ones
+    pub fn holds(&self, marking: &[Option<i32>]) -> bool {
+        self.constraints().iter().all(|c| c.holds(marking))
+    }
+
+    /// parses a linear predicate over the places of `input` such as
+    /// `p0 + 2*p1 >= 3`, several constraints are joined by `and`
+    pub fn parse_predicate(input: &Input, predicate: &str) -> Result<Self, String> {
+        predicate
+            .split(" and ")
+            .map(|constraint| {
+                let (relation, (left, right)) = [
+                    (Relation::AtLeast, ">="),
+                    (Relation::AtMost, "<="),
+                    (Relation::Equal, "="),
+                ]
+                .into_iter()
+                .find_map(|(relation, symbol)| {
+                    constraint.split_once(symbol).map(|sides| (relation, sides))
+                })
+                .ok_or(format!("missing >=, <= or = in {}", constraint))?;
+                let mut coefficients = vec![0; input.m_names.len()];
+                for term in left.replace('-', "+-").split('+') {
+                    let term = term.replace(' ', "");
+                    if term.is_empty() {
+                        continue;
+                    }
+                    let (factor, name) = match term.split_once('*') {
+                        Some((factor, name)) => {
+                            (factor.parse::<i32>().map_err(|e| e.to_string())?, name)
+                        }
+                        None => match term.strip_prefix('-') {
+                            Some(name) => (-1, name),
+                            None => (1, term.as_str()),
+                        },
+                    };
+                    let p = input
+                        .m_names
+                        .iter()
+                        .position(|n| n == name)
+                        .ok_or(format!("unknown place {}", name))?;
+                    coefficients[p] += factor;
+                }
+                Ok(Constraint {
+                    coefficients,
+                    relation,
+                    bound: right.trim().parse::<i64>().map_err(|e| e.to_string())?,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Target::Predicate)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    pub capacity_rule: CapacityRule,
+    /// orders the search with a lower bound of the remaining firings derived
+    /// from the state equation (A*) instead of a breadth first search
+    pub guided: bool,
+    /// solves the state equation first and skips the search when it proves
+    /// the target unreachable
+    pub state_equation: bool,
+}
+
+/// a firing sequence reaching a marking satisfying the target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Witness {
+    pub transitions: Vec<usize>,
+    pub marking: Marking,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryOutcome {
+    /// a shortest witness, `None` when no reachable marking satisfies the
+    /// target
+    pub witness: Option<Witness>,
+    /// number of markings the search went through
+    pub explored: usize,
+    /// result of the state equation pre-check when it was asked for
+    pub state_equation: Option<StateEquation>,
+}
+
+/// lower bound of the number of firings before every constraint holds
+/// according to the state equation, `None` when they never can
+struct Heuristic {
+    constraints: Vec<(Constraint, Option<i64>, Option<i64>)>,
+}
+
+impl Heuristic {
+    fn new(input: &Input, constraints: Vec<Constraint>) -> Self {
+        Heuristic {
+            constraints: constraints
+                .into_iter()
+                .map(|c| {
+                    let (increase, decrease) = c.max_changes(input);
+                    (c, increase, decrease)
+                })
+                .collect(),
+        }
+    }
+
+    fn estimate(&self, marking: &[Option<i32>]) -> Option<i64> {
+        let firings = |missing: i64, change: Option<i64>| match change {
+            _ if missing <= 0 => Some(0),
+            None => Some(0),
+            Some(0) => None,
+            Some(change) => Some((missing + change - 1) / change),
+        };
+        let mut estimate = 0;
+        for (constraint, increase, decrease) in &self.constraints {
+            let Some(value) = constraint.value(marking) else {
+                continue;
+            };
+            let up = firings(constraint.bound - value, *increase);
+            let down = firings(value - constraint.bound, *decrease);
+            let needed = match constraint.relation {
+                Relation::AtLeast => up,
+                Relation::AtMost => down,
+                Relation::Equal => up.zip(down).map(|(up, down)| up.max(down)),
+            }?;
+            estimate = estimate.max(needed);
+        }
+        Some(estimate)
+    }
+}
+
+/// searches a shortest firing sequence from the initial marking to a marking
+/// satisfying `target`, markings are generated on the fly and the search
+/// stops at the first marking found
+pub fn find_witness(
+    input: &Input,
+    target: &Target,
+    options: &QueryOptions,
+) -> Result<QueryOutcome, ErrorTypes> {
+    let constraints = target.constraints();
+    let state_equation = options
+        .state_equation
+        .then(|| check_state_equation(input, &constraints));
+    if state_equation == Some(StateEquation::Unreachable) {
+        return Ok(QueryOutcome {
+            witness: None,
+            explored: 0,
+            state_equation,
+        });
+    }
+    let heuristic = Heuristic::new(input, constraints.clone());
+    let estimate = |marking: &[Option<i32>]| match options.guided {
+        true => heuristic.estimate(marking),
+        false => Some(0),
+    };
+
+    let mut markings = vec![input.m_init.clone()];
+    let mut indices = HashMap::from([(input.m_init.clone(), 0)]);
+    // shortest known distance and the last firing leading to each marking
+    let mut distances = vec![0];
+    let mut parents: Vec<Option<(usize, usize)>> = vec![None];
+    let mut queue = BinaryHeap::new();
+    if let Some(h) = estimate(&input.m_init) {
+        queue.push(Reverse((h, 0, 0)));
+    }
+    let mut explored = 0;
+    while let Some(Reverse((_, distance, current))) = queue.pop() {
+        if distance > distances[current] {
+            continue;
+        }
+        explored += 1;
+        if constraints.iter().all(|c| c.holds(&markings[current])) {
+            let mut transitions = vec![];
+            let mut state = current;
+            while let Some((parent, t)) = parents[state] {
+                transitions.push(t);
+                state = parent;
+            }
+            transitions.reverse();
+            return Ok(QueryOutcome {
+                witness: Some(Witness {
+                    transitions,
+                    marking: markings[current].clone(),
+                }),
+                explored,
+                state_equation,
+            });
+        }
+        let marking = markings[current].clone();
+        for t in enabled_transitions(input, &marking, options.capacity_rule) {
+            let next = activate_transition(input, t, &marking, options.capacity_rule).unwrap();
+            let index = match indices.get(&next) {
+                Some(index) if distances[*index] <= distance + 1 => continue,
+                Some(index) => *index,
+                None => {
+                    if markings.len() >= STATE_LIMIT {
+                        return Err(ErrorTypes::StateLimitExceeded { limit: STATE_LIMIT });
+                    }
+                    indices.insert(next.clone(), markings.len());
+                    markings.push(next);
+                    distances.push(distance + 1);
+                    parents.push(None);
+                    markings.len() - 1
+                }
+            };
+            distances[index] = distance + 1;
+            parents[index] = Some((current, t));
+            if let Some(h) = estimate(&markings[index]) {
+                queue.push(Reverse((distance + 1 + h, distance + 1, index)));
+            }
+        }
+    }
+    Ok(QueryOutcome {
+        witness: None,
+        explored,
+        state_equation,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_witness, QueryOptions, Target};
+    use crate::graph_gen::Input;
+    use crate::state_equation::StateEquation;
+
+    /// t0 moves a token from p0 to p1, t1 doubles the tokens of p1 into p2
+    fn input() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(3), Some(0), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1), (0, 0)], vec![(0, 0), (1, 0), (0, 2)]],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shortest_witness() {
+        let target = Target::Marking(vec![Some(1), Some(0), Some(4)]);
+        for guided in [false, true] {
+            let options = QueryOptions {
+                guided,
+                ..Default::default()
+            };
+            let outcome = find_witness(&input(), &target, &options).unwrap();
+            assert_eq!(outcome.witness.unwrap().transitions.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_cover_and_predicate_targets() {
+        let options = QueryOptions {
+            guided: true,
+            ..Default::default()
+        };
+        let cover = Target::Cover(vec![Some(0), Some(1), Some(2)]);
+        let witness = find_witness(&input(), &cover, &options).unwrap().witness;
+        assert_eq!(witness.unwrap().marking, vec![Some(1), Some(1), Some(2)]);
+        let predicate = Target::parse_predicate(&input(), "p2 - p1 >= 6").unwrap();
+        let witness = find_witness(&input(), &predicate, &options)
+            .unwrap()
+            .witness;
+        assert_eq!(witness.unwrap().transitions.len(), 6);
+        let impossible = Target::parse_predicate(&input(), "p0 + p1 >= 4").unwrap();
+        let outcome = find_witness(&input(), &impossible, &options).unwrap();
+        assert_eq!((outcome.witness, outcome.explored), (None, 0));
+    }
+
+    #[test]
+    fn test_state_equation_skips_the_search() {
+        let options = QueryOptions {
+            state_equation: true,
+            ..Default::default()
+        };
+        // p2 only receives tokens two at a time
+        let odd = Target::parse_predicate(&input(), "p0 = 0 and p1 = 0 and p2 = 5").unwrap();
+        let outcome = find_witness(&input(), &odd, &options).unwrap();
+        assert_eq!(outcome.state_equation, Some(StateEquation::Unreachable));
+        assert_eq!((outcome.witness, outcome.explored), (None, 0));
+        let even = Target::Marking(vec![Some(0), Some(0), Some(6)]);
+        let outcome = find_witness(&input(), &even, &options).unwrap();
+        assert_eq!(
+            outcome.state_equation,
+            Some(StateEquation::Candidate(vec![3, 3]))
+        );
+        assert_eq!(outcome.witness.unwrap().transitions.len(), 6);
+    }
+}
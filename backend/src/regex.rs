@@ -0,0 +1,387 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use iter_tools::Itertools;
+
+use crate::{
+    automaton::Automaton,
+    graph_gen::{Input, ReachabilityGraph},
+};
+
+/// regular expression over transition names
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Regex {
+    /// matches no word
+    Empty,
+    /// matches the empty word
+    Epsilon,
+    Symbol(String),
+    Concat(Vec<Regex>),
+    Union(Vec<Regex>),
+    Star(Box<Regex>),
+}
+
+impl Regex {
+    /// factors of a concatenation, none for the empty word
+    fn factors(&self) -> Vec<Regex> {
+        match self {
+            Regex::Epsilon => vec![],
+            Regex::Concat(parts) => parts.clone(),
+            r => vec![r.clone()],
+        }
+    }
+
+    fn from_factors(factors: &[Regex]) -> Regex {
+        match factors {
+            [] => Regex::Epsilon,
+            [r] => r.clone(),
+            _ => Regex::Concat(factors.to_vec()),
+        }
+    }
+
+    /// `r` when `self` is `r r*` or `r* r`
+    fn as_plus(&self) -> Option<Regex> {
+        let factors = self.factors();
+        let (first, last) = (factors.first()?, factors.last()?);
+        match (first, last) {
+            (_, Regex::Star(r)) if Regex::from_factors(&factors[..factors.len() - 1]) == **r => {
+                Some(*r.clone())
+            }
+            (Regex::Star(r), _) if Regex::from_factors(&factors[1..]) == **r => Some(*r.clone()),
+            _ => None,
+        }
+    }
+
+    /// union of `parts` without duplicates, `ε | r r*` written `r*`, the
+    /// `ε` dropped next to a star and the prefix and suffix common to every
+    /// alternative factored out
+    fn alternatives(parts: Vec<Regex>) -> Regex {
+        let mut parts = parts.into_iter().unique().collect::<Vec<_>>();
+        if parts.contains(&Regex::Epsilon) {
+            for part in parts.iter_mut() {
+                if let Some(r) = part.as_plus() {
+                    *part = r.star();
+                }
+            }
+            if parts.iter().any(|part| matches!(part, Regex::Star(_))) {
+                parts.retain(|part| *part != Regex::Epsilon);
+            }
+            parts = parts.into_iter().unique().collect();
+        }
+        if parts.len() > 1 {
+            let factors = parts.iter().map(Regex::factors).collect::<Vec<_>>();
+            let shortest = factors.iter().map(Vec::len).min().unwrap();
+            let prefix = (0..shortest)
+                .take_while(|i| factors.iter().all(|f| f[*i] == factors[0][*i]))
+                .count();
+            let suffix = (0..shortest - prefix)
+                .take_while(|i| {
+                    let last = &factors[0][factors[0].len() - 1 - i];
+                    factors.iter().all(|f| f[f.len() - 1 - i] == *last)
+                })
+                .count();
+            if prefix + suffix > 0 {
+                let middle = factors
+                    .iter()
+                    .map(|f| Regex::from_factors(&f[prefix..f.len() - suffix]))
+                    .fold(Regex::Empty, Regex::union);
+                let end = factors[0].len() - suffix;
+                return Regex::from_factors(&factors[0][..prefix])
+                    .concat(middle)
+                    .concat(Regex::from_factors(&factors[0][end..]));
+            }
+        }
+        match parts.len() {
+            0 => Regex::Empty,
+            1 => parts.pop().unwrap(),
+            _ => Regex::Union(parts),
+        }
+    }
+
+    pub fn union(self, other: Regex) -> Regex {
+        let parts = [self, other]
+            .into_iter()
+            .flat_map(|r| match r {
+                Regex::Empty => vec![],
+                Regex::Union(parts) => parts,
+                r => vec![r],
+            })
+            .collect();
+        Regex::alternatives(parts)
+    }
+
+    /// `r* r*` written `r*`
+    pub fn concat(self, other: Regex) -> Regex {
+        if self == Regex::Empty || other == Regex::Empty {
+            return Regex::Empty;
+        }
+        let mut factors: Vec<Regex> = vec![];
+        for factor in self.factors().into_iter().chain(other.factors()) {
+            if matches!(factor, Regex::Star(_)) && factors.last() == Some(&factor) {
+                continue;
+            }
+            factors.push(factor);
+        }
+        Regex::from_factors(&factors)
+    }
+
+    /// `(ε | r)*` and `(r* | s)*` written `r*` and `(r | s)*`
+    pub fn star(self) -> Regex {
+        let inner = match self {
+            Regex::Empty | Regex::Epsilon => return Regex::Epsilon,
+            Regex::Star(r) => return Regex::Star(r),
+            Regex::Union(parts) => parts
+                .into_iter()
+                .filter(|part| *part != Regex::Epsilon)
+                .map(|part| match part {
+                    Regex::Star(r) => *r,
+                    part => part,
+                })
+                .fold(Regex::Empty, Regex::union),
+            r => r,
+        };
+        match inner {
+            Regex::Empty | Regex::Epsilon => Regex::Epsilon,
+            Regex::Star(r) => Regex::Star(r),
+            r => Regex::Star(Box::new(r)),
+        }
+    }
+
+    /// number of symbols
+    pub fn size(&self) -> usize {
+        match self {
+            Regex::Empty | Regex::Epsilon => 0,
+            Regex::Symbol(_) => 1,
+            Regex::Concat(parts) | Regex::Union(parts) => parts.iter().map(Regex::size).sum(),
+            Regex::Star(r) => r.size(),
+        }
+    }
+
+    /// binding strength, unions bind the loosest
+    fn precedence(&self) -> u8 {
+        match self {
+            Regex::Union(_) => 0,
+            Regex::Concat(_) => 1,
+            Regex::Star(_) => 2,
+            _ => 3,
+        }
+    }
+
+    fn fmt_within(&self, f: &mut fmt::Formatter<'_>, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "(")?;
+            self.fmt_within(f, 0)?;
+            return write!(f, ")");
+        }
+        let join = |f: &mut fmt::Formatter<'_>, parts: &[Regex], sep: &str, precedence| {
+            for (i, part) in parts.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", sep)?;
+                }
+                part.fmt_within(f, precedence)?;
+            }
+            Ok(())
+        };
+        match self {
+            Regex::Empty => write!(f, "∅"),
+            Regex::Epsilon => write!(f, "ε"),
+            Regex::Symbol(name) => write!(f, "{}", name),
+            Regex::Concat(parts) => join(f, parts, " ", 2),
+            Regex::Union(parts) => join(f, parts, " | ", 1),
+            Regex::Star(r) => {
+                r.fmt_within(f, 3)?;
+                write!(f, "*")
+            }
+        }
+    }
+}
+
+/// concatenation written with spaces, `|` for unions and `*` for stars
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_within(f, 0)
+    }
+}
+
+/// order in which the states are eliminated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Elimination {
+    /// by increasing state number
+    Index,
+    /// the state whose removal adds the fewest symbols first (Delgado and
+    /// Morais), ties broken by state number
+    #[default]
+    Weight,
+}
+
+impl FromStr for Elimination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(Elimination::Index),
+            "weight" => Ok(Elimination::Weight),
+            _ => Err(format!(
+                "unknown elimination order {}, expected index or weight",
+                s
+            )),
+        }
+    }
+}
+
+/// generalised automaton whose arcs are labelled by expressions
+struct Arcs {
+    /// arcs leaving each state
+    outgoing: Vec<BTreeMap<usize, Regex>>,
+    /// states with an arc into each state
+    incoming: Vec<BTreeSet<usize>>,
+}
+
+impl Arcs {
+    fn add(&mut self, from: usize, to: usize, r: Regex) {
+        let previous = self.outgoing[from].remove(&to).unwrap_or(Regex::Empty);
+        self.outgoing[from].insert(to, previous.union(r));
+        self.incoming[to].insert(from);
+    }
+
+    /// symbols added to the expression by eliminating `k`
+    fn weight(&self, k: usize) -> usize {
+        let around = self.outgoing[k].get(&k).map_or(0, Regex::size);
+        let outgoing = self.outgoing[k]
+            .iter()
+            .filter(|(q, _)| **q != k)
+            .map(|(_, r)| r.size())
+            .collect::<Vec<_>>();
+        let incoming = self.incoming[k]
+            .iter()
+            .filter(|p| **p != k)
+            .map(|p| self.outgoing[*p][&k].size())
+            .collect::<Vec<_>>();
+        let (ins, outs) = (incoming.len(), outgoing.len());
+        incoming.iter().sum::<usize>() * outs.saturating_sub(1)
+            + outgoing.iter().sum::<usize>() * ins.saturating_sub(1)
+            + around * (ins * outs).saturating_sub(1)
+    }
+
+    fn eliminate(&mut self, k: usize) {
+        let around = self.outgoing[k].remove(&k).unwrap_or(Regex::Empty).star();
+        let outgoing = std::mem::take(&mut self.outgoing[k]);
+        for q in outgoing.keys() {
+            self.incoming[*q].remove(&k);
+        }
+        for p in std::mem::take(&mut self.incoming[k]) {
+            let Some(into) = self.outgoing[p].remove(&k) else {
+                continue;
+            };
+            for (q, out) in &outgoing {
+                let through = into.clone().concat(around.clone()).concat(out.clone());
+                self.add(p, *q, through);
+            }
+        }
+    }
+}
+
+/// language of `automaton` by state elimination
+pub fn to_regex(automaton: &Automaton, order: Elimination) -> Regex {
+    let count = automaton.names.len();
+    let (start, end) = (count, count + 1);
+    let mut arcs = Arcs {
+        outgoing: vec![BTreeMap::new(); count + 2],
+        incoming: vec![BTreeSet::new(); count + 2],
+    };
+    arcs.add(start, automaton.lts.initial, Regex::Epsilon);
+    for (s, next) in automaton.lts.edges.iter().enumerate() {
+        if automaton.accepting[s] {
+            arcs.add(s, end, Regex::Epsilon);
+        }
+        for (label, t) in next {
+            let symbol = label.clone().map_or(Regex::Epsilon, Regex::Symbol);
+            arcs.add(s, *t, symbol);
+        }
+    }
+    let mut remaining = (0..count).collect::<BTreeSet<_>>();
+    while let Some(k) = match order {
+        Elimination::Index => remaining.first().copied(),
+        Elimination::Weight => remaining.iter().copied().min_by_key(|k| arcs.weight(*k)),
+    } {
+        remaining.remove(&k);
+        arcs.eliminate(k);
+    }
+    arcs.outgoing[start].remove(&end).unwrap_or(Regex::Empty)
+}
+
+/// firing language of the graph of `input` over the names of its visible
+/// steps, from the minimal automaton whose states all accept
+pub fn firing_language(input: &Input, graph: &ReachabilityGraph) -> Regex {
+    let automaton = Automaton::from_graph(input, graph, |_| true).minimise();
+    to_regex(&automaton, Elimination::Weight)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{firing_language, to_regex, Elimination, Regex};
+    use crate::{
+        automaton::Automaton,
+        graph_gen::{explore, Input},
+    };
+
+    fn symbol(name: &str) -> Regex {
+        Regex::Symbol(name.to_string())
+    }
+
+    #[test]
+    fn test_simplifications() {
+        let (a, b, c) = (symbol("a"), symbol("b"), symbol("c"));
+        let plus = a.clone().concat(a.clone().star());
+        assert_eq!(Regex::Epsilon.union(plus), a.clone().star());
+        assert_eq!(Regex::Epsilon.union(a.clone()).star(), a.clone().star());
+        assert_eq!(a.clone().star().concat(a.clone().star()), a.clone().star());
+        assert_eq!(a.clone().union(a.clone()), a);
+        let factored = a
+            .clone()
+            .concat(b.clone())
+            .union(a.clone().concat(c.clone()));
+        assert_eq!(factored.to_string(), "a (b | c)");
+        let factored = b.concat(a.clone()).union(c.concat(a));
+        assert_eq!(factored.to_string(), "(b | c) a");
+    }
+
+    #[test]
+    fn test_elimination_orders() {
+        let automaton = Automaton::from_dsl(
+            "initial idle\nidle -make-> busy\nbusy -send-> idle\nbusy -drop-> idle\nfinal idle\n",
+        )
+        .unwrap();
+        assert_eq!(
+            to_regex(&automaton, Elimination::Index).to_string(),
+            "ε | make ((send | drop) make)* (send | drop)"
+        );
+        assert_eq!(
+            to_regex(&automaton, Elimination::Weight).to_string(),
+            "(make (send | drop))*"
+        );
+        // b never accepts, only the empty word is left
+        let stuck = Automaton::from_dsl("initial a\na -x-> b\nb -y-> b\nfinal a\n").unwrap();
+        assert_eq!(to_regex(&stuck, Elimination::Weight), Regex::Epsilon);
+    }
+
+    #[test]
+    fn test_firing_language() {
+        // p0 -make-> p1 -send-> p0
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1)], vec![(0, 1), (1, 0)]],
+            t_names: vec!["make".to_string(), "send".to_string()],
+            ..Default::default()
+        };
+        let graph = explore(&input, &Default::default());
+        assert_eq!(
+            firing_language(&input, &graph).to_string(),
+            "(make send)* (ε | make)"
+        );
+    }
+}
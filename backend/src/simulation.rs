@@ -0,0 +1,323 @@
+use std::str::FromStr;
+
+use crate::graph_gen::{
+    activate_transition, enabled_transitions, shortages, CapacityRule, Delay, Input, Marking,
+    Shortage,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// how the transition to fire is picked among the enabled ones
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    #[default]
+    Uniform,
+    /// immediate transitions first, picked according to their weights,
+    /// then the timed ones according to their rates
+    Weighted,
+}
+
+impl FromStr for Choice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Choice::Uniform),
+            "weighted" => Ok(Choice::Weighted),
+            _ => Err(format!(
+                "unknown choice {}, expected uniform or weighted",
+                s
+            )),
+        }
+    }
+}
+
+/// settings of a batch of random runs
+#[derive(Debug, Clone)]
+pub struct SimulationOptions {
+    pub runs: usize,
+    /// maximum number of firings of a run
+    pub length: usize,
+    /// makes the runs reproducible
+    pub seed: Option<u64>,
+    pub choice: Choice,
+    pub capacity_rule: CapacityRule,
+}
+
+/// a random firing sequence from the initial marking
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub transitions: Vec<usize>,
+    /// marking before the first firing and after each one
+    pub markings: Vec<Marking>,
+    /// the run stopped before its length as no transition was enabled
+    pub deadlock: bool,
+}
+
+fn pick<R: Rng>(input: &Input, enabled: &[usize], choice: Choice, rng: &mut R) -> usize {
+    match choice {
+        Choice::Uniform => *enabled.choose(rng).unwrap(),
+        Choice::Weighted => {
+            let immediate = enabled
+                .iter()
+                .copied()
+                .filter(|t| matches!(input.delay(*t), Delay::Immediate(_)))
+                .collect::<Vec<_>>();
+            let candidates = match immediate.is_empty() {
+                true => enabled,
+                false => &immediate,
+            };
+            *candidates
+                .choose_weighted(rng, |t| {
+                    let (Delay::Exponential(w) | Delay::Immediate(w)) = input.delay(*t);
+                    w
+                })
+                .unwrap()
+        }
+    }
+}
+
+/// fires up to `length` transitions picked at random among the enabled ones
+pub fn simulate<R: Rng>(
+    input: &Input,
+    length: usize,
+    choice: Choice,
+    rule: CapacityRule,
+    rng: &mut R,
+) -> Run {
+    let mut run = Run {
+        transitions: vec![],
+        markings: vec![input.m_init.clone()],
+        deadlock: false,
+    };
+    for _ in 0..length {
+        let marking = run.markings.last().unwrap();
+        let enabled = enabled_transitions(input, marking, rule);
+        if enabled.is_empty() {
+            run.deadlock = true;
+            break;
+        }
+        let t = pick(input, &enabled, choice, rng);
+        let next = activate_transition(input, t, marking, rule).unwrap();
+        run.transitions.push(t);
+        run.markings.push(next);
+    }
+    run
+}
+
+pub fn simulate_runs(input: &Input, options: &SimulationOptions) -> Vec<Run> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    (0..options.runs)
+        .map(|_| {
+            simulate(
+                input,
+                options.length,
+                options.choice,
+                options.capacity_rule,
+                &mut rng,
+            )
+        })
+        .collect()
+}
+
+/// the step of a trace that cannot be replayed, steps count from 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayFailure {
+    UnknownTransition {
+        step: usize,
+        name: String,
+    },
+    NotEnabled {
+        step: usize,
+        transition: usize,
+        shortages: Vec<Shortage>,
+    },
+    /// the transition is enabled but transitions of a higher priority are too
+    Preempted {
+        step: usize,
+        transition: usize,
+        by: Vec<usize>,
+    },
+}
+
+impl ReplayFailure {
+    pub fn describe(&self, input: &Input) -> String {
+        match self {
+            ReplayFailure::UnknownTransition { step, name } => {
+                format!("step {}: unknown transition {}", step, name)
+            }
+            ReplayFailure::NotEnabled {
+                step,
+                transition,
+                shortages,
+            } => format!(
+                "step {}: {} is not enabled, {}",
+                step,
+                input.transition_name(*transition),
+                shortages
+                    .iter()
+                    .map(|shortage| match shortage {
+                        Shortage::Tokens {
+                            place,
+                            required,
+                            available,
+                        } => format!(
+                            "{} lacks {} token(s) ({} needed, {} available)",
+                            input.m_names[*place],
+                            required - available,
+                            required,
+                            available
+                        ),
+                        Shortage::Capacity {
+                            place,
+                            capacity,
+                            reached,
+                        } => format!(
+                            "{} would hold {} token(s) over its capacity of {}",
+                            input.m_names[*place], reached, capacity
+                        ),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ReplayFailure::Preempted {
+                step,
+                transition,
+                by,
+            } => format!(
+                "step {}: {} is preempted by {} of higher priority",
+                step,
+                input.transition_name(*transition),
+                by.iter()
+                    .map(|t| input.transition_name(*t))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// the markings reached by a replayed trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    /// marking before the first step and after each replayed step
+    pub markings: Vec<Marking>,
+    /// the first step that could not be replayed
+    pub failure: Option<ReplayFailure>,
+}
+
+/// fires the transitions named in `trace` one after the other from the
+/// initial marking and stops at the first one that cannot fire
+pub fn replay<S: AsRef<str>>(input: &Input, trace: &[S], rule: CapacityRule) -> Replay {
+    let mut replay = Replay {
+        markings: vec![input.m_init.clone()],
+        failure: None,
+    };
+    for (step, name) in trace.iter().enumerate() {
+        let marking = replay.markings.last().unwrap();
+        let Some(t) =
+            (0..input.transitions.len()).find(|t| input.transition_name(*t) == name.as_ref())
+        else {
+            replay.failure = Some(ReplayFailure::UnknownTransition {
+                step,
+                name: name.as_ref().to_string(),
+            });
+            break;
+        };
+        let enabled = enabled_transitions(input, marking, rule);
+        match activate_transition(input, t, marking, rule) {
+            Some(next) if enabled.contains(&t) => replay.markings.push(next),
+            Some(_) => {
+                replay.failure = Some(ReplayFailure::Preempted {
+                    step,
+                    transition: t,
+                    by: enabled,
+                });
+                break;
+            }
+            None => {
+                replay.failure = Some(ReplayFailure::NotEnabled {
+                    step,
+                    transition: t,
+                    shortages: shortages(input, t, marking, rule).collect(),
+                });
+                break;
+            }
+        }
+    }
+    replay
+}
+
+#[cfg(test)]
+mod test {
+    use super::{replay, simulate_runs, Choice, ReplayFailure, Shortage, SimulationOptions};
+    use crate::graph_gen::{CapacityRule, Delay, Input};
+
+    /// t0 and t1 both move the token of p0, only t1 has a weight
+    fn input() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(1), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1)], vec![(1, 0), (0, 1)]],
+            delays: vec![Delay::Exponential(1.0), Delay::Immediate(1.0)],
+            ..Default::default()
+        }
+    }
+
+    fn options(choice: Choice) -> SimulationOptions {
+        SimulationOptions {
+            runs: 20,
+            length: 5,
+            seed: Some(7),
+            choice,
+            capacity_rule: CapacityRule::Strict,
+        }
+    }
+
+    #[test]
+    fn test_runs_stop_on_deadlock() {
+        let runs = simulate_runs(&input(), &options(Choice::Uniform));
+        assert_eq!(runs.len(), 20);
+        assert!(runs
+            .iter()
+            .all(|run| run.deadlock && run.transitions.len() == 1));
+        assert_eq!(
+            runs[0].markings,
+            vec![vec![Some(1), Some(0)], vec![Some(0), Some(1)]]
+        );
+        assert_eq!(runs, simulate_runs(&input(), &options(Choice::Uniform)));
+    }
+
+    #[test]
+    fn test_weighted_choice_prefers_immediate_transitions() {
+        let runs = simulate_runs(&input(), &options(Choice::Weighted));
+        assert!(runs.iter().all(|run| run.transitions == vec![1]));
+    }
+
+    #[test]
+    fn test_replay_reports_missing_tokens() {
+        let mut input = input();
+        input.t_names = vec!["a".to_string(), "b".to_string()];
+        input.transitions[1] = vec![(0, 1), (2, 0)];
+        let replay = replay(&input, &["a", "b"], CapacityRule::Strict);
+        assert_eq!(replay.markings.len(), 2);
+        assert_eq!(
+            replay.failure,
+            Some(ReplayFailure::NotEnabled {
+                step: 1,
+                transition: 1,
+                shortages: vec![Shortage::Tokens {
+                    place: 1,
+                    required: 2,
+                    available: 1
+                }]
+            })
+        );
+        assert_eq!(
+            replay.failure.unwrap().describe(&input),
+            "step 1: b is not enabled, p1 lacks 1 token(s) (2 needed, 1 available)"
+        );
+    }
+}
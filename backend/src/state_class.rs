@@ -0,0 +1,364 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{activate_transition, is_enabled, CapacityRule, Input, Marking, ReachabilityGraph},
+};
+
+/// stands for an infinite bound in a firing domain
+const INF: i64 = i64::MAX / 4;
+
+/// maximum number of classes built before giving up on the net
+pub const CLASS_LIMIT: usize = 100_000;
+
+fn add(a: i64, b: i64) -> i64 {
+    if a >= INF || b >= INF {
+        INF
+    } else {
+        a + b
+    }
+}
+
+/// firing domain of a state class as a difference bound matrix:
+/// `bounds[i][j]` is the upper bound of `θi - θj`, variable 0 is the
+/// constant 0 and variable `k + 1` the firing time of the k-th variable of
+/// the class
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Dbm {
+    bounds: Vec<Vec<i64>>,
+}
+
+impl Dbm {
+    fn unconstrained(variables: usize) -> Self {
+        let size = variables + 1;
+        Dbm {
+            bounds: (0..size)
+                .map(|i| (0..size).map(|j| if i == j { 0 } else { INF }).collect())
+                .collect(),
+        }
+    }
+
+    /// tightens every bound (Floyd–Warshall), `false` when the domain is empty
+    fn close(&mut self) -> bool {
+        let size = self.bounds.len();
+        for k in 0..size {
+            for i in 0..size {
+                for j in 0..size {
+                    let through_k = add(self.bounds[i][k], self.bounds[k][j]);
+                    if through_k < self.bounds[i][j] {
+                        self.bounds[i][j] = through_k;
+                    }
+                }
+            }
+        }
+        (0..size).all(|i| self.bounds[i][i] >= 0)
+    }
+
+    /// earliest and latest value of the variable `k`, `None` for infinity
+    pub fn interval(&self, k: usize) -> (i64, Option<i64>) {
+        let upper = self.bounds[k + 1][0];
+        (-self.bounds[0][k + 1], (upper < INF).then_some(upper))
+    }
+}
+
+/// a marking with the firing domain of its enabled transitions
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StateClass {
+    pub marking: Marking,
+    /// the enabled transitions, variable `k` of the domain is `enabled[k]`
+    pub enabled: Vec<usize>,
+    /// firing domain, holds one more variable than `enabled` when the
+    /// class keeps track of the elapsed time
+    pub domain: Dbm,
+}
+
+impl StateClass {
+    fn initial(input: &Input, rule: CapacityRule, clock: bool) -> Self {
+        let enabled = locally_enabled(input, &input.m_init, rule);
+        let mut domain = Dbm::unconstrained(enabled.len() + usize::from(clock));
+        for (k, t) in enabled.iter().enumerate() {
+            let (lower, upper) = input.interval(*t);
+            domain.bounds[k + 1][0] = upper.map_or(INF, i64::from);
+            domain.bounds[0][k + 1] = -i64::from(lower);
+        }
+        if clock {
+            let c = enabled.len() + 1;
+            domain.bounds[c][0] = 0;
+            domain.bounds[0][c] = 0;
+        }
+        domain.close();
+        StateClass {
+            marking: input.m_init.clone(),
+            enabled,
+            domain,
+        }
+    }
+
+    fn has_clock(&self) -> bool {
+        self.domain.bounds.len() == self.enabled.len() + 2
+    }
+
+    /// earliest and latest time elapsed since the initial class
+    pub fn elapsed(&self) -> Option<(i64, Option<i64>)> {
+        if !self.has_clock() {
+            return None;
+        }
+        let c = self.enabled.len() + 1;
+        let latest = self.domain.bounds[0][c];
+        Some((-self.domain.bounds[c][0], (latest < INF).then_some(latest)))
+    }
+
+    /// the class reached by firing `t` first, `None` when another enabled
+    /// transition has to fire before `t` in every timing of the class
+    pub fn fire(&self, input: &Input, t: usize, rule: CapacityRule) -> Option<Self> {
+        let f = self.enabled.iter().position(|e| *e == t)? + 1;
+        let mut domain = self.domain.clone();
+        for i in 1..=self.enabled.len() {
+            if i != f && domain.bounds[f][i] > 0 {
+                domain.bounds[f][i] = 0;
+            }
+        }
+        if !domain.close() {
+            return None;
+        }
+
+        let marking = activate_transition(input, t, &self.marking, rule)?;
+        // marking once the tokens of `t` have been consumed
+        let intermediate = self
+            .marking
+            .iter()
+            .enumerate()
+            .map(|(p, y)| match input.reset_arc(t, p) {
+                true => Some(0),
+                false => y.map(|y| y - input.transitions[t][p].0),
+            })
+            .collect::<Vec<_>>();
+        let enabled = locally_enabled(input, &marking, rule);
+
+        // variable of the old domain each new variable comes from, `None`
+        // for the newly enabled transitions
+        let mut origins = enabled
+            .iter()
+            .map(|e| {
+                let persistent = *e != t && is_enabled(input, *e, &intermediate, rule);
+                persistent
+                    .then(|| self.enabled.iter().position(|o| o == e))
+                    .flatten()
+                    .map(|k| k + 1)
+            })
+            .collect::<Vec<_>>();
+        if self.has_clock() {
+            origins.push(Some(self.enabled.len() + 1));
+        }
+
+        let mut next = Dbm::unconstrained(origins.len());
+        for (i, origin) in origins.iter().enumerate() {
+            match origin {
+                Some(o) => {
+                    // θ' = θ - θf for the persistent variables
+                    next.bounds[i + 1][0] = domain.bounds[*o][f];
+                    next.bounds[0][i + 1] = domain.bounds[f][*o];
+                    for (j, other) in origins.iter().enumerate() {
+                        if let Some(other) = other {
+                            next.bounds[i + 1][j + 1] = domain.bounds[*o][*other];
+                        }
+                    }
+                }
+                None => {
+                    let (lower, upper) = input.interval(enabled[i]);
+                    next.bounds[i + 1][0] = upper.map_or(INF, i64::from);
+                    next.bounds[0][i + 1] = -i64::from(lower);
+                }
+            }
+        }
+        next.close();
+        Some(StateClass {
+            marking,
+            enabled,
+            domain: next,
+        })
+    }
+
+    /// only keeps the timings where at most `horizon` time units elapsed,
+    /// `false` when there are none, as time never goes back this keeps the
+    /// number of classes finite without losing any run within `horizon`
+    fn restrict(&mut self, horizon: i64) -> bool {
+        if !self.has_clock() {
+            return true;
+        }
+        let c = self.enabled.len() + 1;
+        self.domain.bounds[0][c] = self.domain.bounds[0][c].min(horizon);
+        self.domain.close()
+    }
+
+    /// the transitions that can fire first from the class
+    pub fn firable(&self, input: &Input, rule: CapacityRule) -> Vec<usize> {
+        self.enabled
+            .iter()
+            .copied()
+            .filter(|t| self.fire(input, *t, rule).is_some())
+            .collect()
+    }
+}
+
+fn locally_enabled(input: &Input, marking: &[Option<i32>], rule: CapacityRule) -> Vec<usize> {
+    (0..input.transitions.len())
+        .filter(|t| is_enabled(input, *t, marking, rule))
+        .collect()
+}
+
+/// the state class graph of a time petri net (Berthomieu–Diaz)
+#[derive(Debug, Clone)]
+pub struct StateClassGraph {
+    pub classes: Vec<StateClass>,
+    /// successors of each class labelled by the fired transition
+    pub edges: Vec<Vec<(usize, usize)>>,
+}
+
+impl StateClassGraph {
+    pub fn to_reachability_graph(&self) -> ReachabilityGraph {
+        ReachabilityGraph {
+            initial: 0,
+            markings: self.classes.iter().map(|c| c.marking.clone()).collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|next| next.iter().map(|(t, n)| (vec![*t], *n)).collect())
+                .collect(),
+        }
+    }
+}
+
+/// builds the state class graph of `input` using the static intervals of
+/// its transitions, priorities and steps are not taken into account
+pub fn build_state_class_graph(
+    input: &Input,
+    rule: CapacityRule,
+) -> Result<StateClassGraph, ErrorTypes> {
+    let initial = StateClass::initial(input, rule, false);
+    let mut indices = HashMap::from([(initial.clone(), 0)]);
+    let mut graph = StateClassGraph {
+        classes: vec![initial],
+        edges: vec![],
+    };
+    let mut current = 0;
+    while current < graph.classes.len() {
+        let class = graph.classes[current].clone();
+        let mut next = vec![];
+        for t in &class.enabled {
+            let Some(successor) = class.fire(input, *t, rule) else {
+                continue;
+            };
+            let index = match indices.get(&successor) {
+                Some(index) => *index,
+                None => {
+                    if graph.classes.len() >= CLASS_LIMIT {
+                        return Err(ErrorTypes::StateLimitExceeded { limit: CLASS_LIMIT });
+                    }
+                    indices.insert(successor.clone(), graph.classes.len());
+                    graph.classes.push(successor);
+                    graph.classes.len() - 1
+                }
+            };
+            next.push((*t, index));
+        }
+        graph.edges.push(next);
+        current += 1;
+    }
+    Ok(graph)
+}
+
+/// a firing sequence reaching a marking with the time it may take
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedWitness {
+    pub sequence: Vec<usize>,
+    pub earliest: i64,
+    pub latest: Option<i64>,
+}
+
+/// searches a firing sequence reaching `target` in at most `horizon` time
+/// units, the shortest one in number of firings is returned
+pub fn reachable_within(
+    input: &Input,
+    target: &[Option<i32>],
+    horizon: i64,
+    rule: CapacityRule,
+) -> Result<Option<TimedWitness>, ErrorTypes> {
+    let mut initial = StateClass::initial(input, rule, true);
+    initial.restrict(horizon);
+    let mut parents: HashMap<StateClass, Option<(StateClass, usize)>> =
+        HashMap::from([(initial.clone(), None)]);
+    let mut queue = VecDeque::from([initial]);
+    while let Some(class) = queue.pop_front() {
+        let (earliest, latest) = class.elapsed().unwrap();
+        if class.marking == target {
+            let mut sequence = vec![];
+            let mut current = &class;
+            while let Some((parent, t)) = &parents[current] {
+                sequence.push(*t);
+                current = parent;
+            }
+            sequence.reverse();
+            return Ok(Some(TimedWitness {
+                sequence,
+                earliest,
+                latest,
+            }));
+        }
+        for t in &class.enabled {
+            let Some(mut successor) = class.fire(input, *t, rule) else {
+                continue;
+            };
+            if !successor.restrict(horizon) || parents.contains_key(&successor) {
+                continue;
+            }
+            if parents.len() >= CLASS_LIMIT {
+                return Err(ErrorTypes::StateLimitExceeded { limit: CLASS_LIMIT });
+            }
+            parents.insert(successor.clone(), Some((class.clone(), *t)));
+            queue.push_back(successor);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_state_class_graph, reachable_within};
+    use crate::graph_gen::{CapacityRule, Input};
+
+    /// t0 [2,3] and t1 [1,1] compete for the token of p0
+    fn input() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(1), Some(0), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1), (0, 0)], vec![(1, 0), (0, 0), (0, 1)]],
+            intervals: vec![(2, Some(3)), (1, Some(1))],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_urgent_transition_wins() {
+        let graph = build_state_class_graph(&input(), CapacityRule::Strict).unwrap();
+        assert_eq!(graph.classes.len(), 2);
+        assert_eq!(graph.edges[0], vec![(1, 1)]);
+        assert_eq!(graph.classes[1].marking, vec![Some(0), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_reachable_within() {
+        let mut input = input();
+        input.intervals = vec![(2, Some(3)), (1, None)];
+        let target = [Some(0), Some(1), Some(0)];
+        let witness = reachable_within(&input, &target, 10, CapacityRule::Strict)
+            .unwrap()
+            .unwrap();
+        assert_eq!(witness.sequence, vec![0]);
+        assert_eq!((witness.earliest, witness.latest), (2, Some(3)));
+        assert_eq!(
+            reachable_within(&input, &target, 1, CapacityRule::Strict).unwrap(),
+            None
+        );
+    }
+}
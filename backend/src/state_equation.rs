@@ -0,0 +1,117 @@
+use crate::{
+    graph_gen::Input,
+    ilp::{solve_integer, IlpResult, LinearProgram},
+    query::{Constraint, Relation},
+};
+
+/// maximum number of linear relaxations solved by the branch and bound
+const NODE_LIMIT: usize = 10_000;
+
+/// outcome of the state equation `M = M0 + C·x, x >= 0` for a target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateEquation {
+    /// no firing count vector leads to the target, so no firing sequence does
+    Unreachable,
+    /// fewest firings of each transition solving the equation, a sequence
+    /// firing them may still not exist
+    Candidate(Vec<i64>),
+    /// the equation does not describe the net (reset arcs, omega markings)
+    /// or the solver gave up
+    Inconclusive,
+}
+
+/// `C[p][t]`, tokens transition `t` adds to place `p`
+pub fn incidence_matrix(input: &Input) -> Vec<Vec<i32>> {
+    (0..input.m_names.len())
+        .map(|p| {
+            input
+                .transitions
+                .iter()
+                .map(|arcs| arcs[p].1 - arcs[p].0)
+                .collect()
+        })
+        .collect()
+}
+
+/// solves the state equation for markings satisfying `constraints`, every
+/// reached marking must also be non negative and within the capacities
+pub fn check_state_equation(input: &Input, constraints: &[Constraint]) -> StateEquation {
+    if input.reset_arcs.iter().flatten().any(|reset| *reset) {
+        return StateEquation::Inconclusive;
+    }
+    let Some(m_init) = input.m_init.iter().copied().collect::<Option<Vec<_>>>() else {
+        return StateEquation::Inconclusive;
+    };
+    let incidence = incidence_matrix(input);
+    // Σ_p a_p·M(p) expressed over the firing counts: a·C·x + a·M0
+    let over_firings = |coefficients: &[i32]| {
+        let row = (0..input.transitions.len())
+            .map(|t| {
+                coefficients
+                    .iter()
+                    .zip(&incidence)
+                    .map(|(a, c)| f64::from(a * c[t]))
+                    .sum::<f64>()
+            })
+            .collect::<Vec<_>>();
+        let offset = coefficients
+            .iter()
+            .zip(&m_init)
+            .map(|(a, m)| f64::from(a * m))
+            .sum::<f64>();
+        (row, offset)
+    };
+
+    let mut rows = vec![];
+    for constraint in constraints {
+        let (row, offset) = over_firings(&constraint.coefficients);
+        rows.push((row, constraint.relation, constraint.bound as f64 - offset));
+    }
+    for p in 0..input.m_names.len() {
+        let unit = (0..input.m_names.len())
+            .map(|q| i32::from(p == q))
+            .collect::<Vec<_>>();
+        let (row, offset) = over_firings(&unit);
+        rows.push((row.clone(), Relation::AtLeast, -offset));
+        if let Some(capacity) = input.capacity(p) {
+            rows.push((row, Relation::AtMost, f64::from(capacity) - offset));
+        }
+    }
+
+    let program = LinearProgram {
+        objective: vec![1.0; input.transitions.len()],
+        rows,
+    };
+    match solve_integer(&program, NODE_LIMIT) {
+        IlpResult::Solution(x) => StateEquation::Candidate(x),
+        IlpResult::Infeasible => StateEquation::Unreachable,
+        IlpResult::Unbounded | IlpResult::Unknown => StateEquation::Inconclusive,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_state_equation, StateEquation};
+    use crate::{graph_gen::Input, query::Target};
+
+    #[test]
+    fn test_state_equation() {
+        // t0 moves two tokens of p0 into one token of p1
+        let input = Input {
+            m_names: vec!["p0".to_string(), "p1".to_string()],
+            m_init: vec![Some(4), Some(0)],
+            transitions: vec![vec![(2, 0), (0, 1)]],
+            ..Default::default()
+        };
+        let reachable = Target::Marking(vec![Some(0), Some(2)]).constraints();
+        assert_eq!(
+            check_state_equation(&input, &reachable),
+            StateEquation::Candidate(vec![2])
+        );
+        let odd = Target::Marking(vec![Some(1), Some(1)]).constraints();
+        assert_eq!(
+            check_state_equation(&input, &odd),
+            StateEquation::Unreachable
+        );
+    }
+}
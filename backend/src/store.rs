@@ -0,0 +1,251 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+};
+
+use crate::graph_gen::Marking;
+
+/// end of a chain of markings sharing a fingerprint
+const END: u32 = u32::MAX;
+
+/// what the markings of a store take in memory
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StoreStats {
+    pub markings: usize,
+    /// bits of the fields of a marking
+    pub bits: u32,
+    /// words of 64 bits holding a marking
+    pub words: usize,
+    /// bytes of the packed markings, their fingerprints and their chains
+    pub bytes: usize,
+    /// bytes the same markings take as `Marking` vectors
+    pub unpacked_bytes: usize,
+    /// markings sharing their fingerprint with an other one
+    pub collisions: usize,
+    /// times the markings were packed again after a field grew
+    pub repacks: usize,
+}
+
+impl fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} markings packed in {} bits ({} words), {} bytes stored instead of {} ({} fingerprint collisions, {} repacks)",
+            self.markings,
+            self.bits,
+            self.words,
+            self.bytes,
+            self.unpacked_bytes,
+            self.collisions,
+            self.repacks
+        )
+    }
+}
+
+/// splitmix64 finaliser
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 64 bits hash of the token counts, independent of the packing
+pub fn fingerprint(marking: &[Option<i32>]) -> u64 {
+    marking.iter().fold(0x9e37_79b9_7f4a_7c15, |hash, x| {
+        mix(hash ^ x.map_or(u64::MAX, |x| x as u64))
+    })
+}
+
+/// bits of a field holding `value`, the all ones field is kept for omega
+fn width(value: i32) -> u32 {
+    u64::BITS - (value as u64 + 1).leading_zeros()
+}
+
+/// word and first bit of the field of each place, a field never spans two
+/// words, and the number of words
+fn layout(widths: &[u32]) -> (Vec<(usize, u32)>, usize) {
+    let mut positions = vec![];
+    let (mut word, mut bit) = (0, 0);
+    for width in widths {
+        if bit + width > u64::BITS {
+            word += 1;
+            bit = 0;
+        }
+        positions.push((word, bit));
+        bit += width;
+    }
+    (positions, if widths.is_empty() { 0 } else { word + 1 })
+}
+
+/// distinct markings, each stored once as packed words and numbered in
+/// insertion order (hash-consing); the fields of the places grow with the
+/// largest counts inserted
+#[derive(Debug, Clone)]
+pub struct MarkingStore {
+    widths: Vec<u32>,
+    positions: Vec<(usize, u32)>,
+    words: usize,
+    data: Vec<u64>,
+    /// last marking inserted with each fingerprint
+    fingerprints: HashMap<u64, u32>,
+    /// marking inserted before each marking with the same fingerprint
+    chains: Vec<u32>,
+    collisions: usize,
+    repacks: usize,
+}
+
+impl MarkingStore {
+    pub fn new(places: usize) -> Self {
+        let widths = vec![1; places];
+        let (positions, words) = layout(&widths);
+        MarkingStore {
+            widths,
+            positions,
+            words,
+            data: vec![],
+            fingerprints: HashMap::new(),
+            chains: vec![],
+            collisions: 0,
+            repacks: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    fn field(&self, id: usize, p: usize) -> u64 {
+        let (word, bit) = self.positions[p];
+        let mask = (1 << self.widths[p]) - 1;
+        (self.data[id * self.words + word] >> bit) & mask
+    }
+
+    /// tokens of place `p` in marking `id`
+    pub fn value(&self, id: usize, p: usize) -> Option<i32> {
+        let field = self.field(id, p);
+        (field != (1 << self.widths[p]) - 1).then_some(field as i32)
+    }
+
+    pub fn marking(&self, id: usize) -> Marking {
+        (0..self.widths.len()).map(|p| self.value(id, p)).collect()
+    }
+
+    fn pack(&self, marking: &[Option<i32>]) -> Vec<u64> {
+        let mut packed = vec![0; self.words];
+        for (p, x) in marking.iter().enumerate() {
+            let (word, bit) = self.positions[p];
+            let field = x.map_or((1 << self.widths[p]) - 1, |x| x as u64);
+            packed[word] |= field << bit;
+        }
+        packed
+    }
+
+    /// widens the fields too small for `marking` and packs every marking
+    /// again
+    fn widen(&mut self, marking: &[Option<i32>]) {
+        let widths = self
+            .widths
+            .iter()
+            .zip(marking)
+            .map(|(old, x)| x.map_or(*old, |x| width(x).max(*old)))
+            .collect::<Vec<_>>();
+        if widths == self.widths {
+            return;
+        }
+        let markings = (0..self.len())
+            .map(|id| self.marking(id))
+            .collect::<Vec<_>>();
+        (self.positions, self.words) = layout(&widths);
+        self.widths = widths;
+        self.data = markings.iter().flat_map(|m| self.pack(m)).collect();
+        self.repacks += usize::from(!markings.is_empty());
+    }
+
+    /// number of `marking` if it was inserted
+    pub fn get(&self, marking: &[Option<i32>]) -> Option<usize> {
+        let mut id = *self.fingerprints.get(&fingerprint(marking))?;
+        while id != END {
+            let same = marking
+                .iter()
+                .enumerate()
+                .all(|(p, x)| self.value(id as usize, p) == *x);
+            if same {
+                return Some(id as usize);
+            }
+            id = self.chains[id as usize];
+        }
+        None
+    }
+
+    /// number of `marking`, and whether it was not inserted yet
+    pub fn insert(&mut self, marking: &[Option<i32>]) -> (usize, bool) {
+        if let Some(id) = self.get(marking) {
+            return (id, false);
+        }
+        self.widen(marking);
+        let id = self.len();
+        let packed = self.pack(marking);
+        self.data.extend(packed);
+        match self.fingerprints.entry(fingerprint(marking)) {
+            Entry::Occupied(mut entry) => {
+                self.collisions += 1;
+                self.chains.push(*entry.get());
+                entry.insert(id as u32);
+            }
+            Entry::Vacant(entry) => {
+                self.chains.push(END);
+                entry.insert(id as u32);
+            }
+        }
+        (id, true)
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        let places = self.widths.len();
+        StoreStats {
+            markings: self.len(),
+            bits: self.widths.iter().sum(),
+            words: self.words,
+            bytes: self.data.capacity() * std::mem::size_of::<u64>()
+                + self.chains.capacity() * std::mem::size_of::<u32>()
+                + self.fingerprints.capacity() * std::mem::size_of::<(u64, u32)>(),
+            unpacked_bytes: self.len()
+                * (std::mem::size_of::<Marking>() + places * std::mem::size_of::<Option<i32>>()),
+            collisions: self.collisions,
+            repacks: self.repacks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MarkingStore;
+
+    #[test]
+    fn test_packing_and_hash_consing() {
+        let mut store = MarkingStore::new(3);
+        assert_eq!(store.insert(&[Some(1), Some(0), Some(0)]), (0, true));
+        assert_eq!(store.insert(&[Some(0), Some(1), Some(0)]), (1, true));
+        assert_eq!(store.insert(&[Some(1), Some(0), Some(0)]), (0, false));
+        assert_eq!(store.stats().bits, 5);
+        // a bigger count widens the field of its place, omega fits in any
+        let big = [Some(0), Some(1000), None];
+        assert_eq!(store.insert(&big), (2, true));
+        assert_eq!(store.stats().repacks, 2);
+        assert_eq!(store.stats().bits, 2 + 10 + 1);
+        assert_eq!(store.marking(0), vec![Some(1), Some(0), Some(0)]);
+        assert_eq!(store.marking(2), big.to_vec());
+        assert_eq!(store.get(&[Some(0), Some(1000), Some(1)]), None);
+        assert_eq!(store.get(&big), Some(2));
+        // fields never straddle two words
+        let mut wide = MarkingStore::new(3);
+        wide.insert(&[Some(i32::MAX), Some(i32::MAX), Some(0)]);
+        let stats = wide.stats();
+        assert_eq!((stats.bits, stats.words), (32 + 32 + 1, 2));
+        assert_eq!(wide.value(0, 1), Some(i32::MAX));
+    }
+}
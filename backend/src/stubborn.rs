@@ -0,0 +1,202 @@
+use crate::graph_gen::{is_enabled, CapacityRule, Input};
+
+/// interactions between the transitions of a net, computed once before the
+/// exploration
+#[derive(Debug, Clone)]
+pub struct StubbornSets {
+    /// transitions whose firing does not commute with each transition
+    dependents: Vec<Vec<usize>>,
+    /// transitions able to add tokens to each place
+    increasers: Vec<Vec<usize>>,
+    /// transitions able to remove tokens from each place
+    decreasers: Vec<Vec<usize>>,
+}
+
+/// whether firing `t` may take tokens from `p`
+fn decreases(input: &Input, t: usize, p: usize) -> bool {
+    let (pre, post) = input.transitions[t][p];
+    pre > post || input.reset_arc(t, p)
+}
+
+/// whether firing `t` may add tokens to `p`
+fn increases(input: &Input, t: usize, p: usize) -> bool {
+    let (pre, post) = input.transitions[t][p];
+    post > pre || (input.reset_arc(t, p) && post > 0)
+}
+
+/// whether `t` needs tokens in `p` to be enabled
+fn needs(input: &Input, t: usize, p: usize) -> bool {
+    input.transitions[t][p].0 > 0 || input.read_arc(t, p) > 0
+}
+
+/// whether `t` and `u` can disable each other or reach different markings
+/// depending on their order through the place `p`
+fn conflict_on(input: &Input, t: usize, u: usize, p: usize) -> bool {
+    let changes = |t: usize| decreases(input, t, p) || increases(input, t, p);
+    let puts = |t: usize| input.transitions[t][p].1 > 0;
+    (decreases(input, t, p) && needs(input, u, p))
+        || (decreases(input, u, p) && needs(input, t, p))
+        || (input.reset_arc(t, p) && changes(u))
+        || (input.reset_arc(u, p) && changes(t))
+        || (input.capacity(p).is_some() && puts(t) && puts(u) && (changes(t) || changes(u)))
+}
+
+impl StubbornSets {
+    pub fn new(input: &Input) -> Self {
+        let (transitions, places) = (input.transitions.len(), input.m_names.len());
+        StubbornSets {
+            dependents: (0..transitions)
+                .map(|t| {
+                    (0..transitions)
+                        .filter(|u| *u != t && (0..places).any(|p| conflict_on(input, t, *u, p)))
+                        .collect()
+                })
+                .collect(),
+            increasers: (0..places)
+                .map(|p| {
+                    (0..transitions)
+                        .filter(|t| increases(input, *t, p))
+                        .collect()
+                })
+                .collect(),
+            decreasers: (0..places)
+                .map(|p| {
+                    (0..transitions)
+                        .filter(|t| decreases(input, *t, p))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// transitions that must fire before the disabled `t` can: the ones
+    /// filling a place missing tokens, or emptying a full place
+    fn scapegoats(
+        &self,
+        input: &Input,
+        t: usize,
+        marking: &[Option<i32>],
+        rule: CapacityRule,
+    ) -> Vec<usize> {
+        for (p, y) in marking.iter().enumerate() {
+            let Some(y) = y else {
+                continue;
+            };
+            let (pre, post) = input.transitions[t][p];
+            if *y < pre || *y < input.read_arc(t, p) {
+                return self.increasers[p].clone();
+            }
+            let kept = match rule {
+                CapacityRule::Strict => *y,
+                CapacityRule::Weak => y - pre,
+            };
+            if post > 0 && input.capacity(p).is_some_and(|k| kept + post > k) {
+                return self.decreasers[p].clone();
+            }
+        }
+        (0..input.transitions.len()).collect()
+    }
+
+    /// stubborn set grown from the enabled transition `seed`: the
+    /// transitions dependent on its enabled members and the scapegoats of
+    /// its disabled ones
+    fn grow(
+        &self,
+        input: &Input,
+        marking: &[Option<i32>],
+        rule: CapacityRule,
+        seed: usize,
+    ) -> Vec<bool> {
+        let mut inside = vec![false; input.transitions.len()];
+        let mut stack = vec![seed];
+        while let Some(t) = stack.pop() {
+            if inside[t] {
+                continue;
+            }
+            inside[t] = true;
+            if is_enabled(input, t, marking, rule) {
+                stack.extend(self.dependents[t].iter().filter(|u| !inside[**u]));
+            } else {
+                let scapegoats = self.scapegoats(input, t, marking, rule);
+                stack.extend(scapegoats.into_iter().filter(|u| !inside[*u]));
+            }
+        }
+        inside
+    }
+
+    /// smallest set of enabled transitions of a stubborn set of `marking`
+    /// accepted by `valid`, all of `enabled` when none is; exploring only
+    /// these transitions keeps every deadlock (Valmari)
+    pub fn reduce(
+        &self,
+        input: &Input,
+        marking: &[Option<i32>],
+        enabled: &[usize],
+        rule: CapacityRule,
+        valid: impl Fn(&[usize]) -> bool,
+    ) -> Vec<usize> {
+        enabled
+            .iter()
+            .map(|seed| {
+                let inside = self.grow(input, marking, rule, *seed);
+                enabled
+                    .iter()
+                    .copied()
+                    .filter(|t| inside[*t])
+                    .collect::<Vec<_>>()
+            })
+            .filter(|reduced| valid(reduced))
+            .min_by_key(Vec::len)
+            .unwrap_or_else(|| enabled.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph_gen::{explore_reduced, independent_workers, ExplorationOptions, Reduction};
+
+    fn deadlocks(edges: &[Vec<(Vec<usize>, usize)>]) -> usize {
+        edges.iter().filter(|next| next.is_empty()).count()
+    }
+
+    #[test]
+    fn test_stubborn_sets_keep_deadlocks() {
+        let input = independent_workers(3);
+        let (full, stats) = explore_reduced(&input, &Default::default());
+        assert_eq!(full.markings.len(), 8);
+        assert_eq!(stats.pruned_firings, 0);
+        let options = ExplorationOptions {
+            reduction: Reduction::Deadlocks,
+            ..Default::default()
+        };
+        let (reduced, stats) = explore_reduced(&input, &options);
+        assert_eq!(reduced.markings.len(), 4);
+        assert_eq!(stats.reduced_states, 2);
+        assert_eq!(stats.pruned_firings, 3);
+        assert_eq!(deadlocks(&reduced.edges), deadlocks(&full.edges));
+        // a conflict on a shared place keeps both branches
+        let mut conflict = input.clone();
+        conflict.transitions[1][0] = (1, 0);
+        conflict.transitions[1][2] = (0, 0);
+        let (reduced, _) = explore_reduced(&conflict, &options);
+        let (full, _) = explore_reduced(&conflict, &Default::default());
+        assert_eq!(deadlocks(&reduced.edges), deadlocks(&full.edges));
+        assert_eq!(deadlocks(&full.edges), 2);
+    }
+
+    #[test]
+    fn test_ltl_reduction_keeps_visible_firings() {
+        let mut input = independent_workers(3);
+        input.hidden = vec![false, true, true];
+        let options = ExplorationOptions {
+            reduction: Reduction::Ltl,
+            ..Default::default()
+        };
+        let (reduced, stats) = explore_reduced(&input, &options);
+        assert_eq!(reduced.markings.len(), 4);
+        assert_eq!(stats.pruned_firings, 3);
+        // the only transition fired from the initial marking is internal
+        assert_eq!(reduced.edges[0].len(), 1);
+        assert!(input.is_hidden(reduced.edges[0][0].0[0]));
+    }
+}
@@ -0,0 +1,666 @@
+use std::collections::HashMap;
+
+use crate::{
+    bdd::{Bdd, Node, FALSE, TRUE},
+    error_type::ErrorTypes,
+    graph_gen::{CapacityRule, Input, Marking},
+    query::{Constraint, Relation, Target},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolicOptions {
+    pub capacity_rule: CapacityRule,
+    /// most tokens a place without capacity can hold, 1 for safe nets
+    pub bound: i32,
+}
+
+impl Default for SymbolicOptions {
+    fn default() -> Self {
+        SymbolicOptions {
+            capacity_rule: CapacityRule::default(),
+            bound: 1,
+        }
+    }
+}
+
+/// a transition as a relation between the bits of the places it touches
+/// before firing (current variables) and after (next variables)
+struct Firing {
+    /// markings enabling the transition, priorities left out
+    enabled: Node,
+    /// markings where no transition of a higher priority is enabled
+    guard: Node,
+    relation: Node,
+    /// markings enabling the transition where it puts too many tokens in
+    /// each place
+    overflows: Vec<(usize, Node)>,
+    current: Vec<bool>,
+    next: Vec<bool>,
+}
+
+/// a bounded net encoded with binary decision diagrams, the tokens of each
+/// place are written in binary and the current and next bits of the places
+/// are interleaved
+pub struct SymbolicNet {
+    pub bdd: Bdd,
+    bounds: Vec<i32>,
+    /// current variables of the bits of each place, most significant first,
+    /// the next variable of a bit follows its current one
+    bits: Vec<Vec<u32>>,
+    firings: Vec<Firing>,
+    pub initial: Node,
+}
+
+/// CTL formula over the markings, evaluated on the maximal paths: a path
+/// ending in a deadlock satisfies `EG φ` when `φ` holds all along
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ctl {
+    True,
+    False,
+    Deadlock,
+    /// every constraint holds
+    Atom(Vec<Constraint>),
+    Not(Box<Ctl>),
+    And(Box<Ctl>, Box<Ctl>),
+    Or(Box<Ctl>, Box<Ctl>),
+    Ex(Box<Ctl>),
+    Ax(Box<Ctl>),
+    Ef(Box<Ctl>),
+    Af(Box<Ctl>),
+    Eg(Box<Ctl>),
+    Ag(Box<Ctl>),
+    Eu(Box<Ctl>, Box<Ctl>),
+    Au(Box<Ctl>, Box<Ctl>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+    Not,
+    And,
+    Or,
+    Implies,
+    Word(String),
+    /// linear predicate between braces
+    Atom(String),
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = formula.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '!' => Token::Not,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '-' if chars.next_if_eq(&'>').is_some() => Token::Implies,
+            '{' => {
+                let predicate = chars.by_ref().take_while(|c| *c != '}').collect();
+                Token::Atom(predicate)
+            }
+            _ if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "not" => Token::Not,
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    _ => Token::Word(word),
+                }
+            }
+            _ => return Err(format!("unexpected {} in {}", c, formula)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// recursive descent over the tokens of a formula, from the loosest
+/// operator (implication) to the tightest (unary operators)
+struct Parser<'a> {
+    input: &'a Input,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.position) == Some(token);
+        self.position += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.eat(&token) {
+            true => Ok(()),
+            false => Err(format!("expected {:?} at token {}", token, self.position)),
+        }
+    }
+
+    fn implication(&mut self) -> Result<Ctl, String> {
+        let left = self.disjunction()?;
+        if self.eat(&Token::Implies) {
+            let right = self.implication()?;
+            return Ok(Ctl::Or(Box::new(Ctl::Not(Box::new(left))), Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn disjunction(&mut self) -> Result<Ctl, String> {
+        let mut formula = self.conjunction()?;
+        while self.eat(&Token::Or) {
+            formula = Ctl::Or(Box::new(formula), Box::new(self.conjunction()?));
+        }
+        Ok(formula)
+    }
+
+    fn conjunction(&mut self) -> Result<Ctl, String> {
+        let mut formula = self.unary()?;
+        while self.eat(&Token::And) {
+            formula = Ctl::And(Box::new(formula), Box::new(self.unary()?));
+        }
+        Ok(formula)
+    }
+
+    fn unary(&mut self) -> Result<Ctl, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("unexpected end of the formula")?;
+        self.position += 1;
+        let formula = match token {
+            Token::Not => Ctl::Not(Box::new(self.unary()?)),
+            Token::Open => {
+                let formula = self.implication()?;
+                self.expect(Token::Close)?;
+                formula
+            }
+            Token::Atom(predicate) => {
+                Ctl::Atom(Target::parse_predicate(self.input, &predicate)?.constraints())
+            }
+            Token::Word(word) => match word.as_str() {
+                "true" => Ctl::True,
+                "false" => Ctl::False,
+                "deadlock" => Ctl::Deadlock,
+                "EX" => Ctl::Ex(Box::new(self.unary()?)),
+                "AX" => Ctl::Ax(Box::new(self.unary()?)),
+                "EF" => Ctl::Ef(Box::new(self.unary()?)),
+                "AF" => Ctl::Af(Box::new(self.unary()?)),
+                "EG" => Ctl::Eg(Box::new(self.unary()?)),
+                "AG" => Ctl::Ag(Box::new(self.unary()?)),
+                "E" | "A" => {
+                    self.expect(Token::OpenBracket)?;
+                    let left = Box::new(self.implication()?);
+                    self.expect(Token::Word("U".to_string()))?;
+                    let right = Box::new(self.implication()?);
+                    self.expect(Token::CloseBracket)?;
+                    match word.as_str() {
+                        "E" => Ctl::Eu(left, right),
+                        _ => Ctl::Au(left, right),
+                    }
+                }
+                _ => return Err(format!("unknown operator {}", word)),
+            },
+            token => return Err(format!("unexpected {:?}", token)),
+        };
+        Ok(formula)
+    }
+}
+
+impl Ctl {
+    /// parses a formula such as `AG (EF {idle = 1})` or
+    /// `E[{p0 >= 1} U deadlock]`: predicates over the places of `input` go
+    /// between braces, `!`, `&`, `|` and `->` combine formulas
+    pub fn parse(input: &Input, formula: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            input,
+            tokens: tokenize(formula)?,
+            position: 0,
+        };
+        let ctl = parser.implication()?;
+        match parser.position == parser.tokens.len() {
+            true => Ok(ctl),
+            false => Err(format!("unexpected tokens after {:?}", ctl)),
+        }
+    }
+}
+
+fn not_bounded(input: &Input, p: usize, bound: i32) -> ErrorTypes {
+    ErrorTypes::NotBounded {
+        place: input.m_names[p].clone(),
+        bound,
+    }
+}
+
+impl SymbolicNet {
+    /// encodes `input`, whose places without capacity must never hold more
+    /// than `options.bound` tokens; the firing intervals are left out and
+    /// the transitions fire one at a time
+    pub fn new(input: &Input, options: &SymbolicOptions) -> Result<Self, ErrorTypes> {
+        let bounds = (0..input.m_names.len())
+            .map(|p| input.capacity(p).unwrap_or(options.bound))
+            .collect::<Vec<_>>();
+        let mut bits = vec![];
+        let mut vars = 0;
+        for bound in &bounds {
+            let width = u32::BITS - (*bound).max(1).leading_zeros();
+            bits.push((0..width).map(|b| 2 * (vars + b)).collect());
+            vars += width;
+        }
+        let mut net = SymbolicNet {
+            bdd: Bdd::new(),
+            bounds,
+            bits,
+            firings: vec![],
+            initial: TRUE,
+        };
+        for (p, x) in input.m_init.iter().enumerate() {
+            match x {
+                Some(x) if *x <= net.bounds[p] => {
+                    let code = net.code(p, *x, false);
+                    net.initial = net.bdd.and(net.initial, code);
+                }
+                _ => return Err(not_bounded(input, p, net.bounds[p])),
+            }
+        }
+        for t in 0..input.transitions.len() {
+            let firing = net.firing(input, t, options.capacity_rule, 2 * vars as usize);
+            net.firings.push(firing);
+        }
+        for t in 0..input.transitions.len() {
+            let mut blocking = FALSE;
+            for u in 0..input.transitions.len() {
+                if input.priority(u) > input.priority(t) {
+                    blocking = net.bdd.or(blocking, net.firings[u].enabled);
+                }
+            }
+            net.firings[t].guard = net.bdd.not(blocking);
+        }
+        Ok(net)
+    }
+
+    /// markings where place `p` holds `value` tokens, on the next
+    /// variables when `next`
+    fn code(&mut self, p: usize, value: i32, next: bool) -> Node {
+        let width = self.bits[p].len();
+        let mut code = TRUE;
+        for (b, var) in self.bits[p].clone().into_iter().enumerate().rev() {
+            let literal = self
+                .bdd
+                .literal(var + u32::from(next), value >> (width - 1 - b) & 1 == 1);
+            code = self.bdd.and(literal, code);
+        }
+        code
+    }
+
+    fn firing(&mut self, input: &Input, t: usize, rule: CapacityRule, vars: usize) -> Firing {
+        let touched = (0..input.m_names.len())
+            .filter(|p| {
+                input.transitions[t][*p] != (0, 0)
+                    || input.read_arc(t, *p) > 0
+                    || input.reset_arc(t, *p)
+            })
+            .collect::<Vec<_>>();
+        let mut firing = Firing {
+            enabled: TRUE,
+            guard: TRUE,
+            relation: TRUE,
+            overflows: vec![],
+            current: vec![false; vars],
+            next: vec![false; vars],
+        };
+        for p in touched {
+            let (pre, post) = input.transitions[t][p];
+            let reset = input.reset_arc(t, p);
+            let (mut local, mut moves, mut overflow) = (FALSE, FALSE, FALSE);
+            for value in 0..=self.bounds[p] {
+                // same rules as `is_enabled` and `activate_transition`
+                let kept = match (reset, rule) {
+                    (true, CapacityRule::Weak) => 0,
+                    (_, CapacityRule::Strict) => value,
+                    (false, CapacityRule::Weak) => value - pre,
+                };
+                if value < pre
+                    || value < input.read_arc(t, p)
+                    || (post > 0 && input.capacity(p).is_some_and(|k| kept + post > k))
+                {
+                    continue;
+                }
+                let after = match reset {
+                    true => post,
+                    false => value - pre + post,
+                };
+                let before = self.code(p, value, false);
+                local = self.bdd.or(local, before);
+                if after > self.bounds[p] {
+                    overflow = self.bdd.or(overflow, before);
+                } else {
+                    let after = self.code(p, after, true);
+                    let step = self.bdd.and(before, after);
+                    moves = self.bdd.or(moves, step);
+                }
+            }
+            firing.enabled = self.bdd.and(firing.enabled, local);
+            firing.relation = self.bdd.and(firing.relation, moves);
+            if overflow != FALSE {
+                firing.overflows.push((p, overflow));
+            }
+            for var in &self.bits[p] {
+                firing.current[*var as usize] = true;
+                firing.next[*var as usize + 1] = true;
+            }
+        }
+        for (_, overflow) in &mut firing.overflows {
+            *overflow = self.bdd.and(*overflow, firing.enabled);
+        }
+        firing
+    }
+
+    /// markings from which firing `t` reaches `states`
+    fn preimage(&mut self, t: usize, states: Node) -> Node {
+        let firing = &self.firings[t];
+        let current = &firing.current;
+        let next = self
+            .bdd
+            .rename(states, &|var| var + u32::from(current[var as usize]));
+        let before = self.bdd.and_exists(firing.relation, next, &firing.next);
+        self.bdd.and(before, firing.guard)
+    }
+
+    /// least set holding `start` and closed by the firings, forwards or
+    /// backwards, whose ends are in `within`, by saturation
+    fn closure(&mut self, start: Node, within: Node, backward: bool) -> Node {
+        let mut events = vec![];
+        for t in 0..self.firings.len() {
+            let event = self
+                .bdd
+                .and(self.firings[t].relation, self.firings[t].guard);
+            events.push(event);
+        }
+        let vars = 2 * self.bits.iter().map(Vec::len).sum::<usize>() as u32;
+        self.bdd.saturate(start, within, &events, vars, backward)
+    }
+
+    /// reachable markings
+    pub fn reachable(&mut self, input: &Input) -> Result<Node, ErrorTypes> {
+        let reached = self.closure(self.initial, TRUE, false);
+        for t in 0..self.firings.len() {
+            for (p, overflow) in self.firings[t].overflows.clone() {
+                let allowed = self.bdd.and(overflow, self.firings[t].guard);
+                if self.bdd.and(allowed, reached) != FALSE {
+                    return Err(not_bounded(input, p, self.bounds[p]));
+                }
+            }
+        }
+        Ok(reached)
+    }
+
+    /// number of markings of `states`, `None` when `states` also depends on
+    /// the next variables
+    pub fn count(&self, states: Node) -> Option<f64> {
+        let vars = self.bits.iter().flatten().copied().collect::<Vec<_>>();
+        self.bdd.count(states, &vars)
+    }
+
+    /// one of the markings of `states`
+    pub fn marking(&self, states: Node) -> Option<Marking> {
+        let path = self
+            .bdd
+            .pick(states)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        Some(
+            self.bits
+                .iter()
+                .map(|bits| {
+                    Some(bits.iter().fold(0, |value, var| {
+                        2 * value + i32::from(path.get(var).copied().unwrap_or(false))
+                    }))
+                })
+                .collect(),
+        )
+    }
+
+    /// markings of `reachable` where no transition is enabled
+    pub fn deadlocks(&mut self, reachable: Node) -> Node {
+        let mut live = FALSE;
+        for t in 0..self.firings.len() {
+            live = self.bdd.or(live, self.firings[t].enabled);
+        }
+        self.bdd.diff(reachable, live)
+    }
+
+    /// markings where every constraint holds
+    fn constraints(&mut self, constraints: &[Constraint]) -> Node {
+        let mut result = TRUE;
+        for constraint in constraints {
+            let places = (0..self.bits.len())
+                .filter(|p| constraint.coefficients[*p] != 0)
+                .collect::<Vec<_>>();
+            let node = self.sum(constraint, &places, 0, 0, &mut HashMap::new());
+            result = self.bdd.and(result, node);
+        }
+        result
+    }
+
+    /// markings of the places from `places[i]` on completing the weighted
+    /// sum `partial` of the places before so that `constraint` holds
+    fn sum(
+        &mut self,
+        constraint: &Constraint,
+        places: &[usize],
+        i: usize,
+        partial: i64,
+        memo: &mut HashMap<(usize, i64), Node>,
+    ) -> Node {
+        let Some(p) = places.get(i) else {
+            let holds = match constraint.relation {
+                Relation::AtLeast => partial >= constraint.bound,
+                Relation::AtMost => partial <= constraint.bound,
+                Relation::Equal => partial == constraint.bound,
+            };
+            return if holds { TRUE } else { FALSE };
+        };
+        if let Some(node) = memo.get(&(i, partial)) {
+            return *node;
+        }
+        let mut result = FALSE;
+        for value in 0..=self.bounds[*p] {
+            let weight = i64::from(constraint.coefficients[*p]) * i64::from(value);
+            let rest = self.sum(constraint, places, i + 1, partial + weight, memo);
+            let code = self.code(*p, value, false);
+            let both = self.bdd.and(code, rest);
+            result = self.bdd.or(result, both);
+        }
+        memo.insert((i, partial), result);
+        result
+    }
+
+    /// markings of `reachable` with a successor in `states`
+    fn ex(&mut self, reachable: Node, states: Node) -> Node {
+        let mut result = FALSE;
+        for t in 0..self.firings.len() {
+            let before = self.preimage(t, states);
+            result = self.bdd.or(result, before);
+        }
+        self.bdd.and(reachable, result)
+    }
+
+    /// markings of `reachable` starting a path through `left` to `right`
+    fn eu(&mut self, reachable: Node, left: Node, right: Node) -> Node {
+        let left = self.bdd.and(reachable, left);
+        self.closure(right, left, true)
+    }
+
+    /// markings of `reachable` starting a maximal path staying in `states`
+    fn eg(&mut self, reachable: Node, states: Node) -> Node {
+        let deadlocks = self.deadlocks(reachable);
+        let mut result = states;
+        loop {
+            let before = self.ex(reachable, result);
+            let continued = self.bdd.or(before, deadlocks);
+            let next = self.bdd.and(states, continued);
+            if next == result {
+                return result;
+            }
+            result = next;
+        }
+    }
+
+    /// markings of `reachable` satisfying `formula`
+    pub fn satisfying(&mut self, reachable: Node, formula: &Ctl) -> Node {
+        match formula {
+            Ctl::True => reachable,
+            Ctl::False => FALSE,
+            Ctl::Deadlock => self.deadlocks(reachable),
+            Ctl::Atom(constraints) => {
+                let atom = self.constraints(constraints);
+                self.bdd.and(reachable, atom)
+            }
+            Ctl::Not(f) => {
+                let f = self.satisfying(reachable, f);
+                self.bdd.diff(reachable, f)
+            }
+            Ctl::And(f, g) => {
+                let (f, g) = (self.satisfying(reachable, f), self.satisfying(reachable, g));
+                self.bdd.and(f, g)
+            }
+            Ctl::Or(f, g) => {
+                let (f, g) = (self.satisfying(reachable, f), self.satisfying(reachable, g));
+                self.bdd.or(f, g)
+            }
+            Ctl::Ex(f) => {
+                let f = self.satisfying(reachable, f);
+                self.ex(reachable, f)
+            }
+            Ctl::Ax(f) => {
+                let f = self.satisfying(reachable, f);
+                let not_f = self.bdd.diff(reachable, f);
+                let escape = self.ex(reachable, not_f);
+                self.bdd.diff(reachable, escape)
+            }
+            Ctl::Ef(f) => {
+                let f = self.satisfying(reachable, f);
+                self.eu(reachable, reachable, f)
+            }
+            Ctl::Af(f) => {
+                let f = self.satisfying(reachable, f);
+                let not_f = self.bdd.diff(reachable, f);
+                let avoid = self.eg(reachable, not_f);
+                self.bdd.diff(reachable, avoid)
+            }
+            Ctl::Eg(f) => {
+                let f = self.satisfying(reachable, f);
+                self.eg(reachable, f)
+            }
+            Ctl::Ag(f) => {
+                let f = self.satisfying(reachable, f);
+                let not_f = self.bdd.diff(reachable, f);
+                let reach = self.eu(reachable, reachable, not_f);
+                self.bdd.diff(reachable, reach)
+            }
+            Ctl::Eu(f, g) => {
+                let (f, g) = (self.satisfying(reachable, f), self.satisfying(reachable, g));
+                self.eu(reachable, f, g)
+            }
+            Ctl::Au(f, g) => {
+                // A[f U g] = ¬(E[¬g U ¬f ∧ ¬g] ∨ EG ¬g)
+                let (f, g) = (self.satisfying(reachable, f), self.satisfying(reachable, g));
+                let not_g = self.bdd.diff(reachable, g);
+                let neither = self.bdd.diff(not_g, f);
+                let broken = self.eu(reachable, not_g, neither);
+                let never = self.eg(reachable, not_g);
+                let failing = self.bdd.or(broken, never);
+                self.bdd.diff(reachable, failing)
+            }
+        }
+    }
+
+    /// whether the initial marking satisfies `formula`
+    pub fn holds(&mut self, reachable: Node, formula: &Ctl) -> bool {
+        let states = self.satisfying(reachable, formula);
+        self.bdd.and(self.initial, states) != FALSE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Ctl, SymbolicNet, SymbolicOptions};
+    use crate::error_type::ErrorTypes;
+    use crate::graph_gen::{explore, independent_workers, Input};
+
+    /// t0 moves a token from p0 to p1, t1 doubles the tokens of p1 into p2
+    fn doubling() -> Input {
+        Input {
+            m_names: vec!["p0".to_string(), "p1".to_string(), "p2".to_string()],
+            m_init: vec![Some(3), Some(0), Some(0)],
+            transitions: vec![vec![(1, 0), (0, 1), (0, 0)], vec![(0, 0), (1, 0), (0, 2)]],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reachable_markings() {
+        let input = doubling();
+        let options = SymbolicOptions {
+            bound: 6,
+            ..Default::default()
+        };
+        let mut net = SymbolicNet::new(&input, &options).unwrap();
+        let reachable = net.reachable(&input).unwrap();
+        assert_eq!(
+            net.count(reachable),
+            Some(explore(&input, &Default::default()).markings.len() as f64)
+        );
+        let deadlocks = net.deadlocks(reachable);
+        assert_eq!(net.count(deadlocks), Some(1.0));
+        assert_eq!(
+            net.marking(deadlocks),
+            Some(vec![Some(0), Some(0), Some(6)])
+        );
+        // p2 gets more tokens than a bound of 4
+        let options = SymbolicOptions {
+            bound: 4,
+            ..Default::default()
+        };
+        let mut net = SymbolicNet::new(&input, &options).unwrap();
+        assert!(matches!(
+            net.reachable(&input),
+            Err(ErrorTypes::NotBounded { place, bound: 4 }) if place == "p2"
+        ));
+    }
+
+    #[test]
+    fn test_ctl() {
+        let input = independent_workers(3);
+        let mut net = SymbolicNet::new(&input, &Default::default()).unwrap();
+        let reachable = net.reachable(&input).unwrap();
+        assert_eq!(net.count(reachable), Some(8.0));
+        let mut check = |formula: &str| {
+            let formula = Ctl::parse(&input, formula).unwrap();
+            net.holds(reachable, &formula)
+        };
+        assert!(check("AF deadlock"));
+        assert!(check("AG {p0 + p1 = 1}"));
+        assert!(check("EF {p1 + p3 + p5 = 3}"));
+        assert!(check("EX {p1 = 1} & !AX {p1 = 1}"));
+        assert!(check("A[{p1 = 0} U {p1 = 1}]"));
+        assert!(!check("EG {p1 = 0}"));
+        assert!(check("EG {p1 = 0} -> false"));
+        assert!(check("AG ({p1 = 1} -> AG {p1 = 1})"));
+        assert!(!check("E[{p3 = 0} U {p1 = 1 and p5 = 1}] -> AX false"));
+        assert!(Ctl::parse(&input, "EF {q = 1}").is_err());
+        assert!(Ctl::parse(&input, "E[true U]").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::graph_gen::{Input, Marking};
+
+/// largest group whose elements are all listed, the canonical markings of
+/// bigger groups are reached by applying the generators while they lower
+/// the marking
+pub const GROUP_LIMIT: usize = 10_000;
+
+/// what connects a transition to a place: consumed, produced and read
+/// tokens and whether the place is reset
+type Arc = (i32, i32, i32, bool);
+
+const NO_ARC: Arc = (0, 0, 0, false);
+
+/// automorphisms of a net as permutations of its places, a permutation maps
+/// each place `p` to `permutation[p]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symmetries {
+    /// strong generators along the places taken in order
+    pub generators: Vec<Vec<usize>>,
+    /// number of automorphisms, `None` when it does not fit in a `usize`
+    pub group_size: Option<usize>,
+    /// every automorphism when there are at most `GROUP_LIMIT`, none otherwise
+    elements: Vec<Vec<usize>>,
+}
+
+fn arc(input: &Input, t: usize, p: usize) -> Arc {
+    let (pre, post) = input.transitions[t][p];
+    (pre, post, input.read_arc(t, p), input.reset_arc(t, p))
+}
+
+/// everything but the arcs an automorphism must keep on a transition
+fn attributes(input: &Input, t: usize) -> String {
+    format!(
+        "{:?}",
+        (
+            input.priority(t),
+            input.interval(t),
+            input.delay(t),
+            input.is_hidden(t)
+        )
+    )
+}
+
+/// numbers the distinct signatures in their order
+fn renumber<S: Ord + Clone>(signatures: &[S]) -> Vec<usize> {
+    let ids = signatures
+        .iter()
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(i, s)| (s, i))
+        .collect::<BTreeMap<_, _>>();
+    signatures.iter().map(|s| ids[s]).collect()
+}
+
+fn image(permutation: &[usize], marking: &[Option<i32>]) -> Marking {
+    let mut image = marking.to_vec();
+    for (p, x) in marking.iter().enumerate() {
+        image[permutation[p]] = *x;
+    }
+    image
+}
+
+/// places reached from `point` by the permutations
+fn orbit(point: usize, permutations: &[Vec<usize>]) -> BTreeSet<usize> {
+    let mut orbit = BTreeSet::from([point]);
+    let mut stack = vec![point];
+    while let Some(p) = stack.pop() {
+        for permutation in permutations {
+            if orbit.insert(permutation[p]) {
+                stack.push(permutation[p]);
+            }
+        }
+    }
+    orbit
+}
+
+/// permutations of the places keeping some markings and the arcs up to a
+/// permutation of the transitions, searched place by place
+struct Search<'a> {
+    input: &'a Input,
+    arcs: Vec<Vec<Arc>>,
+    /// places only an automorphism can swap share a colour
+    colours: Vec<usize>,
+    /// transitions connecting each pair of places, with their arcs
+    pairs: Vec<Vec<Vec<(Arc, Arc, usize)>>>,
+    /// transitions of each attributes and arcs, counted
+    transitions: HashMap<(String, Vec<Arc>), usize>,
+}
+
+impl<'a> Search<'a> {
+    fn new(input: &'a Input, markings: &[&[Option<i32>]]) -> Self {
+        let (places, count) = (input.m_names.len(), input.transitions.len());
+        let arcs = (0..count)
+            .map(|t| (0..places).map(|p| arc(input, t, p)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        // colour refinement of the places and transitions by their neighbours
+        let mut colours = renumber(
+            &(0..places)
+                .map(|p| {
+                    let tokens = markings.iter().map(|m| m[p]).collect::<Vec<_>>();
+                    (tokens, input.capacity(p))
+                })
+                .collect::<Vec<_>>(),
+        );
+        let mut transition_colours =
+            renumber(&(0..count).map(|t| attributes(input, t)).collect::<Vec<_>>());
+        loop {
+            let place_signatures = (0..places)
+                .map(|p| {
+                    let mut neighbours = (0..count)
+                        .filter(|t| arcs[*t][p] != NO_ARC)
+                        .map(|t| (arcs[t][p], transition_colours[t]))
+                        .collect::<Vec<_>>();
+                    neighbours.sort_unstable();
+                    (colours[p], neighbours)
+                })
+                .collect::<Vec<_>>();
+            let transition_signatures = (0..count)
+                .map(|t| {
+                    let mut neighbours = (0..places)
+                        .filter(|p| arcs[t][*p] != NO_ARC)
+                        .map(|p| (arcs[t][p], colours[p]))
+                        .collect::<Vec<_>>();
+                    neighbours.sort_unstable();
+                    (transition_colours[t], neighbours)
+                })
+                .collect::<Vec<_>>();
+            let next = renumber(&place_signatures);
+            let next_transitions = renumber(&transition_signatures);
+            let stable = next.iter().max() == colours.iter().max()
+                && next_transitions.iter().max() == transition_colours.iter().max();
+            colours = next;
+            transition_colours = next_transitions;
+            if stable {
+                break;
+            }
+        }
+        let pairs = (0..places)
+            .map(|a| {
+                (0..places)
+                    .map(|b| {
+                        let mut shared = (0..count)
+                            .filter(|t| arcs[*t][a] != NO_ARC && arcs[*t][b] != NO_ARC)
+                            .map(|t| (arcs[t][a], arcs[t][b], transition_colours[t]))
+                            .collect::<Vec<_>>();
+                        shared.sort_unstable();
+                        shared
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut transitions = HashMap::new();
+        for (t, arc) in arcs.iter().enumerate() {
+            *transitions
+                .entry((attributes(input, t), arc.clone()))
+                .or_insert(0) += 1;
+        }
+        Search {
+            input,
+            arcs,
+            colours,
+            pairs,
+            transitions,
+        }
+    }
+
+    /// whether the transitions, moved along with the places, are the
+    /// transitions of the net again
+    fn maps_transitions(&self, permutation: &[usize]) -> bool {
+        let mut images = HashMap::new();
+        for (t, arcs) in self.arcs.iter().enumerate() {
+            let mut image = vec![NO_ARC; arcs.len()];
+            for (p, arc) in arcs.iter().enumerate() {
+                image[permutation[p]] = *arc;
+            }
+            *images
+                .entry((attributes(self.input, t), image))
+                .or_insert(0) += 1;
+        }
+        images == self.transitions
+    }
+
+    /// whether the next place of `permutation` can go to `q`
+    fn fits(&self, permutation: &[usize], q: usize) -> bool {
+        let p = permutation.len();
+        self.colours[q] == self.colours[p]
+            && (0..p).all(|r| self.pairs[r][p] == self.pairs[permutation[r]][q])
+    }
+
+    /// first automorphism starting with `permutation`
+    fn complete(&self, permutation: &mut Vec<usize>, used: &mut Vec<bool>) -> bool {
+        if permutation.len() == self.colours.len() {
+            return self.maps_transitions(permutation);
+        }
+        for q in 0..self.colours.len() {
+            if !used[q] && self.fits(permutation, q) {
+                permutation.push(q);
+                used[q] = true;
+                if self.complete(permutation, used) {
+                    return true;
+                }
+                used[q] = false;
+                permutation.pop();
+            }
+        }
+        false
+    }
+
+    /// an automorphism fixing the places before `i` and moving `i` to `q`
+    fn mapping(&self, i: usize, q: usize) -> Option<Vec<usize>> {
+        let mut permutation = (0..i).collect::<Vec<_>>();
+        let mut used = vec![false; self.colours.len()];
+        used[..i].iter_mut().for_each(|u| *u = true);
+        if used[q] || !self.fits(&permutation, q) {
+            return None;
+        }
+        permutation.push(q);
+        used[q] = true;
+        self.complete(&mut permutation, &mut used)
+            .then_some(permutation)
+    }
+
+    /// strong generators of the group along the stabilisers of the first
+    /// places and its order, the product of the orbits of the stabilisers,
+    /// `None` when it overflows
+    fn generators(&self) -> (Vec<Vec<usize>>, Option<usize>) {
+        let mut generators = vec![];
+        let mut order = Some(1usize);
+        for i in (0..self.colours.len()).rev() {
+            // the generators found so far fix every place before `i`
+            let mut reached = orbit(i, &generators);
+            for q in i + 1..self.colours.len() {
+                if reached.contains(&q) {
+                    continue;
+                }
+                if let Some(permutation) = self.mapping(i, q) {
+                    generators.push(permutation);
+                    reached = orbit(i, &generators);
+                }
+            }
+            order = order.and_then(|order| order.checked_mul(reached.len()));
+        }
+        (generators, order)
+    }
+}
+
+impl Symmetries {
+    /// automorphisms of `input`: permutations of the places and transitions
+    /// keeping the arcs, the initial marking, the capacities and the
+    /// attributes of the transitions
+    pub fn find(input: &Input) -> Self {
+        let (generators, group_size) = Search::new(input, &[&input.m_init]).generators();
+        let mut elements = vec![];
+        if group_size.is_some_and(|size| size <= GROUP_LIMIT) {
+            let mut seen = HashSet::from([(0..input.m_names.len()).collect::<Vec<_>>()]);
+            elements = seen.iter().cloned().collect();
+            let mut current = 0;
+            while current < elements.len() {
+                for generator in &generators {
+                    let next = elements[current]
+                        .iter()
+                        .map(|p| generator[*p])
+                        .collect::<Vec<_>>();
+                    if seen.insert(next.clone()) {
+                        elements.push(next);
+                    }
+                }
+                current += 1;
+            }
+        }
+        Symmetries {
+            generators,
+            group_size,
+            elements,
+        }
+    }
+
+    /// whether `canonical` gives a single marking of each orbit, which only
+    /// holds for the groups small enough to be listed
+    pub fn is_canonical(&self) -> bool {
+        !self.elements.is_empty()
+    }
+
+    /// smallest image of `marking` by the automorphisms, or a marking no
+    /// generator lowers for groups too big to be listed
+    pub fn canonical(&self, marking: &[Option<i32>]) -> Marking {
+        if !self.elements.is_empty() {
+            return self
+                .elements
+                .iter()
+                .map(|permutation| image(permutation, marking))
+                .min()
+                .unwrap();
+        }
+        let mut current = marking.to_vec();
+        loop {
+            let lower = self
+                .generators
+                .iter()
+                .map(|generator| image(generator, &current))
+                .filter(|next| *next < current)
+                .min();
+            match lower {
+                Some(lower) => current = lower,
+                None => return current,
+            }
+        }
+    }
+
+    /// number of images of `marking`, the order of the group over the order
+    /// of its automorphisms keeping `marking`, `None` when the order of the
+    /// group overflows
+    pub fn orbit_size(&self, input: &Input, marking: &[Option<i32>]) -> Option<usize> {
+        if !self.elements.is_empty() {
+            return Some(
+                self.elements
+                    .iter()
+                    .map(|permutation| image(permutation, marking))
+                    .collect::<HashSet<_>>()
+                    .len(),
+            );
+        }
+        // the automorphisms keeping both markings are a subgroup, their
+        // order fits when the order of the group does
+        let stabiliser = Search::new(input, &[&input.m_init, marking])
+            .generators()
+            .1?;
+        Some(self.group_size? / stabiliser)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symmetries;
+    use crate::graph_gen::{
+        explore, explore_reduced, independent_workers, ExplorationOptions, Input,
+    };
+
+    /// `n` philosophers around a table, each taking the left fork then the
+    /// right one
+    fn philosophers(n: usize) -> Input {
+        let places = ["think", "fork", "left", "eat"];
+        let index = |i: usize, kind: usize| 4 * (i % n) + kind;
+        let mut transitions = vec![];
+        let mut t_names = vec![];
+        for i in 0..n {
+            let arcs: [(&str, Vec<usize>, Vec<usize>); 3] = [
+                (
+                    "take_left",
+                    vec![index(i, 0), index(i, 1)],
+                    vec![index(i, 2)],
+                ),
+                (
+                    "take_right",
+                    vec![index(i, 2), index(i + 1, 1)],
+                    vec![index(i, 3)],
+                ),
+                (
+                    "release",
+                    vec![index(i, 3)],
+                    vec![index(i, 0), index(i, 1), index(i + 1, 1)],
+                ),
+            ];
+            for (name, pre, post) in arcs {
+                transitions.push(
+                    (0..4 * n)
+                        .map(|p| (i32::from(pre.contains(&p)), i32::from(post.contains(&p))))
+                        .collect(),
+                );
+                t_names.push(format!("{}{}", name, i));
+            }
+        }
+        Input {
+            m_names: (0..4 * n)
+                .map(|p| format!("{}{}", places[p % 4], p / 4))
+                .collect(),
+            m_init: (0..4 * n).map(|p| Some(i32::from(p % 4 < 2))).collect(),
+            transitions,
+            t_names,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rotations_of_the_table() {
+        let input = philosophers(4);
+        let symmetries = Symmetries::find(&input);
+        assert_eq!(symmetries.group_size, Some(4));
+        let full = explore(&input, &Default::default());
+        let options = ExplorationOptions {
+            symmetry: true,
+            ..Default::default()
+        };
+        let (reduced, stats) = explore_reduced(&input, &options);
+        assert!(reduced.markings.len() * 3 < full.markings.len());
+        assert_eq!(stats.group_size, Some(4));
+        assert_eq!(stats.represented_states, Some(full.markings.len()));
+    }
+
+    #[test]
+    fn test_interchangeable_workers() {
+        let input = independent_workers(3);
+        let symmetries = Symmetries::find(&input);
+        assert_eq!(symmetries.group_size, Some(6));
+        let options = ExplorationOptions {
+            symmetry: true,
+            ..Default::default()
+        };
+        let (reduced, stats) = explore_reduced(&input, &options);
+        assert_eq!(reduced.markings.len(), 4);
+        assert_eq!(stats.represented_states, Some(8));
+    }
+
+    #[test]
+    fn test_group_order_overflow() {
+        // n interchangeable empty places, the group has n! automorphisms
+        let places = |n: usize| Input {
+            m_names: (0..n).map(|p| format!("p{}", p)).collect(),
+            m_init: vec![Some(0); n],
+            ..Default::default()
+        };
+        let symmetries = Symmetries::find(&places(20));
+        assert_eq!(symmetries.group_size, Some(2_432_902_008_176_640_000));
+        assert_eq!(symmetries.orbit_size(&places(20), &[Some(0); 20]), Some(1));
+        let symmetries = Symmetries::find(&places(21));
+        assert_eq!(symmetries.group_size, None);
+        assert_eq!(symmetries.orbit_size(&places(21), &[Some(0); 21]), None);
+        let options = ExplorationOptions {
+            symmetry: true,
+            ..Default::default()
+        };
+        let (_, stats) = explore_reduced(&places(21), &options);
+        assert_eq!(stats.represented_states, None);
+    }
+
+    #[test]
+    fn test_orbits_in_a_big_group() {
+        // the tokens of p0 move to p1 and back, the 8 other places are
+        // empty and interchangeable
+        let mut transitions = vec![vec![(0, 0); 10]; 2];
+        transitions[0][..2].copy_from_slice(&[(1, 0), (0, 1)]);
+        transitions[1][..2].copy_from_slice(&[(0, 1), (1, 0)]);
+        let mut m_init = vec![Some(0); 10];
+        m_init[0] = Some(2);
+        let input = Input {
+            m_names: (0..10).map(|p| format!("p{}", p)).collect(),
+            m_init,
+            transitions,
+            ..Default::default()
+        };
+        let symmetries = Symmetries::find(&input);
+        assert_eq!(symmetries.group_size, Some(40320));
+        let mut marking = vec![Some(0); 10];
+        marking[..2].copy_from_slice(&[Some(1), Some(1)]);
+        assert_eq!(symmetries.orbit_size(&input, &marking), Some(1));
+        marking[..3].copy_from_slice(&[Some(2), Some(0), Some(1)]);
+        assert_eq!(symmetries.orbit_size(&input, &marking), Some(8));
+        let options = ExplorationOptions {
+            symmetry: true,
+            ..Default::default()
+        };
+        let (reduced, stats) = explore_reduced(&input, &options);
+        assert_eq!(reduced.markings.len(), 3);
+        assert_eq!(stats.represented_states, None);
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashSet},
+};
+
+use crate::{
+    error_type::ErrorTypes,
+    graph_gen::{Input, Marking},
+};
+
+/// maximum number of events of a prefix before giving up
+pub const EVENT_LIMIT: usize = 100_000;
+
+/// occurrence of a token in a place
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub place: usize,
+    /// event putting the token, none for the initial marking
+    pub producer: Option<usize>,
+}
+
+/// occurrence of a transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub transition: usize,
+    pub preset: Vec<usize>,
+    pub postset: Vec<usize>,
+    /// the marking reached by its local configuration was already reached by
+    /// a smaller one, the prefix is not extended after it
+    pub cutoff: bool,
+}
+
+/// finite complete prefix of the unfolding of a safe net, the events are
+/// numbered in the adequate order they were added in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prefix {
+    pub conditions: Vec<Condition>,
+    pub events: Vec<Event>,
+}
+
+/// reachable marking enabling no transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlock {
+    /// firing sequence reaching it
+    pub transitions: Vec<usize>,
+    pub marking: Marking,
+}
+
+/// Esparza–Römer–Vogler order on local configurations: size, then Parikh
+/// vector, then Parikh vectors of the Foata normal form level by level
+type Key = (usize, Vec<u32>, Vec<Vec<u32>>);
+
+/// possible extension of the prefix
+type Extension = Reverse<(Key, usize, Vec<usize>)>;
+
+/// preset and postset places of a transition
+type Arcs = (Vec<usize>, Vec<usize>);
+
+fn not_safe(reason: String) -> ErrorTypes {
+    ErrorTypes::NotSafe { reason }
+}
+
+/// preset and postset places of every transition, the net must be an
+/// ordinary safe net without read arcs, resets or priorities; a capacity of
+/// 2 or more never blocks a firing of a safe net, a smaller one is rejected
+fn places(input: &Input) -> Result<Vec<Arcs>, ErrorTypes> {
+    if input.m_init.iter().any(|x| !matches!(x, Some(0 | 1))) {
+        return Err(not_safe("the initial marking is not safe".to_string()));
+    }
+    if let Some(p) = (0..input.m_names.len()).find(|p| input.capacity(*p).is_some_and(|k| k < 2)) {
+        return Err(not_safe(format!(
+            "the capacity of {} restricts the firings",
+            input.m_names[p]
+        )));
+    }
+    let priorities = (0..input.transitions.len())
+        .map(|t| input.priority(t))
+        .collect::<HashSet<_>>();
+    if priorities.len() > 1 {
+        return Err(not_safe("priorities are not supported".to_string()));
+    }
+    (0..input.transitions.len())
+        .map(|t| {
+            let name = input.transition_name(t);
+            let arcs = &input.transitions[t];
+            if (0..arcs.len()).any(|p| input.read_arc(t, p) != 0 || input.reset_arc(t, p)) {
+                return Err(not_safe(format!("{} has read or reset arcs", name)));
+            }
+            if arcs.iter().any(|(pre, post)| *pre > 1 || *post > 1) {
+                return Err(not_safe(format!("{} has weighted arcs", name)));
+            }
+            let preset = (0..arcs.len()).filter(|p| arcs[*p].0 == 1).collect();
+            let postset = (0..arcs.len()).filter(|p| arcs[*p].1 == 1).collect();
+            if arcs.iter().all(|(pre, _)| *pre == 0) {
+                return Err(not_safe(format!("{} has no input place", name)));
+            }
+            Ok((preset, postset))
+        })
+        .collect()
+}
+
+/// builds the prefix from the initial conditions
+struct Unfolder<'a> {
+    input: &'a Input,
+    arcs: Vec<Arcs>,
+    prefix: Prefix,
+    /// conditions concurrent to each condition
+    co: Vec<BTreeSet<usize>>,
+    /// events of the local configuration of each event
+    local: Vec<BTreeSet<usize>>,
+    /// length of the longest causal chain ending with each event
+    depth: Vec<usize>,
+    /// markings of the local configurations added so far
+    markings: HashSet<Vec<usize>>,
+    queue: BinaryHeap<Extension>,
+    /// extensions queued or added, as transitions and presets
+    seen: HashSet<(usize, Vec<usize>)>,
+}
+
+impl<'a> Unfolder<'a> {
+    /// local configuration of an event of `transition` consuming `preset`,
+    /// the event itself left out
+    fn history(&self, preset: &[usize]) -> BTreeSet<usize> {
+        preset
+            .iter()
+            .filter_map(|b| self.prefix.conditions[*b].producer)
+            .flat_map(|e| self.local[e].iter().copied())
+            .collect()
+    }
+
+    fn key(&self, transition: usize, preset: &[usize]) -> Key {
+        let history = self.history(preset);
+        let count = self.input.transitions.len();
+        let depth = preset
+            .iter()
+            .filter_map(|b| self.prefix.conditions[*b].producer)
+            .map(|e| self.depth[e])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let mut parikh = vec![0; count];
+        let mut foata = vec![vec![0; count]; depth];
+        for (e, t) in history
+            .iter()
+            .map(|e| (*e, self.prefix.events[*e].transition))
+            .chain([(usize::MAX, transition)])
+        {
+            let level = match e {
+                usize::MAX => depth,
+                e => self.depth[e],
+            };
+            parikh[t] += 1;
+            foata[level - 1][t] += 1;
+        }
+        (history.len() + 1, parikh, foata)
+    }
+
+    /// places marked after the local configuration of an event of
+    /// `transition` consuming `preset`, sorted
+    fn marking(&self, transition: usize, preset: &[usize]) -> Vec<usize> {
+        let history = self.history(preset);
+        let mut cut = self
+            .prefix
+            .conditions
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.producer.is_none_or(|e| history.contains(&e)))
+            .map(|(b, _)| b)
+            .collect::<BTreeSet<_>>();
+        for e in history.iter().map(|e| &self.prefix.events[*e]) {
+            e.preset.iter().for_each(|b| {
+                cut.remove(b);
+            });
+        }
+        preset.iter().for_each(|b| {
+            cut.remove(b);
+        });
+        let mut places = cut
+            .iter()
+            .map(|b| self.prefix.conditions[*b].place)
+            .chain(self.arcs[transition].1.iter().copied())
+            .collect::<Vec<_>>();
+        places.sort_unstable();
+        places
+    }
+
+    /// queues the extensions consuming at least one of the `fresh` conditions
+    fn extend(&mut self, fresh: &[usize]) {
+        for c in fresh {
+            let place = self.prefix.conditions[*c].place;
+            for t in 0..self.arcs.len() {
+                if !self.arcs[t].0.contains(&place) {
+                    continue;
+                }
+                let mut presets = vec![];
+                self.choose(t, &mut vec![*c], &mut presets);
+                for mut preset in presets {
+                    preset.sort_unstable();
+                    if self.seen.insert((t, preset.clone())) {
+                        let key = self.key(t, &preset);
+                        self.queue.push(Reverse((key, t, preset)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// completes `chosen` with pairwise concurrent conditions for the other
+    /// input places of `t`
+    fn choose(&self, t: usize, chosen: &mut Vec<usize>, presets: &mut Vec<Vec<usize>>) {
+        let marked = chosen
+            .iter()
+            .map(|b| self.prefix.conditions[*b].place)
+            .collect::<Vec<_>>();
+        let Some(place) = self.arcs[t].0.iter().find(|p| !marked.contains(p)) else {
+            presets.push(chosen.clone());
+            return;
+        };
+        let candidates = self.co[chosen[0]]
+            .iter()
+            .filter(|b| self.prefix.conditions[**b].place == *place)
+            .filter(|b| chosen.iter().all(|c| self.co[*c].contains(b)))
+            .copied()
+            .collect::<Vec<_>>();
+        for b in candidates {
+            chosen.push(b);
+            self.choose(t, chosen, presets);
+            chosen.pop();
+        }
+    }
+
+    fn add_conditions(&mut self, places: &[usize], producer: Option<usize>) -> Vec<usize> {
+        let first = self.prefix.conditions.len();
+        for place in places {
+            self.prefix.conditions.push(Condition {
+                place: *place,
+                producer,
+            });
+            self.co.push(BTreeSet::new());
+        }
+        (first..self.prefix.conditions.len()).collect()
+    }
+}
+
+/// complete finite prefix of the unfolding of the safe net `input`
+/// (Esparza, Römer and Vogler), the extensions are added in the adequate
+/// order and an event whose local configuration reaches a marking already
+/// reached by a smaller one is a cut-off
+pub fn unfold(input: &Input) -> Result<Prefix, ErrorTypes> {
+    let mut unfolder = Unfolder {
+        input,
+        arcs: places(input)?,
+        prefix: Prefix::default(),
+        co: vec![],
+        local: vec![],
+        depth: vec![],
+        markings: HashSet::new(),
+        queue: BinaryHeap::new(),
+        seen: HashSet::new(),
+    };
+    let initial = (0..input.m_init.len())
+        .filter(|p| input.m_init[*p] == Some(1))
+        .collect::<Vec<_>>();
+    let fresh = unfolder.add_conditions(&initial, None);
+    for b in &fresh {
+        unfolder.co[*b] = fresh.iter().filter(|c| *c != b).copied().collect();
+    }
+    unfolder.markings.insert(initial);
+    unfolder.extend(&fresh);
+    while let Some(Reverse((_, t, preset))) = unfolder.queue.pop() {
+        if unfolder.prefix.events.len() >= EVENT_LIMIT {
+            return Err(ErrorTypes::StateLimitExceeded { limit: EVENT_LIMIT });
+        }
+        let marking = unfolder.marking(t, &preset);
+        let cutoff = !unfolder.markings.insert(marking);
+        let e = unfolder.prefix.events.len();
+        let mut local = unfolder.history(&preset);
+        local.insert(e);
+        let depth = preset
+            .iter()
+            .filter_map(|b| unfolder.prefix.conditions[*b].producer)
+            .map(|e| unfolder.depth[e])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        unfolder.local.push(local);
+        unfolder.depth.push(depth);
+        let postset = unfolder.add_conditions(&unfolder.arcs[t].1.clone(), Some(e));
+        unfolder.prefix.events.push(Event {
+            transition: t,
+            preset: preset.clone(),
+            postset: postset.clone(),
+            cutoff,
+        });
+        if cutoff {
+            continue;
+        }
+        // conditions concurrent to the whole preset stay concurrent to the
+        // new ones
+        let mut shared = unfolder.co[preset[0]].clone();
+        for b in &preset[1..] {
+            shared.retain(|c| unfolder.co[*b].contains(c));
+        }
+        for c in &postset {
+            let place = unfolder.prefix.conditions[*c].place;
+            if shared
+                .iter()
+                .any(|b| unfolder.prefix.conditions[*b].place == place)
+            {
+                return Err(not_safe(format!(
+                    "firing {} puts a second token in {}",
+                    input.transition_name(t),
+                    input.m_names[place]
+                )));
+            }
+            let mut co = shared.clone();
+            co.extend(postset.iter().filter(|d| *d != c));
+            unfolder.co[*c] = co;
+        }
+        for b in shared {
+            unfolder.co[b].extend(postset.iter().copied());
+        }
+        unfolder.extend(&postset);
+    }
+    Ok(unfolder.prefix)
+}
+
+impl Prefix {
+    pub fn cutoffs_count(&self) -> usize {
+        self.events.iter().filter(|e| e.cutoff).count()
+    }
+
+    /// configuration of the prefix without cut-off events enabling no event
+    /// of the prefix, which exists exactly when the net can deadlock as the
+    /// prefix is complete; the events are decided in their order, an
+    /// enabled event left out must have one of its conditions consumed
+    /// by a later event
+    pub fn find_deadlock(&self, input: &Input) -> Option<Deadlock> {
+        let mut marked = self
+            .conditions
+            .iter()
+            .map(|c| c.producer.is_none())
+            .collect::<Vec<_>>();
+        // the last event able to consume each condition
+        let mut last_consumer = vec![None; self.conditions.len()];
+        for (e, event) in self.events.iter().enumerate() {
+            event
+                .preset
+                .iter()
+                .for_each(|b| last_consumer[*b] = Some(e));
+        }
+        let mut chosen = vec![];
+        if !self.search(0, &mut marked, &mut chosen, &mut vec![], &last_consumer) {
+            return None;
+        }
+        let mut marking = vec![Some(0); input.m_init.len()];
+        for (b, condition) in self.conditions.iter().enumerate() {
+            if marked[b] {
+                marking[condition.place] = Some(1);
+            }
+        }
+        Some(Deadlock {
+            transitions: chosen.iter().map(|e| self.events[*e].transition).collect(),
+            marking,
+        })
+    }
+
+    fn enabled(&self, e: usize, marked: &[bool]) -> bool {
+        self.events[e].preset.iter().all(|b| marked[*b])
+    }
+
+    fn search(
+        &self,
+        e: usize,
+        marked: &mut Vec<bool>,
+        chosen: &mut Vec<usize>,
+        left_out: &mut Vec<usize>,
+        last_consumer: &[Option<usize>],
+    ) -> bool {
+        // an enabled event none of whose conditions can still be consumed
+        let stuck = left_out.iter().any(|f| {
+            self.enabled(*f, marked)
+                && self.events[*f]
+                    .preset
+                    .iter()
+                    .all(|b| last_consumer[*b].is_none_or(|g| g < e))
+        });
+        if stuck {
+            return false;
+        }
+        if e == self.events.len() {
+            return true;
+        }
+        if !self.enabled(e, marked) {
+            return self.search(e + 1, marked, chosen, left_out, last_consumer);
+        }
+        let event = &self.events[e];
+        if !event.cutoff {
+            event.preset.iter().for_each(|b| marked[*b] = false);
+            event.postset.iter().for_each(|b| marked[*b] = true);
+            chosen.push(e);
+            if self.search(e + 1, marked, chosen, left_out, last_consumer) {
+                return true;
+            }
+            chosen.pop();
+            event.postset.iter().for_each(|b| marked[*b] = false);
+            event.preset.iter().for_each(|b| marked[*b] = true);
+        }
+        left_out.push(e);
+        let found = self.search(e + 1, marked, chosen, left_out, last_consumer);
+        left_out.pop();
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::unfold;
+    use crate::{error_type::ErrorTypes, graph_gen::Input};
+
+    fn net(places: &[&str], m_init: &[i32], transitions: &[(&str, &[usize], &[usize])]) -> Input {
+        Input {
+            m_names: places.iter().map(|p| p.to_string()).collect(),
+            m_init: m_init.iter().map(|x| Some(*x)).collect(),
+            transitions: transitions
+                .iter()
+                .map(|(_, pre, post)| {
+                    (0..places.len())
+                        .map(|p| (i32::from(pre.contains(&p)), i32::from(post.contains(&p))))
+                        .collect()
+                })
+                .collect(),
+            t_names: transitions.iter().map(|(t, _, _)| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_concurrent_events_stay_linear() {
+        // three independent transitions, 8 reachable markings
+        let input = net(
+            &["p0", "q0", "p1", "q1", "p2", "q2"],
+            &[1, 0, 1, 0, 1, 0],
+            &[("a", &[0], &[1]), ("b", &[2], &[3]), ("c", &[4], &[5])],
+        );
+        let prefix = unfold(&input).unwrap();
+        assert_eq!(prefix.events.len(), 3);
+        assert_eq!(prefix.conditions.len(), 6);
+        let deadlock = prefix.find_deadlock(&input).unwrap();
+        assert_eq!(deadlock.transitions.len(), 3);
+        assert_eq!(
+            deadlock.marking,
+            input
+                .m_init
+                .iter()
+                .map(|x| x.map(|x| 1 - x))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_cutoffs_and_deadlocks() {
+        // p -a-> q is a dead end, p -b-> r -c-> p goes round
+        let input = net(
+            &["p", "q", "r"],
+            &[1, 0, 0],
+            &[("a", &[0], &[1]), ("b", &[0], &[2]), ("c", &[2], &[0])],
+        );
+        let prefix = unfold(&input).unwrap();
+        assert_eq!(prefix.events.len(), 3);
+        assert_eq!(prefix.cutoffs_count(), 1);
+        let deadlock = prefix.find_deadlock(&input).unwrap();
+        assert_eq!(deadlock.transitions, vec![0]);
+        assert_eq!(deadlock.marking, vec![Some(0), Some(1), Some(0)]);
+        // without a the net cycles forever
+        let cycle = net(
+            &["p", "r"],
+            &[1, 0],
+            &[("b", &[0], &[1]), ("c", &[1], &[0])],
+        );
+        assert_eq!(unfold(&cycle).unwrap().find_deadlock(&cycle), None);
+        // two tokens end up in r
+        let unsafe_net = net(
+            &["p", "q", "r"],
+            &[1, 1, 0],
+            &[("a", &[0], &[2]), ("b", &[1], &[2])],
+        );
+        assert!(unfold(&unsafe_net).is_err());
+        // a capacity of 1 on r would block b instead, which is not encoded
+        let capped = Input {
+            capacities: vec![None, None, Some(1)],
+            ..unsafe_net.clone()
+        };
+        assert!(matches!(
+            unfold(&capped),
+            Err(ErrorTypes::NotSafe { reason }) if reason == "the capacity of r restricts the firings"
+        ));
+        let loose = Input {
+            capacities: vec![None, None, Some(2)],
+            ..unsafe_net
+        };
+        assert!(unfold(&loose).is_err());
+        assert!(unfold(&Input {
+            capacities: vec![Some(2), Some(2)],
+            ..cycle.clone()
+        })
+        .is_ok());
+    }
+}
@@ -1,8 +1,8 @@
-use std::fs;
+mod simulator;
 
-use backend::{graph_gen::compile_to_output, output_generators, petri_parser::parser::PetriNet};
-use eframe::glow::Texture;
-use egui::{Color32, ColorImage, TextEdit, TextureHandle, TextureId, Vec2};
+use backend::{graph_gen::compile_to_output, petri_parser::parser::PetriNet};
+use egui::{Color32, ColorImage, TextureHandle};
+use simulator::Simulator;
 
 fn main() -> Result<(), eframe::Error> {
     let options = eframe::NativeOptions {
@@ -16,15 +16,28 @@ fn main() -> Result<(), eframe::Error> {
     )
 }
 
+/// what the right column shows
+#[derive(PartialEq)]
+enum View {
+    Automaton,
+    Simulation,
+}
+
 struct MyApp {
     code: String,
     image: Option<TextureHandle>,
+    simulator: Option<Simulator>,
+    view: View,
+    error: Option<String>,
 }
 
 impl MyApp {
-    fn build(&mut self, ui: &egui::Ui) {
-        let output =
-            compile_to_output(PetriNet::new(&self.code).unwrap().generate_input()).unwrap();
+    fn build(&mut self, ui: &egui::Ui) -> Result<(), anyhow::Error> {
+        let input = PetriNet::new(&self.code)?.generate_input();
+        input.validate()?;
+        self.simulator = Some(Simulator::new(input.clone()));
+        let output = compile_to_output(input, &Default::default())?;
+        let img = image::load_from_memory(&output.png)?;
         let buffer = img.to_rgba8().into_vec();
         let size = [img.width() as usize, img.height() as usize];
         let pixels = buffer
@@ -36,6 +49,7 @@ impl MyApp {
             ui.ctx()
                 .load_texture("build result", image, Default::default()),
         );
+        Ok(())
     }
 }
 
@@ -44,6 +58,9 @@ impl Default for MyApp {
         Self {
             code: Default::default(),
             image: Default::default(),
+            simulator: Default::default(),
+            view: View::Automaton,
+            error: Default::default(),
         }
     }
 }
@@ -55,17 +72,27 @@ impl eframe::App for MyApp {
                 columns[0].vertical(|ui| {
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
                         if ui.button("build 🔨").clicked() {
-                            self.build(ui);
+                            self.error = self.build(ui).err().map(|e| e.to_string());
                         }
                     });
                     ui.text_edit_multiline(&mut self.code);
-                });
-                columns[1].vertical(|ui| match &self.image {
-                    Some(image) => {
-                        ui.image(image, image.size_vec2());
+                    if let Some(error) = &self.error {
+                        ui.colored_label(Color32::RED, error);
                     }
-                    None => {
-                        ui.label("nothing to build");
+                });
+                columns[1].vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut self.view, View::Automaton, "automaton");
+                        ui.selectable_value(&mut self.view, View::Simulation, "simulation");
+                    });
+                    match (&self.view, &self.image, &mut self.simulator) {
+                        (View::Automaton, Some(image), _) => {
+                            ui.image(image, image.size_vec2());
+                        }
+                        (View::Simulation, _, Some(simulator)) => simulator.show(ui),
+                        _ => {
+                            ui.label("nothing to build");
+                        }
                     }
                 });
             });
@@ -0,0 +1,260 @@
+use std::fs;
+
+use backend::graph_gen::{activate_transition, enabled_transitions, CapacityRule, Input, Marking};
+use egui::{
+    emath::Rot2, Align2, Button, Color32, FontId, Key, Pos2, Rect, ScrollArea, Sense, Shape,
+    Stroke, Ui, Vec2,
+};
+
+/// distance between two nodes of the same row
+const SPACING: f32 = 90.0;
+const PLACE_RADIUS: f32 = 22.0;
+const TRANSITION_SIZE: Vec2 = Vec2::new(36.0, 28.0);
+
+/// token game on a net: fires the transitions clicked by the user with the
+/// firing rule of the backend and keeps the fired sequence
+pub struct Simulator {
+    input: Input,
+    rule: CapacityRule,
+    /// marking after each firing of the trace, the first one is `m_init`
+    markings: Vec<Marking>,
+    trace: Vec<usize>,
+    /// transitions undone, the last one is fired first by redo
+    undone: Vec<usize>,
+    trace_path: String,
+    message: Option<String>,
+}
+
+impl Simulator {
+    pub fn new(input: Input) -> Self {
+        Simulator {
+            markings: vec![input.m_init.clone()],
+            input,
+            rule: CapacityRule::default(),
+            trace: vec![],
+            undone: vec![],
+            trace_path: "trace.txt".to_string(),
+            message: None,
+        }
+    }
+
+    fn marking(&self) -> &Marking {
+        self.markings.last().unwrap()
+    }
+
+    /// fires `t` unless it is not enabled, by its tokens, the capacities or
+    /// the priorities
+    fn fire(&mut self, t: usize) -> bool {
+        if !enabled_transitions(&self.input, self.marking(), self.rule).contains(&t) {
+            return false;
+        }
+        match activate_transition(&self.input, t, self.marking(), self.rule) {
+            Some(next) => {
+                self.markings.push(next);
+                self.trace.push(t);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(t) = self.trace.pop() {
+            self.markings.pop();
+            self.undone.push(t);
+        }
+    }
+
+    /// fires the last undone transition, which the capacity rule or the
+    /// priorities may now block, it is then kept for a later redo
+    fn redo(&mut self) {
+        if let Some(&t) = self.undone.last() {
+            if self.fire(t) {
+                self.undone.pop();
+            } else {
+                self.message = Some(format!(
+                    "{} cannot be redone, it is not enabled",
+                    self.input.transition_name(t)
+                ));
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.markings.truncate(1);
+        self.trace.clear();
+        self.undone.clear();
+    }
+
+    /// the fired transitions, one name per line
+    fn export(&self) -> std::io::Result<()> {
+        let names = self
+            .trace
+            .iter()
+            .map(|t| self.input.transition_name(*t) + "\n")
+            .collect::<String>();
+        fs::write(&self.trace_path, names)
+    }
+
+    pub fn show(&mut self, ui: &mut Ui) {
+        // the shortcuts are left to the text fields being edited
+        let typing = ui.ctx().wants_keyboard_input();
+        let (undo, redo) = ui.input(|i| {
+            (
+                !typing && i.modifiers.command && i.key_pressed(Key::Z),
+                !typing && i.modifiers.command && i.key_pressed(Key::Y),
+            )
+        });
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!self.trace.is_empty(), Button::new("⟲ undo"))
+                .clicked()
+                || undo
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(!self.undone.is_empty(), Button::new("⟳ redo"))
+                .clicked()
+                || redo
+            {
+                self.redo();
+            }
+            if ui.button("restart").clicked() {
+                self.restart();
+            }
+            ui.selectable_value(&mut self.rule, CapacityRule::Strict, "strict");
+            ui.selectable_value(&mut self.rule, CapacityRule::Weak, "weak");
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.trace_path);
+            if ui.button("export trace").clicked() {
+                self.message = Some(match self.export() {
+                    Ok(()) => format!("trace saved to {}", self.trace_path),
+                    Err(e) => e.to_string(),
+                });
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+        ui.label(format!(
+            "trace: {}",
+            self.trace
+                .iter()
+                .map(|t| self.input.transition_name(*t))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+        let enabled = enabled_transitions(&self.input, self.marking(), self.rule);
+        if enabled.is_empty() {
+            ui.colored_label(Color32::RED, "deadlock");
+        }
+        ScrollArea::both().show(ui, |ui| {
+            if let Some(t) = self.draw(ui, &enabled) {
+                if self.fire(t) {
+                    self.undone.clear();
+                }
+            }
+        });
+    }
+
+    /// draws the places on a row above the transitions, the enabled
+    /// transitions are filled in green, returns the one clicked
+    fn draw(&self, ui: &mut Ui, enabled: &[usize]) -> Option<usize> {
+        let places = self.input.m_names.len();
+        let transitions = self.input.transitions.len();
+        let size = Vec2::new(places.max(transitions) as f32 * SPACING + SPACING, 260.0);
+        let (response, painter) = ui.allocate_painter(size, Sense::click());
+        let origin = response.rect.min;
+        let place_pos = |p: usize| origin + Vec2::new(SPACING * (p as f32 + 1.0), 60.0);
+        let transition_pos = |t: usize| origin + Vec2::new(SPACING * (t as f32 + 1.0), 200.0);
+        let stroke = Stroke::new(1.5, ui.visuals().text_color());
+        let font = FontId::proportional(14.0);
+
+        for t in 0..transitions {
+            for p in 0..places {
+                let (pre, post) = self.input.transitions[t][p];
+                let (from, to) = (place_pos(p), transition_pos(t));
+                if pre > 0 {
+                    arrow(&painter, from, to, stroke, pre);
+                }
+                if post > 0 {
+                    arrow(&painter, to, from, stroke, post);
+                }
+                if self.input.read_arc(t, p) > 0 {
+                    painter.extend(Shape::dashed_line(&[from, to], stroke, 6.0, 4.0));
+                }
+                if self.input.reset_arc(t, p) {
+                    painter.line_segment([to, from], Stroke::new(3.5, stroke.color));
+                }
+            }
+        }
+
+        for (p, tokens) in self.marking().iter().enumerate() {
+            let center = place_pos(p);
+            painter.circle(center, PLACE_RADIUS, ui.visuals().extreme_bg_color, stroke);
+            let tokens = tokens.map_or("ω".to_string(), |x| x.to_string());
+            painter.text(
+                center,
+                Align2::CENTER_CENTER,
+                tokens,
+                font.clone(),
+                stroke.color,
+            );
+            painter.text(
+                center - Vec2::new(0.0, PLACE_RADIUS + 4.0),
+                Align2::CENTER_BOTTOM,
+                &self.input.m_names[p],
+                font.clone(),
+                stroke.color,
+            );
+        }
+
+        let mut clicked = None;
+        let pointer = response.interact_pointer_pos();
+        for t in 0..transitions {
+            let rect = Rect::from_center_size(transition_pos(t), TRANSITION_SIZE);
+            let fill = match enabled.contains(&t) {
+                true => Color32::from_rgb(60, 170, 80),
+                false => ui.visuals().faint_bg_color,
+            };
+            painter.rect(rect, 2.0, fill, stroke);
+            painter.text(
+                rect.center_bottom() + Vec2::new(0.0, 4.0),
+                Align2::CENTER_TOP,
+                self.input.transition_name(t),
+                font.clone(),
+                stroke.color,
+            );
+            if response.clicked()
+                && enabled.contains(&t)
+                && pointer.is_some_and(|pos| rect.contains(pos))
+            {
+                clicked = Some(t);
+            }
+        }
+        clicked
+    }
+}
+
+/// an arc between the borders of its nodes, labelled with its weight when
+/// it is not 1
+fn arrow(painter: &egui::Painter, from: Pos2, to: Pos2, stroke: Stroke, weight: i32) {
+    let direction = (to - from).normalized();
+    let start = from + direction * PLACE_RADIUS;
+    let tip = to - direction * PLACE_RADIUS;
+    painter.line_segment([start, tip], stroke);
+    let rotation = Rot2::from_angle(std::f32::consts::TAU / 12.0);
+    painter.line_segment([tip, tip - 10.0 * (rotation * direction)], stroke);
+    painter.line_segment([tip, tip - 10.0 * (rotation.inverse() * direction)], stroke);
+    if weight != 1 {
+        painter.text(
+            start + (tip - start) * 0.3,
+            Align2::LEFT_CENTER,
+            weight,
+            FontId::proportional(12.0),
+            stroke.color,
+        );
+    }
+}
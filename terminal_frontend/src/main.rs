@@ -1,8 +1,25 @@
-use backend::error_type::ErrorTypes;
+use backend::automaton::Automaton;
+use backend::ctmc::Ctmc;
+use backend::equivalence::{bisimulation_difference, trace_difference, Answer, Side};
 use backend::graph_gen::*;
+use backend::inclusion::{find_violation, ViolationKind};
+use backend::lts::{Equivalence, Label, Lts};
+use backend::output_generators::{
+    generate_aut, generate_automaton_dot, generate_lts_dot, generate_prefix_dot,
+    generate_prism_lab, generate_prism_sta, generate_prism_tra, generate_trace_csv,
+    generate_trace_json, generate_transition_table, string_to_vector, vector_to_string,
+};
+use backend::parallel::sequential_reason;
 use backend::petri_parser::parser::*;
+use backend::query::{find_witness, QueryOptions, Target};
+use backend::regex::{to_regex, Elimination};
+use backend::simulation::{replay, simulate_runs, Choice, SimulationOptions};
+use backend::state_class::reachable_within;
+use backend::state_equation::StateEquation;
+use backend::symbolic::{Ctl, SymbolicNet, SymbolicOptions};
+use backend::unfolding::unfold;
 use clap::*;
-use std::fs;
+use std::{fs, path::Path};
 
 #[derive(Debug, Parser)]
 /// Program that allows to convert a petri network to a Finite state automata
@@ -15,33 +32,782 @@ pub struct Args {
     /// path to the output file
     #[arg(short,long,default_value_t=String::from("./automata"))]
     output: String,
+    /// how place capacities restrict firing: strict or weak
+    #[arg(long, default_value = "strict")]
+    capacity_rule: CapacityRule,
+    /// firing semantics: interleaving, step or maximal-step
+    #[arg(long, default_value = "interleaving")]
+    semantics: FiringSemantics,
+    /// uses the firing intervals of the transitions and outputs the state
+    /// class graph instead of the marking graph
+    #[arg(long)]
+    timed: bool,
+    /// merges the strongly bisimilar states of the automaton and saves the
+    /// markings behind each remaining state in a `.map` file
+    #[arg(long)]
+    minimise: bool,
+    /// saves the firing language of the automaton as a regular expression
+    /// in a `.regex` file
+    #[arg(long)]
+    regex: bool,
+    /// partial order reduction of the interleavings: none, deadlocks
+    /// (stubborn sets) or ltl (also keeps the next-free LTL properties of the
+    /// visible transitions)
+    #[arg(long, default_value = "none")]
+    reduction: Reduction,
+    /// explores one marking out of each class of markings equal up to an
+    /// automorphism of the net (replicated components)
+    #[arg(long)]
+    symmetry: bool,
+    /// marking to reach within the `--within` time units, token counts
+    /// separated by commas (e.g. 1,0,2)
+    #[arg(long, requires = "within")]
+    target: Option<String>,
+    /// checks whether the target marking is reachable within this many time units
+    #[arg(long, requires = "target")]
+    within: Option<i64>,
+    /// derives the markov chain of the stochastic net, saves it in PRISM
+    /// explicit format and prints the steady state probabilities
+    #[arg(long)]
+    ctmc: bool,
+    /// also prints the probabilities at this time
+    #[arg(long, requires = "ctmc")]
+    transient: Option<f64>,
+    /// threads exploring the marking graph, the graph and its numbering
+    /// are the same for any number
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    // READING INPUTS
-    let args = Args::parse();
-    let petri = fs::read_to_string(&args.source)?;
-    let input = if petri.starts_with("{") {
-        serde_json::from_str(&petri)?
+impl Args {
+    /// exploration settings given on the command line, the partial order and
+    /// symmetry reductions are left out unless `reduced` since they change
+    /// the behaviour the other commands read off the marking graph
+    fn exploration_options(&self, reduced: bool) -> ExplorationOptions {
+        ExplorationOptions {
+            capacity_rule: self.capacity_rule,
+            semantics: self.semantics,
+            timed: self.timed,
+            minimise: self.minimise,
+            regex: self.regex,
+            reduction: match reduced {
+                true => self.reduction,
+                false => Reduction::None,
+            },
+            symmetry: reduced && self.symmetry,
+            jobs: self.jobs,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// synchronises two nets on their transitions with the same name, their
+    /// places are prefixed by the file names, the composed net is saved as
+    /// json next to the other outputs
+    Compose(ComposeArgs),
+    /// fires random transitions from the initial marking of the source net
+    /// and saves the runs next to the other outputs
+    Simulate(SimulateArgs),
+    /// fires a sequence of transitions of the source net and prints the
+    /// marking after each of them, or why one of them cannot fire
+    Replay(ReplayArgs),
+    /// searches a shortest firing sequence of the source net reaching a
+    /// marking, without building the whole marking graph
+    Reach(ReachArgs),
+    /// builds the automaton of the source net, hides its internal
+    /// transitions and reduces it modulo an equivalence, the result is saved
+    /// next to the other outputs
+    Reduce(ReduceArgs),
+    /// compares the behaviour of the source net with another net on the
+    /// names of their visible transitions: trace equivalence, strong and
+    /// branching bisimilarity
+    Equiv(EquivArgs),
+    /// checks that every firing sequence of the source net, read through
+    /// the names of its visible transitions, is allowed by a specification
+    /// automaton and prints the shortest one that is not
+    Refines(RefinesArgs),
+    /// builds the automaton of the source net over the names of its visible
+    /// transitions and prints it as a transition table, a regular expression
+    /// or a dot graph
+    Automaton(AutomatonArgs),
+    /// builds the marking graph of the source net, with the partial order
+    /// and symmetry reductions if any, and prints its size and the memory
+    /// taken by its markings
+    Explore(ExploreArgs),
+    /// builds a complete finite prefix of the unfolding of the source net,
+    /// which must be safe, and saves it as a dot graph next to the other
+    /// outputs
+    Unfold(UnfoldArgs),
+    /// explores the markings of the source net symbolically (binary
+    /// decision diagrams), prints their number and a reachable deadlock and
+    /// checks CTL formulas
+    Symbolic(SymbolicArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ComposeArgs {
+    /// path to the first net
+    left: String,
+    /// path to the second net
+    right: String,
+    /// places to merge, prefixed by the file names (e.g. a.p=b.q)
+    #[arg(long = "fuse")]
+    fusions: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+struct SimulateArgs {
+    /// number of runs
+    #[arg(short = 'n', long, default_value_t = 1)]
+    runs: usize,
+    /// maximum number of firings of a run, a run stops earlier on deadlock
+    #[arg(short = 'k', long, default_value_t = 100)]
+    length: usize,
+    /// seed of the random generator, for reproducible runs
+    #[arg(long)]
+    seed: Option<u64>,
+    /// how the fired transition is picked: uniform or weighted (by the
+    /// weights and rates of the transitions)
+    #[arg(long, default_value = "uniform")]
+    choice: Choice,
+    /// format of the runs: json or csv
+    #[arg(long, default_value = "json", value_parser = ["json", "csv"])]
+    format: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct ReplayArgs {
+    /// file listing the names of the fired transitions, separated by
+    /// spaces or new lines like the traces the simulator exports
+    trace: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct ReachArgs {
+    /// marking to reach (e.g. 1,0,2) or linear predicate over the places
+    /// (e.g. "p0 + 2*p1 >= 3 and p2 = 0")
+    target: String,
+    /// only requires at least the tokens of the target marking
+    #[arg(long)]
+    cover: bool,
+    /// guides the search with the state equation (A*)
+    #[arg(long)]
+    guided: bool,
+    /// solves the state equation before searching, an unsolvable
+    /// equation proves the target unreachable
+    #[arg(long)]
+    state_equation: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ReduceArgs {
+    /// transition to hide on top of the ones declared internal, repeated
+    /// for each one (names of coloured transitions hold commas)
+    #[arg(long)]
+    hide: Vec<String>,
+    /// strong, branching or weak bisimulation, or weak-trace equivalence
+    #[arg(long, default_value = "branching")]
+    equivalence: Equivalence,
+    /// format of the reduced automaton: aut or dot
+    #[arg(long, default_value = "aut", value_parser = ["aut", "dot"])]
+    format: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct EquivArgs {
+    /// path to the other net
+    other: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct RefinesArgs {
+    /// path to the specification: a dot graph (.dot, .gv), an automaton
+    /// in aldebaran format (.aut) or lines like `idle -send-> busy`
+    spec: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct AutomatonArgs {
+    /// final marking (e.g. 1,0,2), can be repeated; every marking is
+    /// final when neither this nor --final-if is given
+    #[arg(long = "final")]
+    finals: Vec<String>,
+    /// linear predicate over the places satisfied by the final markings
+    /// (e.g. "p0 + p1 = 0")
+    #[arg(long)]
+    final_if: Option<String>,
+    /// determinises the automaton (subset construction)
+    #[arg(long)]
+    determinise: bool,
+    /// determinises and minimises the automaton (Hopcroft)
+    #[arg(long)]
+    minimise: bool,
+    /// order of the state elimination building the regular expression:
+    /// weight (shorter expressions) or index
+    #[arg(long, default_value = "weight")]
+    elimination: Elimination,
+    /// output: table, regex or dot
+    #[arg(long, default_value = "table", value_parser = ["table", "regex", "dot"])]
+    format: String,
+}
+
+#[derive(Debug, clap::Args)]
+struct ExploreArgs {
+    /// also builds the whole marking graph to count the pruned states
+    #[arg(long)]
+    compare: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct UnfoldArgs {
+    /// searches the prefix for a reachable deadlock
+    #[arg(long)]
+    deadlock: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct SymbolicArgs {
+    /// most tokens a place without capacity can hold
+    #[arg(long, default_value_t = 1)]
+    bound: i32,
+    /// CTL formula to check in the initial marking, can be repeated
+    /// (e.g. "AG EF {p0 = 1}" or "E[{p1 <= 2} U deadlock]")
+    #[arg(long)]
+    ctl: Vec<String>,
+}
+
+fn component_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or(path.to_string(), |stem| stem.to_string_lossy().to_string())
+}
+
+/// reads a net written in the dsl or in json
+fn read_net(path: &str) -> Result<Input, anyhow::Error> {
+    let petri = fs::read_to_string(path)?;
+    if petri.starts_with("{") {
+        Ok(serde_json::from_str(&petri)?)
     } else {
-        PetriNet::new(&petri)?.generate_input()
-    };
+        Ok(PetriNet::new(&petri)?.generate_input())
+    }
+}
+
+/// labels separated by spaces, tau written `τ`
+fn trace_to_string(trace: &[Label]) -> String {
+    trace
+        .iter()
+        .map(|label| label.as_deref().unwrap_or("τ"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// names of the transitions separated by spaces
+fn sequence_to_string(input: &Input, transitions: &[usize]) -> String {
+    transitions
+        .iter()
+        .map(|t| input.transition_name(*t))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// a marking written like the initial marking, token counts separated by
+/// commas
+fn parse_marking(input: &Input, marking: &str) -> Option<Marking> {
+    string_to_vector(marking, ",").filter(|marking| marking.len() == input.m_init.len())
+}
 
-    if input
-        .transitions
+fn compose(left: &str, right: &str, fusions: &[String]) -> Result<Input, anyhow::Error> {
+    let left_name = component_name(left);
+    let right_name = component_name(right);
+    let fusions = fusions
         .iter()
-        .any(|t| t.len() != input.m_init.len())
+        .map(|fusion| fusion.split('=').map(|p| p.to_string()).collect())
+        .collect::<Vec<_>>();
+    let net = PetriNet::compose(
+        (&left_name, PetriNet::new(&fs::read_to_string(left)?)?),
+        (&right_name, PetriNet::new(&fs::read_to_string(right)?)?),
+        &fusions,
+    )?;
+    Ok(net.generate_input())
+}
+
+fn simulate(args: &Args, input: &Input, command: &SimulateArgs) -> Result<(), anyhow::Error> {
+    let options = SimulationOptions {
+        runs: command.runs,
+        length: command.length,
+        seed: command.seed,
+        choice: command.choice,
+        capacity_rule: args.capacity_rule,
+    };
+    let runs = simulate_runs(input, &options);
+    let traces = match command.format.as_str() {
+        "csv" => generate_trace_csv(input, &runs),
+        _ => generate_trace_json(input, &runs)?,
+    };
+    fs::write(format!("{}.{}", args.output, command.format), traces)?;
+    for (i, run) in runs.iter().enumerate() {
+        println!(
+            "run {}: {} firings{}",
+            i,
+            run.transitions.len(),
+            if run.deadlock { ", deadlock" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn replay_trace(args: &Args, input: &Input, command: &ReplayArgs) -> Result<(), anyhow::Error> {
+    let trace = fs::read_to_string(&command.trace)?;
+    // unfolded coloured transitions hold commas, like `send[1,2]`
+    let names = trace.split_whitespace().collect::<Vec<_>>();
+    let replay = replay(input, &names, args.capacity_rule);
+    println!("step\ttransition\t{}", input.m_names.join("-"));
+    for (i, marking) in replay.markings.iter().enumerate() {
+        println!(
+            "{}\t{}\t{}",
+            i,
+            i.checked_sub(1).map_or("", |k| names[k]),
+            vector_to_string(marking, "-")
+        );
+    }
+    if let Some(failure) = replay.failure {
+        anyhow::bail!(failure.describe(input));
+    }
+    Ok(())
+}
+
+fn reach(args: &Args, input: &Input, command: &ReachArgs) -> Result<(), anyhow::Error> {
+    // a marking is only made of numbers, anything else must be a predicate
+    let target = match (parse_marking(input, &command.target), command.cover) {
+        (Some(marking), true) => Target::Cover(marking),
+        (Some(marking), false) => Target::Marking(marking),
+        (None, cover) => {
+            let target = Target::parse_predicate(input, &command.target).map_err(|error| {
+                anyhow::anyhow!(
+                    "Bad target {}: neither a marking of {} places nor a predicate ({})",
+                    command.target,
+                    input.m_init.len(),
+                    error
+                )
+            })?;
+            if cover {
+                anyhow::bail!("--cover only applies to a target marking");
+            }
+            target
+        }
+    };
+    let options = QueryOptions {
+        capacity_rule: args.capacity_rule,
+        guided: command.guided,
+        state_equation: command.state_equation,
+    };
+    let outcome = find_witness(input, &target, &options)?;
+    match &outcome.state_equation {
+        Some(StateEquation::Unreachable) => {
+            println!("definitely unreachable (the state equation has no solution)");
+            return Ok(());
+        }
+        Some(StateEquation::Candidate(parikh)) => println!(
+            "candidate Parikh vector: {}",
+            parikh
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| **n > 0)
+                .map(|(t, n)| format!("{}×{}", input.transition_name(t), n))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        Some(StateEquation::Inconclusive) => {
+            println!("the state equation is inconclusive for this net")
+        }
+        None => (),
+    }
+    match outcome.witness {
+        Some(witness) => println!(
+            "reachable by [{}] reaching {} ({} markings explored)",
+            sequence_to_string(input, &witness.transitions),
+            vector_to_string(&witness.marking, "-"),
+            outcome.explored
+        ),
+        None => println!("not reachable ({} markings explored)", outcome.explored),
+    }
+    Ok(())
+}
+
+fn reduce(args: &Args, mut input: Input, command: &ReduceArgs) -> Result<(), anyhow::Error> {
+    input.hidden = (0..input.transitions.len())
+        .map(|t| input.is_hidden(t))
+        .collect();
+    for name in &command.hide {
+        let t = (0..input.transitions.len())
+            .find(|t| input.transition_name(*t) == *name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown transition {}", name))?;
+        input.hidden[t] = true;
+    }
+    let options = args.exploration_options(false);
+    let lts = Lts::from_graph(&input, &build_graph(&input, &options)?);
+    let reduced = lts.reduce(command.equivalence);
+    let text = match command.format.as_str() {
+        "dot" => generate_lts_dot(&reduced),
+        _ => generate_aut(&reduced),
+    };
+    fs::write(format!("{}.{}", args.output, command.format), text)?;
+    println!(
+        "{} states and {} transitions reduced to {} states and {} transitions",
+        lts.edges.len(),
+        lts.transitions_count(),
+        reduced.edges.len(),
+        reduced.transitions_count()
+    );
+    Ok(())
+}
+
+fn equiv(args: &Args, input: &Input, command: &EquivArgs) -> Result<(), anyhow::Error> {
+    let other_input = read_net(&command.other)?;
+    other_input.validate()?;
+    let options = args.exploration_options(false);
+    let left = Lts::from_graph(input, &build_graph(input, &options)?);
+    let right = Lts::from_graph(&other_input, &build_graph(&other_input, &options)?);
+    let names = [component_name(&args.source), component_name(&command.other)];
+    let checks = [
+        ("trace equivalent", trace_difference(&left, &right)),
+        (
+            "strongly bisimilar",
+            bisimulation_difference(&left, &right, Equivalence::Strong),
+        ),
+        (
+            "branching bisimilar",
+            bisimulation_difference(&left, &right, Equivalence::Branching),
+        ),
+    ];
+    let players = |side: Side| match side {
+        Side::Left => (&names[0], &names[1]),
+        Side::Right => (&names[1], &names[0]),
+    };
+    let (name, difference) = &checks[0];
+    match difference {
+        None => println!("{}: yes", name),
+        Some(difference) => {
+            let (able, unable) = players(difference.side());
+            let trace = difference.trace();
+            let (last, before) = trace.split_last().unwrap();
+            println!(
+                "{}: no, after [{}] {} can fire {} and {} cannot match it",
+                name,
+                trace_to_string(before),
+                able,
+                trace_to_string(std::slice::from_ref(last)),
+                unable
+            )
+        }
+    }
+    for (name, difference) in &checks[1..] {
+        let Some(difference) = difference else {
+            println!("{}: yes", name);
+            continue;
+        };
+        println!("{}: no", name);
+        for round in &difference.rounds {
+            let (attacker, defender) = players(round.side);
+            let label = trace_to_string(std::slice::from_ref(&round.label));
+            match round.answer {
+                Answer::Same => println!(
+                    "  {} fires {}, {} answers {}",
+                    attacker, label, defender, label
+                ),
+                Answer::Stay => println!("  {} fires {}, {} stays put", attacker, label, defender),
+                Answer::Unmatched => println!(
+                    "  {} fires {}, {} cannot match it",
+                    attacker, label, defender
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn refines(args: &Args, input: &Input, command: &RefinesArgs) -> Result<(), anyhow::Error> {
+    let text = fs::read_to_string(&command.spec)?;
+    let automaton = match Path::new(&command.spec)
+        .extension()
+        .and_then(|e| e.to_str())
     {
-        return Err(anyhow::Error::new(ErrorTypes::TransitionSizeNotMatching {
-            expected: input.m_init.len(),
-        }));
+        Some("dot" | "gv") => Automaton::from_dot(&text)?,
+        Some("aut") => Automaton::from_aut(&text)?,
+        _ => Automaton::from_dsl(&text)?,
+    };
+    let Some(violation) = find_violation(input, &automaton, &args.exploration_options(false))?
+    else {
+        println!("every firing sequence is allowed by the specification");
+        return Ok(());
+    };
+    let sequence = violation
+        .steps
+        .iter()
+        .map(|step| input.step_name(step))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match violation.kind {
+        ViolationKind::Unexpected => anyhow::bail!(
+            "[{}] is not allowed by the specification, its last step is unexpected",
+            sequence
+        ),
+        ViolationKind::NotAccepted => anyhow::bail!(
+            "[{}] leaves the specification outside its final states",
+            sequence
+        ),
+    }
+}
+
+fn automaton(args: &Args, input: &Input, command: &AutomatonArgs) -> Result<(), anyhow::Error> {
+    let mut targets = command
+        .finals
+        .iter()
+        .map(|marking| {
+            parse_marking(input, marking)
+                .map(Target::Marking)
+                .ok_or_else(|| anyhow::anyhow!("Bad final marking {}", marking))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(predicate) = &command.final_if {
+        targets.push(Target::parse_predicate(input, predicate).map_err(anyhow::Error::msg)?);
+    }
+    let graph = build_graph(input, &args.exploration_options(false))?;
+    let mut automaton = Automaton::from_graph(input, &graph, |marking| {
+        targets.is_empty() || targets.iter().any(|target| target.holds(marking))
+    });
+    if command.minimise {
+        automaton = automaton.minimise();
+    } else if command.determinise {
+        automaton = automaton.determinise();
+    }
+    match command.format.as_str() {
+        "regex" => println!("{}", to_regex(&automaton, command.elimination)),
+        "dot" => print!("{}", generate_automaton_dot(&automaton)),
+        _ => print!("{}", generate_transition_table(&automaton)),
+    }
+    Ok(())
+}
+
+fn explore_net(args: &Args, input: &Input, command: &ExploreArgs) -> Result<(), anyhow::Error> {
+    let (graph, stats) = explore_reduced(input, &args.exploration_options(true));
+    let deadlocks = graph.edges.iter().filter(|next| next.is_empty()).count();
+    println!(
+        "{} states, {} edges, {} deadlocks",
+        graph.markings.len(),
+        graph.edges.iter().map(Vec::len).sum::<usize>(),
+        deadlocks
+    );
+    if args.reduction != Reduction::None {
+        println!(
+            "{} states not fully expanded, {} firings pruned",
+            stats.reduced_states, stats.pruned_firings
+        );
+    }
+    if args.symmetry {
+        let count =
+            |n: Option<usize>, unknown: &str| n.map_or(unknown.to_string(), |n| n.to_string());
+        println!(
+            "symmetry group of {} automorphisms, the states stand for {} markings",
+            count(stats.group_size, "too many to count"),
+            count(stats.represented_states, "an uncounted number of")
+        );
+    }
+    println!("{}", stats.memory);
+    if command.compare {
+        let full = explore(input, &args.exploration_options(false));
+        // an accelerated graph is not always smaller once reduced
+        println!(
+            "{} states without reduction, {} pruned",
+            full.markings.len(),
+            full.markings.len() as i64 - graph.markings.len() as i64
+        );
+    }
+    Ok(())
+}
+
+fn unfold_net(args: &Args, input: &Input, command: &UnfoldArgs) -> Result<(), anyhow::Error> {
+    let prefix = unfold(input)?;
+    fs::write(
+        format!("{}{}", args.output, "_prefix.dot"),
+        generate_prefix_dot(input, &prefix),
+    )?;
+    println!(
+        "{} conditions, {} events ({} cut-offs)",
+        prefix.conditions.len(),
+        prefix.events.len(),
+        prefix.cutoffs_count()
+    );
+    if command.deadlock {
+        match prefix.find_deadlock(input) {
+            Some(deadlock) => println!(
+                "deadlock after [{}] reaching {}",
+                sequence_to_string(input, &deadlock.transitions),
+                vector_to_string(&deadlock.marking, "-")
+            ),
+            None => println!("no deadlock"),
+        }
     }
+    Ok(())
+}
+
+fn symbolic(args: &Args, input: &Input, command: &SymbolicArgs) -> Result<(), anyhow::Error> {
+    let formulas = command
+        .ctl
+        .iter()
+        .map(|formula| Ctl::parse(input, formula).map_err(anyhow::Error::msg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = SymbolicOptions {
+        capacity_rule: args.capacity_rule,
+        bound: command.bound,
+    };
+    let mut net = SymbolicNet::new(input, &options)?;
+    let reachable = net.reachable(input)?;
+    let count = |net: &SymbolicNet, states| {
+        net.count(states)
+            .ok_or_else(|| anyhow::anyhow!("the markings depend on the next variables"))
+    };
+    println!(
+        "{} reachable markings, {} BDD nodes",
+        count(&net, reachable)?,
+        net.bdd.node_count(reachable)
+    );
+    let deadlocks = net.deadlocks(reachable);
+    match net.marking(deadlocks) {
+        Some(marking) => println!(
+            "{} deadlocks, e.g. {}",
+            count(&net, deadlocks)?,
+            vector_to_string(&marking, "-")
+        ),
+        None => println!("no deadlock"),
+    }
+    for (text, formula) in command.ctl.iter().zip(&formulas) {
+        println!("{}: {}", text, net.holds(reachable, formula));
+    }
+    Ok(())
+}
 
-    let output = compile_to_output(input)?;
+fn reach_within(
+    input: &Input,
+    target: &str,
+    within: i64,
+    rule: CapacityRule,
+) -> Result<(), anyhow::Error> {
+    let target = parse_marking(input, target)
+        .ok_or_else(|| anyhow::anyhow!("Bad target marking {}", target))?;
+    match reachable_within(input, &target, within, rule)? {
+        Some(witness) => println!(
+            "reachable within {} time units by [{}] (between {} and {} time units)",
+            within,
+            sequence_to_string(input, &witness.sequence),
+            witness.earliest,
+            witness.latest.map_or("inf".to_string(), |x| x.to_string())
+        ),
+        None => println!("not reachable within {} time units", within),
+    }
+    Ok(())
+}
+
+fn markov_chain(args: &Args, input: &Input) -> Result<(), anyhow::Error> {
+    let ctmc = Ctmc::build(input, &args.exploration_options(false))?;
+    fs::write(
+        format!("{}{}", args.output, ".tra"),
+        generate_prism_tra(&ctmc),
+    )?;
+    fs::write(
+        format!("{}{}", args.output, ".sta"),
+        generate_prism_sta(&input.m_names, &ctmc),
+    )?;
+    fs::write(
+        format!("{}{}", args.output, ".lab"),
+        generate_prism_lab(&ctmc),
+    )?;
+    let steady = ctmc.steady_state();
+    let transient = args.transient.map(|time| ctmc.transient(time));
+    println!(
+        "{}\tsteady{}",
+        input.m_names.join("-"),
+        match args.transient {
+            Some(time) => format!("\tt={}", time),
+            None => String::new(),
+        }
+    );
+    for (i, marking) in ctmc.states.iter().enumerate() {
+        println!(
+            "{}\t{:.6}{}",
+            vector_to_string(marking, "-"),
+            steady[i],
+            transient
+                .as_ref()
+                .map_or(String::new(), |p| format!("\t{:.6}", p[i]))
+        );
+    }
+    Ok(())
+}
+
+/// the outputs of the source net when no other command is given
+fn compile(args: &Args, input: Input) -> Result<(), anyhow::Error> {
+    if let (Some(target), Some(within)) = (&args.target, args.within) {
+        return reach_within(&input, target, within, args.capacity_rule);
+    }
+    if args.ctmc {
+        return markov_chain(args, &input);
+    }
+    let output = compile_to_output(input, &args.exploration_options(true))?;
+    if let Some(memory) = output.memory {
+        println!("{}", memory);
+    }
 
     output.save_smv(&format!("{}{}", args.output, ".smv"))?;
     output.save_png(&format!("{}{}", args.output, ".png"))?;
+    output.save_net_dot(&format!("{}{}", args.output, "_net.dot"))?;
+    output.save_state_map(&format!("{}{}", args.output, ".map"))?;
+    output.save_regex(&format!("{}{}", args.output, ".regex"))?;
 
     open::that(&format!("{}{}", args.output, ".png"))?;
     Ok(())
 }
+
+fn main() -> Result<(), anyhow::Error> {
+    // READING INPUTS
+    let args = Args::parse();
+    let input = match &args.command {
+        Some(Command::Compose(command)) => {
+            let input = compose(&command.left, &command.right, &command.fusions)?;
+            fs::write(
+                format!("{}{}", args.output, ".json"),
+                serde_json::to_string_pretty(&input)?,
+            )?;
+            input
+        }
+        _ => read_net(&args.source)?,
+    };
+
+    input.validate()?;
+    if args.jobs > 1 {
+        let reduced = matches!(
+            args.command,
+            None | Some(Command::Compose(_)) | Some(Command::Explore(_))
+        );
+        if let Some(reason) = sequential_reason(&input, &args.exploration_options(reduced)) {
+            eprintln!("warning: --jobs {} is ignored, {}", args.jobs, reason);
+        }
+    }
+
+    match &args.command {
+        None | Some(Command::Compose(_)) => compile(&args, input),
+        Some(Command::Simulate(command)) => simulate(&args, &input, command),
+        Some(Command::Replay(command)) => replay_trace(&args, &input, command),
+        Some(Command::Reach(command)) => reach(&args, &input, command),
+        Some(Command::Reduce(command)) => reduce(&args, input, command),
+        Some(Command::Equiv(command)) => equiv(&args, &input, command),
+        Some(Command::Refines(command)) => refines(&args, &input, command),
+        Some(Command::Automaton(command)) => automaton(&args, &input, command),
+        Some(Command::Explore(command)) => explore_net(&args, &input, command),
+        Some(Command::Unfold(command)) => unfold_net(&args, &input, command),
+        Some(Command::Symbolic(command)) => symbolic(&args, &input, command),
+    }
+}